@@ -0,0 +1,279 @@
+#!/usr/bin/env python3
+"""Generates the signed DNS responses that src/validate.rs's tests read.
+
+There are three zones under a test root zone:
+
+- example. is signed with ECDSA P-256, and uses NSEC.
+- hashed. is signed with RSA/SHA-256, and uses NSEC3 (no salt, no extra iterations).
+- insecure. isn't signed, and the root proves it has no DS records.
+
+The records of each zone are below, along with the responses to each query, which are written
+to <name>_<TYPE>.bin. The keys are in keys/, and both RSA and (RFC 6979) ECDSA signatures are
+deterministic, so running this again gives exactly the same files. If a key is missing, a new
+one is made, and every response signed with it will change.
+
+Signatures are valid from 2024-01-01 until 2034-01-01.
+
+Needs the `cryptography` package (version 44 or later):
+
+    python3 fixtures/dnssec/generate.py
+"""
+import base64
+import hashlib
+import os
+import struct
+
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec, padding, rsa, utils
+
+OUT = os.path.dirname(os.path.abspath(__file__))
+KEYS = os.path.join(OUT, "keys")
+INCEPTION = 1704067200  # 2024-01-01
+EXPIRATION = 2019686400  # 2034-01-01
+TYPES = dict(
+    A=1, NS=2, CNAME=5, SOA=6, MX=15, TXT=16, AAAA=28, OPT=41, DS=43, RRSIG=46, NSEC=47,
+    DNSKEY=48, NSEC3=50, NSEC3PARAM=51,
+)
+RSASHA256 = 8
+ECDSAP256SHA256 = 13
+
+
+def wire(name):
+    """A name in canonical wire format, i.e. lowercase and uncompressed."""
+    name = name.lower()
+    if name == ".":
+        return b"\0"
+    out = b""
+    for label in name.rstrip(".").split("."):
+        out += bytes([len(label)]) + label.encode()
+    return out + b"\0"
+
+
+def label_count(name):
+    """The RRSIG labels field: how many labels the name has, not counting the root or a *."""
+    labels = [label for label in name.rstrip(".").split(".") if label]
+    if labels and labels[0] == "*":
+        labels = labels[1:]
+    return len(labels)
+
+
+def bitmap(types):
+    """The type bitmaps field of NSEC and NSEC3 records."""
+    windows = {}
+    for num in sorted(set(TYPES[t] for t in types)):
+        windows.setdefault(num >> 8, []).append(num & 0xFF)
+    out = b""
+    for window, lows in sorted(windows.items()):
+        bits = bytearray(32)
+        for low in lows:
+            bits[low // 8] |= 0x80 >> (low % 8)
+        length = max(lows) // 8 + 1
+        out += bytes([window, length]) + bytes(bits[:length])
+    return out
+
+
+class Key:
+    """A zone's key signing key, which signs everything in the zone to keep things simple."""
+
+    def __init__(self, zone, file_name, algorithm):
+        self.zone, self.algorithm = zone, algorithm
+        path = os.path.join(KEYS, file_name)
+        if os.path.exists(path):
+            with open(path, "rb") as f:
+                self.private = serialization.load_pem_private_key(f.read(), password=None)
+        else:
+            if algorithm == RSASHA256:
+                self.private = rsa.generate_private_key(65537, 2048)
+            else:
+                self.private = ec.generate_private_key(ec.SECP256R1())
+            os.makedirs(KEYS, exist_ok=True)
+            with open(path, "wb") as f:
+                f.write(
+                    self.private.private_bytes(
+                        serialization.Encoding.PEM,
+                        serialization.PrivateFormat.PKCS8,
+                        serialization.NoEncryption(),
+                    )
+                )
+        numbers = self.private.public_key().public_numbers()
+        if algorithm == RSASHA256:
+            # RFC 3110: exponent length, exponent, modulus.
+            exponent = numbers.e.to_bytes((numbers.e.bit_length() + 7) // 8, "big")
+            public_key = bytes([len(exponent)]) + exponent + numbers.n.to_bytes(256, "big")
+        else:
+            # RFC 6605: the point's x and y coordinates.
+            public_key = numbers.x.to_bytes(32, "big") + numbers.y.to_bytes(32, "big")
+        self.dnskey = struct.pack(">HBB", 257, 3, algorithm) + public_key
+
+    def tag(self):
+        """The key tag, from RFC 4034 appendix B."""
+        acc = 0
+        for i, byte in enumerate(self.dnskey):
+            acc += byte << 8 if i % 2 == 0 else byte
+        acc += (acc >> 16) & 0xFFFF
+        return acc & 0xFFFF
+
+    def ds(self):
+        """The DS record's RDATA, with a SHA-256 digest."""
+        digest = hashlib.sha256(wire(self.zone) + self.dnskey).digest()
+        return struct.pack(">HBB", self.tag(), self.algorithm, 2) + digest
+
+    def sign(self, data):
+        if self.algorithm == RSASHA256:
+            return self.private.sign(data, padding.PKCS1v15(), hashes.SHA256())
+        der = self.private.sign(data, ec.ECDSA(hashes.SHA256(), deterministic_signing=True))
+        r, s = utils.decode_dss_signature(der)
+        return r.to_bytes(32, "big") + s.to_bytes(32, "big")
+
+
+def rr(name, rtype, ttl, rdata):
+    return (name, rtype, ttl, rdata)
+
+
+def rr_wire(record):
+    name, rtype, ttl, rdata = record
+    return wire(name) + struct.pack(">HHIH", TYPES[rtype], 1, ttl, len(rdata)) + rdata
+
+
+def rrsig(rrset, key):
+    """Signs the RRset, see RFC 4034 section 3.1.8.1."""
+    name, rtype, ttl, _ = rrset[0]
+    head = struct.pack(
+        ">HBBIIIH", TYPES[rtype], key.algorithm, label_count(name), ttl, EXPIRATION, INCEPTION,
+        key.tag(),
+    ) + wire(key.zone)
+    data = head + b"".join(rr_wire(r) for r in sorted(rrset, key=lambda r: r[3]))
+    return rr(name, "RRSIG", ttl, head + key.sign(data))
+
+
+def signed(rrset, key):
+    return rrset + [rrsig(rrset, key)]
+
+
+def expanded(rrset, key, name):
+    """Signs an RRset owned by a wildcard, then gives it (and its RRSIG) the name it matched."""
+    return [rr(name, rtype, ttl, rdata) for (_, rtype, ttl, rdata) in signed(rrset, key)]
+
+
+def soa(zone):
+    rdata = (
+        wire("ns." + zone.lstrip("."))
+        + wire("admin." + zone.lstrip("."))
+        + struct.pack(">IIIII", 1, 7200, 3600, 1209600, 300)
+    )
+    return rr(zone, "SOA", 3600, rdata)
+
+
+def nsec(name, next_name, types):
+    return rr(name, "NSEC", 300, wire(next_name) + bitmap(types))
+
+
+def nsec3_hash(name):
+    return hashlib.sha1(wire(name)).digest()
+
+
+def b32hex(digest):
+    return base64.b32hexencode(digest).decode().rstrip("=").lower()
+
+
+def nsec3_chain(zone, names_types):
+    """One NSEC3 RRset per name, each pointing at the next hash along."""
+    hashed = sorted((nsec3_hash(name), types) for name, types in names_types)
+    chain = []
+    for i, (digest, types) in enumerate(hashed):
+        next_digest = hashed[(i + 1) % len(hashed)][0]
+        rdata = (
+            struct.pack(">BBH", 1, 0, 0) + b"\0" + bytes([len(next_digest)]) + next_digest
+            + bitmap(types)
+        )
+        chain.append([rr(b32hex(digest) + "." + zone, "NSEC3", 300, rdata)])
+    return chain
+
+
+def response(qname, qtype, rcode=0, answer=(), authority=()):
+    """Writes a response with the AA bit and DNSSEC OK set, like an authoritative server's."""
+    answer, authority = list(answer), list(authority)
+    header = struct.pack(">HHHHHH", 0, 0x8500 | rcode, 1, len(answer), len(authority), 1)
+    question = wire(qname) + struct.pack(">HH", TYPES[qtype], 1)
+    opt = b"\0" + struct.pack(">HHIH", TYPES["OPT"], 1232, 0x8000, 0)
+    body = header + question + b"".join(rr_wire(r) for r in answer + authority) + opt
+    file_name = ("root" if qname == "." else qname.rstrip(".")) + "_" + qtype + ".bin"
+    with open(os.path.join(OUT, file_name), "wb") as f:
+        f.write(body)
+
+
+root = Key(".", "root.pem", RSASHA256)
+example = Key("example.", "example.pem", ECDSAP256SHA256)
+hashed = Key("hashed.", "hashed.pem", RSASHA256)
+
+# The root zone: example. and hashed. are signed, insecure. isn't.
+response(".", "DNSKEY", answer=signed([rr(".", "DNSKEY", 3600, root.dnskey)], root))
+response("example.", "DS", answer=signed([rr("example.", "DS", 3600, example.ds())], root))
+response("hashed.", "DS", answer=signed([rr("hashed.", "DS", 3600, hashed.ds())], root))
+response(
+    "insecure.",
+    "DS",
+    authority=signed([soa(".")], root)
+    + signed([nsec("insecure.", ".", ["NS", "RRSIG", "NSEC"])], root),
+)
+
+# example.:
+#   @        DNSKEY  ...
+#   alias    CNAME   www
+#   www      A       192.0.2.1
+#            A       192.0.2.2
+#   *.wild   A       192.0.2.4
+# Its NSEC chain, in canonical order: @, alias, *.wild, www.
+example_soa = signed([soa("example.")], example)
+apex_nsec = signed(
+    [nsec("example.", "alias.example.", ["NS", "SOA", "RRSIG", "NSEC", "DNSKEY"])], example
+)
+alias_nsec = signed([nsec("alias.example.", "*.wild.example.", ["CNAME", "RRSIG", "NSEC"])], example)
+wild_nsec = signed([nsec("*.wild.example.", "www.example.", ["A", "RRSIG", "NSEC"])], example)
+www_nsec = signed([nsec("www.example.", "example.", ["A", "RRSIG", "NSEC"])], example)
+response("example.", "DNSKEY", answer=signed([rr("example.", "DNSKEY", 3600, example.dnskey)], example))
+www_a = [
+    rr("www.example.", "A", 300, bytes([192, 0, 2, 1])),
+    rr("www.example.", "A", 300, bytes([192, 0, 2, 2])),
+]
+response("www.example.", "A", answer=signed(www_a, example))
+alias = [rr("alias.example.", "CNAME", 300, wire("www.example."))]
+response("alias.example.", "A", answer=signed(alias, example) + signed(www_a, example))
+# nx. is between alias. and *.wild., and there's no *.example. either.
+response("nx.example.", "A", rcode=3, authority=example_soa + alias_nsec + apex_nsec)
+response("www.example.", "DS", authority=example_soa + www_nsec)
+response("www.example.", "MX", authority=example_soa + www_nsec)
+# foo.wild. only exists because of the wildcard, which the NSEC after *.wild. proves.
+wild_a = [rr("*.wild.example.", "A", 300, bytes([192, 0, 2, 4]))]
+response(
+    "foo.wild.example.",
+    "A",
+    answer=expanded(wild_a, example, "foo.wild.example."),
+    authority=wild_nsec,
+)
+
+# hashed.:
+#   @        DNSKEY  ...
+#   www      A       192.0.2.5
+response("hashed.", "DNSKEY", answer=signed([rr("hashed.", "DNSKEY", 3600, hashed.dnskey)], hashed))
+chain = nsec3_chain(
+    "hashed.",
+    [
+        ("hashed.", ["NS", "SOA", "RRSIG", "DNSKEY", "NSEC3PARAM"]),
+        ("www.hashed.", ["A", "RRSIG"]),
+    ],
+)
+authority = signed([soa("hashed.")], hashed)
+for rrset in chain:
+    authority += signed(rrset, hashed)
+response("nx.hashed.", "A", rcode=3, authority=authority)
+www_nsec3 = next(rrset for rrset in chain if rrset[0][0].startswith(b32hex(nsec3_hash("www.hashed."))))
+response("www.hashed.", "TXT", authority=signed([soa("hashed.")], hashed) + signed(www_nsec3, hashed))
+
+# insecure.:
+#   www      A       192.0.2.3
+response("www.insecure.", "A", answer=[rr("www.insecure.", "A", 300, bytes([192, 0, 2, 3]))])
+
+with open(os.path.join(OUT, "root-anchor.zone"), "w") as f:
+    f.write("; The trust anchor for the test root zone these responses were signed with.\n")
+    f.write(". 3600 IN DS %d %d 2 %s\n" % (root.tag(), RSASHA256, root.ds()[4:].hex().upper()))
@@ -1,48 +1,299 @@
 use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    ffi::OsString,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs},
+    path::PathBuf,
     process::exit,
+    str::FromStr,
+    time::Duration,
 };
 
 use ascii::AsciiString;
 
-use crate::dns_types::RecordType;
+use dingo::{
+    dns_types::{reverse_lookup_name, RecordType},
+    io::{
+        https::{self, parse_url, url_host, url_port, HttpsConfig},
+        quic::{QuicConfig, DOQ_PORT},
+        tls::{parse_spki_pin, TlsConfig, DOT_PORT},
+        Retry, Transport,
+    },
+    message::edns::{Edns, DEFAULT_UDP_PAYLOAD_SIZE},
+    validate::TrustAnchor,
+    zone::read_zone_file,
+};
 
 const HELP: &str = "\
 dingo -- domain information gatherer, obviously
 USAGE:
   dingo [OPTIONS] --record-type TYPE NAME
+  dingo [OPTIONS] -x IP
+  dingo serve --zone FILE [--origin NAME] [--listen ADDR]
+  dingo proxy [--listen ADDR] [-r IP]... [--tcp] [--timeout SECS] [--tries N]
 FLAGS:
   -h, --help                Prints help information
+      --tcp                 Send the query over TCP instead of UDP
+      --tls                 Send the query over DNS-over-TLS (the default resolver is then 1.1.1.1:853)
+      --https-get           With --https, put the query in the URL of a GET request, instead of POSTing it
+      --quic                Send the query over DNS-over-QUIC, to the resolver given with -r (on port 853, unless it has a port)
+      --no-edns             Don't send an EDNS OPT record with the query
+      --dnssec              Ask for DNSSEC records (RRSIGs etc.) too, by setting the DNSSEC OK bit
+      --validate            Check the response's DNSSEC signatures, from the root zone's keys down (implies --dnssec)
+      --trace               Resolve the name starting from the root servers, and print every referral
 OPTIONS:
-  -t, --record-type TYPE    Choose the DNS record type (currently only supports A, CNAME, SOA and AAAA)
-  -r, --resolver IP         Which DNS resolver to query (default is 1.1.1.1:53)
+  -t, --record-type TYPE    Choose the DNS record type (A, AAAA, CNAME, SOA, NS, MX, TXT, PTR, SRV, DNSKEY, DS, RRSIG, NSEC, NSEC3, NSEC3PARAM, or TYPEnnn for any other type)
+  -x IP                     Reverse lookup: query the PTR record for this IPv4 or IPv6 address
+  -r, --resolver IP         Which DNS resolver to query, optionally with a port (default is 1.1.1.1:53). Repeat it to query several at once, and compare their responses
+      --bufsize SIZE        UDP payload size to advertise via EDNS (default is 1232)
+      --format FORMAT       How to print the response: text (the default), json or dig
+      --timeout SECS        How long to wait for each response (default is 5)
+      --tries N             How many times to send the query before giving up, with a new ID each time (default is 3)
+      --retry-backoff SECS  How long to wait before the first retry. It doubles after each retry (default is 0.2)
+      --https URL           Send the query over DNS-over-HTTPS to this URL, e.g. https://cloudflare-dns.com/dns-query (the resolver is then the URL's host, unless -r is given)
+      --tls-hostname NAME   With --tls, --https or --quic, the name to send with SNI and check the certificate is for (default is the resolver's IP, or the URL's host)
+      --tls-ca FILE         With --tls, --https or --quic, a PEM file of CA certificates to trust (default is the Mozilla root store)
+      --tls-pin BASE64      With --tls, --https or --quic, the SHA-256 digest of a public key (SPKI) the certificate chain must have. Can be repeated
+      --trust-anchor FILE   A zone file of DS records to validate from, instead of the root zone's keys (implies --validate)
 ARGS:
   NAME A domain name to look up. Remember, these must be ASCII.
+SERVE OPTIONS:
+      --zone FILE           Zone file to answer queries from, authoritatively
+      --origin NAME         Origin for relative names in the zone file (default is the file name, minus any .zone extension)
+      --listen ADDR         Address to listen on, over both UDP and TCP (default is 127.0.0.1:53)
+PROXY OPTIONS:
+      --listen ADDR         Address to listen on, over both UDP and TCP (default is 127.0.0.1:53)
+  -r, --resolver IP         Upstream resolver to forward queries to. Repeat it to add more, they're tried in order (default is 1.1.1.1:53)
+      --tcp                 Forward queries over TCP instead of UDP
+      --timeout SECS        How long to wait for each upstream resolver's response (default is 5)
+      --tries N             How many times to ask each upstream resolver before trying the next one (default is 1)
 ";
 
+// I asked some coworkers and they suggested this DNS resolver
+const DEFAULT_RESOLVER: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 53));
+
+/// The same resolver, but its DNS-over-TLS port.
+const DEFAULT_TLS_RESOLVER: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), DOT_PORT));
+
+/// Where `dingo serve` and `dingo proxy` listen by default.
+const DEFAULT_LISTEN: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 53));
+
+/// What the user asked dingo to do.
+#[derive(Debug)]
+pub enum Command {
+    /// Look up a name.
+    Query(AppArgs),
+    /// Act as an authoritative server for a zone.
+    Serve(ServeArgs),
+    /// Forward queries to other resolvers, caching their responses.
+    Proxy(ProxyArgs),
+}
+
+impl Command {
+    pub fn parse() -> Result<Self, pico_args::Error> {
+        let mut args: Vec<OsString> = std::env::args_os().skip(1).collect();
+        if args.first().is_some_and(|arg| arg == "serve") {
+            args.remove(0);
+            return ServeArgs::parse(pico_args::Arguments::from_vec(args)).map(Self::Serve);
+        }
+        if args.first().is_some_and(|arg| arg == "proxy") {
+            args.remove(0);
+            return ProxyArgs::parse(pico_args::Arguments::from_vec(args)).map(Self::Proxy);
+        }
+        AppArgs::parse(pico_args::Arguments::from_vec(args)).map(Self::Query)
+    }
+}
+
+/// Values derived from the CLI arguments to `dingo serve`.
+#[derive(Debug)]
+pub struct ServeArgs {
+    pub zone: PathBuf,
+    pub origin: String,
+    pub listen: SocketAddr,
+}
+
+impl ServeArgs {
+    fn parse(mut pargs: pico_args::Arguments) -> Result<Self, pico_args::Error> {
+        if pargs.contains(["-h", "--help"]) {
+            print!("{}", HELP);
+            std::process::exit(0);
+        }
+        let zone: PathBuf = pargs.value_from_str("--zone")?;
+        // Zone files are usually named after their zone, e.g. example.com.zone
+        let origin = match pargs.opt_value_from_str("--origin")? {
+            Some(origin) => origin,
+            None => {
+                let file_name = zone.file_name().unwrap_or_default().to_string_lossy();
+                file_name.trim_end_matches(".zone").to_owned()
+            }
+        };
+        let listen = pargs
+            .opt_value_from_str("--listen")?
+            .unwrap_or(DEFAULT_LISTEN);
+
+        let remaining = pargs.finish();
+        if !remaining.is_empty() {
+            eprintln!("Warning: unused arguments left: {:?}.", remaining);
+        }
+        Ok(Self {
+            zone,
+            origin,
+            listen,
+        })
+    }
+}
+
+/// Values derived from the CLI arguments to `dingo proxy`.
+#[derive(Debug)]
+pub struct ProxyArgs {
+    pub listen: SocketAddr,
+    pub upstreams: Vec<SocketAddr>,
+    pub transport: Transport,
+    /// Overrides the proxy's default timeout for each upstream.
+    pub timeout: Option<Duration>,
+    /// Overrides how many times the proxy asks each upstream.
+    pub tries: Option<u32>,
+}
+
+impl ProxyArgs {
+    fn parse(mut pargs: pico_args::Arguments) -> Result<Self, pico_args::Error> {
+        if pargs.contains(["-h", "--help"]) {
+            print!("{}", HELP);
+            std::process::exit(0);
+        }
+        let listen = pargs
+            .opt_value_from_str("--listen")?
+            .unwrap_or(DEFAULT_LISTEN);
+        let mut pargs = merge_resolver_flags(pargs);
+        let mut upstreams: Vec<SocketAddr> = pargs.values_from_str("-r")?;
+        if upstreams.is_empty() {
+            upstreams.push(DEFAULT_RESOLVER);
+        }
+        let transport = if pargs.contains("--tcp") {
+            Transport::Tcp
+        } else {
+            Transport::Udp
+        };
+        let timeout = pargs.opt_value_from_fn("--timeout", parse_timeout)?;
+        let tries = pargs.opt_value_from_fn("--tries", parse_tries)?;
+
+        let remaining = pargs.finish();
+        if !remaining.is_empty() {
+            eprintln!("Warning: unused arguments left: {:?}.", remaining);
+        }
+        Ok(Self {
+            listen,
+            upstreams,
+            transport,
+            timeout,
+            tries,
+        })
+    }
+}
+
+/// pico-args returns every value of one flag before any values of another (and `-r=IP`
+/// values after all `-r IP` ones), so the resolver flags are all turned into `-r IP` first,
+/// to keep the resolvers in the order they were given.
+fn merge_resolver_flags(pargs: pico_args::Arguments) -> pico_args::Arguments {
+    let args = pargs
+        .finish()
+        .into_iter()
+        .flat_map(|arg| {
+            let value = arg.to_str().and_then(|arg| {
+                arg.strip_prefix("--resolver=")
+                    .or_else(|| arg.strip_prefix("-r="))
+            });
+            match value {
+                Some(value) => vec![OsString::from("-r"), OsString::from(value)],
+                None if arg == "--resolver" => vec![OsString::from("-r")],
+                None => vec![arg],
+            }
+        })
+        .collect();
+    pico_args::Arguments::from_vec(args)
+}
+
 /// Values derived from the CLI arguments.
 #[derive(Debug)]
 pub struct AppArgs {
     pub record_type: RecordType,
     pub name: String,
-    pub resolver: SocketAddr,
+    /// The resolvers to query, and how to reach each one. If there's more than one, their
+    /// responses are compared.
+    pub resolvers: Vec<(SocketAddr, Transport)>,
+    pub edns: Option<Edns>,
+    pub retry: Retry,
+    /// Resolve iteratively from the root servers, instead of asking the resolver.
+    pub trace: bool,
+    /// Check the response's DNSSEC signatures, following the chain of trust down from this
+    /// trust anchor.
+    pub validate: Option<TrustAnchor>,
+    pub format: OutputFormat,
 }
 
-impl AppArgs {
-    pub fn parse() -> Result<Self, pico_args::Error> {
-        let mut pargs = pico_args::Arguments::from_env();
+/// How to print the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text.
+    Text,
+    /// The whole message as JSON, for other programs to read.
+    Json,
+    /// The same layout as `dig`.
+    Dig,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "dig" => Ok(Self::Dig),
+            other => Err(format!(
+                "{other} is not an output format, use text, json or dig"
+            )),
+        }
+    }
+}
 
+impl AppArgs {
+    fn parse(mut pargs: pico_args::Arguments) -> Result<Self, pico_args::Error> {
         // Help has a higher priority and should be handled separately.
         if pargs.contains(["-h", "--help"]) {
             print!("{}", HELP);
             std::process::exit(0);
         }
+        let tcp = pargs.contains("--tcp");
+        let tls = pargs.contains("--tls");
+        let https_url = pargs.opt_value_from_fn("--https", parse_url)?;
+        let https_method = if pargs.contains("--https-get") {
+            https::Method::Get
+        } else {
+            https::Method::Post
+        };
+        let quic = pargs.contains("--quic");
+        if [tls, https_url.is_some(), quic]
+            .iter()
+            .filter(|&&t| t)
+            .count()
+            > 1
+        {
+            eprintln!("Choose only one of --tls, --https and --quic");
+            exit(1);
+        }
+        let tls_hostname: Option<String> = pargs.opt_value_from_str("--tls-hostname")?;
+        let tls_ca: Option<PathBuf> = pargs.opt_value_from_str("--tls-ca")?;
+        let tls_pins = pargs.values_from_fn("--tls-pin", parse_spki_pin)?;
 
+        let reverse_lookup: Option<IpAddr> = pargs.opt_value_from_str("-x")?;
         let record_type = match pargs
             .opt_value_from_str("--record-type")?
             .xor(pargs.opt_value_from_str("-t")?)
         {
             Some(rt) => rt,
+            // Reverse lookups are always looking for PTR records.
+            None if reverse_lookup.is_some() => RecordType::Ptr,
             None => {
                 eprintln!("You must supply exactly one of either -t or --record-type");
                 print!("{}", HELP);
@@ -50,15 +301,168 @@ impl AppArgs {
             }
         };
 
-        // I asked some coworkers and they suggested this DNS resolver
-        let default_resolver = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 53));
-        let resolver = pargs
-            .opt_value_from_str("--resolver")?
-            .or(pargs.opt_value_from_str("-r")?)
-            .unwrap_or(default_resolver);
+        let trace = pargs.contains("--trace");
+        let format = pargs
+            .opt_value_from_str("--format")?
+            .unwrap_or(OutputFormat::Text);
+        let no_edns = pargs.contains("--no-edns");
+        let bufsize = pargs
+            .opt_value_from_str("--bufsize")?
+            .unwrap_or(DEFAULT_UDP_PAYLOAD_SIZE);
+        let default_retry = Retry::default();
+        let retry = Retry {
+            timeout: pargs
+                .opt_value_from_fn("--timeout", parse_timeout)?
+                .unwrap_or(default_retry.timeout),
+            tries: pargs
+                .opt_value_from_fn("--tries", parse_tries)?
+                .unwrap_or(default_retry.tries),
+            backoff: pargs
+                .opt_value_from_fn("--retry-backoff", parse_seconds)?
+                .unwrap_or(default_retry.backoff),
+        };
+        let trust_anchor: Option<PathBuf> = pargs.opt_value_from_str("--trust-anchor")?;
+        let mut validate = pargs.contains("--validate") || trust_anchor.is_some();
+        let dnssec = pargs.contains("--dnssec") || validate;
+        let edns = if no_edns {
+            if dnssec {
+                eprintln!("Warning: --dnssec and --validate need EDNS, so they're ignored with --no-edns.");
+            }
+            validate = false;
+            None
+        } else {
+            let mut edns = Edns::new(bufsize);
+            edns.dnssec_ok = dnssec;
+            Some(edns)
+        };
+
+        // Resolvers without a port get the one the protocol usually uses.
+        let default_port = match &https_url {
+            Some(url) => url_port(url),
+            None if quic => DOQ_PORT,
+            None if tls => DOT_PORT,
+            None => DEFAULT_RESOLVER.port(),
+        };
+        // Compared responses are printed in the order the resolvers were given.
+        let mut pargs = merge_resolver_flags(pargs);
+        let mut resolvers: Vec<SocketAddr> = pargs
+            .values_from_fn("-r", parse_resolver)?
+            .into_iter()
+            .map(|(ip, port)| SocketAddr::new(ip, port.unwrap_or(default_port)))
+            .collect();
+        if trace && !resolvers.is_empty() {
+            eprintln!(
+                "--trace asks the root servers instead of a resolver, so it can't be used with -r"
+            );
+            exit(1);
+        }
+        if resolvers.is_empty() {
+            resolvers.push(match &https_url {
+                // This looks the host up with the OS's resolver, just like browsers do to find
+                // their DoH server.
+                Some(url) => {
+                    let host = url_host(url);
+                    match (host, url_port(url))
+                        .to_socket_addrs()
+                        .map(|mut addrs| addrs.next())
+                    {
+                        Ok(Some(addr)) => addr,
+                        Ok(None) => {
+                            eprintln!("{host} doesn't have any addresses, use -r to choose one");
+                            exit(1);
+                        }
+                        Err(e) => {
+                            eprintln!("Couldn't look up {host}: {e}");
+                            exit(1);
+                        }
+                    }
+                }
+                None if tls => DEFAULT_TLS_RESOLVER,
+                // The default resolver doesn't support DoQ.
+                None if quic => {
+                    eprintln!(
+                        "--quic needs a resolver which supports DNS-over-QUIC, use -r to choose one"
+                    );
+                    exit(1);
+                }
+                None => DEFAULT_RESOLVER,
+            });
+        }
+        if resolvers.len() > 1 && validate {
+            eprintln!("Warning: --validate only applies with one resolver.");
+            validate = false;
+        }
+        let validate = validate.then(|| match &trust_anchor {
+            // Anchors are usually written with absolute names, like IANA's root-anchors.
+            Some(path) => match read_zone_file(path, ".")
+                .and_then(|records| TrustAnchor::from_records(&records))
+            {
+                Ok(anchor) => anchor,
+                Err(e) => {
+                    eprintln!(
+                        "Couldn't read the trust anchor from {}: {e:#}",
+                        path.display()
+                    );
+                    exit(1);
+                }
+            },
+            None => TrustAnchor::root(),
+        });
 
-        let mut name: String = pargs.free_from_str()?;
-        use std::str::FromStr;
+        let encrypted = tls || https_url.is_some() || quic;
+        let ca_pem = match &tls_ca {
+            Some(path) if encrypted => match std::fs::read(path) {
+                Ok(ca_pem) => Some(ca_pem),
+                Err(e) => {
+                    eprintln!("Couldn't read {}: {e}", path.display());
+                    exit(1);
+                }
+            },
+            _ => None,
+        };
+        if !encrypted {
+            if tls_hostname.is_some() || tls_ca.is_some() || !tls_pins.is_empty() {
+                eprintln!("Warning: --tls-hostname, --tls-ca and --tls-pin only apply with --tls, --https or --quic.");
+            }
+            if https_method == https::Method::Get {
+                eprintln!("Warning: --https-get only applies with --https.");
+            }
+        }
+        let transport_for = |resolver: SocketAddr| {
+            if !encrypted {
+                // UDP responses which don't fit will be retried over TCP anyway, but users can
+                // skip straight to TCP if they know the response is going to be big.
+                return if tcp { Transport::Tcp } else { Transport::Udp };
+            }
+            // Without a hostname, the certificate has to be valid for the URL's host, or
+            // failing that, the resolver's IP.
+            let server_name = tls_hostname
+                .clone()
+                .or_else(|| https_url.as_ref().map(|url| url_host(url).to_owned()))
+                .unwrap_or_else(|| resolver.ip().to_string());
+            match TlsConfig::new(&server_name, ca_pem.as_deref(), tls_pins.clone()) {
+                Ok(config) => match &https_url {
+                    Some(url) => {
+                        Transport::Https(HttpsConfig::new(url.clone(), https_method, config))
+                    }
+                    None if quic => Transport::Quic(QuicConfig::new(config)),
+                    None => Transport::Tls(config),
+                },
+                Err(e) => {
+                    eprintln!("{e:#}");
+                    exit(1);
+                }
+            }
+        };
+        let resolvers = resolvers
+            .into_iter()
+            .map(|resolver| (resolver, transport_for(resolver)))
+            .collect();
+
+        let mut name: String = match reverse_lookup {
+            Some(ip) => reverse_lookup_name(ip),
+            None => pargs.free_from_str()?,
+        };
         if AsciiString::from_str(&name).is_err() {
             eprintln!("DNS names must be ASCII, and {name} is not.");
             exit(1);
@@ -70,7 +474,12 @@ impl AppArgs {
         let args = AppArgs {
             record_type,
             name,
-            resolver,
+            resolvers,
+            edns,
+            retry,
+            trace,
+            validate,
+            format,
         };
 
         let remaining = pargs.finish();
@@ -81,3 +490,114 @@ impl AppArgs {
         Ok(args)
     }
 }
+
+/// Parses a resolver's IP address, and its port if it has one.
+fn parse_resolver(s: &str) -> Result<(IpAddr, Option<u16>), String> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok((addr.ip(), Some(addr.port())));
+    }
+    s.parse()
+        .map(|ip| (ip, None))
+        .map_err(|_| format!("{s} isn't an IP address, or an IP address and port"))
+}
+
+/// Parses a number of seconds, which can have a fractional part, like 0.5.
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("{s} isn't a number of seconds"))
+}
+
+fn parse_timeout(s: &str) -> Result<Duration, String> {
+    match parse_seconds(s)? {
+        Duration::ZERO => Err("--timeout has to be more than 0".to_owned()),
+        timeout => Ok(timeout),
+    }
+}
+
+fn parse_tries(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(0) => Err("--tries has to be at least 1".to_owned()),
+        Ok(tries) => Ok(tries),
+        Err(e) => Err(format!("{s} isn't a number of tries: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pargs(args: &[&str]) -> pico_args::Arguments {
+        pico_args::Arguments::from_vec(args.iter().map(OsString::from).collect())
+    }
+
+    #[test]
+    fn test_resolvers_in_order() {
+        let args = ProxyArgs::parse(pargs(&[
+            "-r",
+            "1.1.1.1:53",
+            "--resolver",
+            "8.8.8.8:53",
+            "--resolver=9.9.9.9:53",
+            "-r",
+            "1.0.0.1:53",
+        ]))
+        .unwrap();
+        let upstreams: Vec<String> = args.upstreams.iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            upstreams,
+            ["1.1.1.1:53", "8.8.8.8:53", "9.9.9.9:53", "1.0.0.1:53"]
+        );
+
+        let args = AppArgs::parse(pargs(&[
+            "--resolver",
+            "8.8.8.8",
+            "-t",
+            "A",
+            "-r=9.9.9.9",
+            "example.com",
+            "-r",
+            "1.1.1.1",
+        ]))
+        .unwrap();
+        let resolvers: Vec<String> = args.resolvers.iter().map(|r| r.0.to_string()).collect();
+        assert_eq!(resolvers, ["8.8.8.8:53", "9.9.9.9:53", "1.1.1.1:53"]);
+        assert_eq!(args.name, "example.com.");
+    }
+
+    #[test]
+    fn test_resolver_default_ports() {
+        let resolver = |flags: &[&str]| {
+            let mut args = vec!["-t", "A", "example.com"];
+            args.extend(flags);
+            AppArgs::parse(pargs(&args)).unwrap().resolvers[0].0
+        };
+        assert_eq!(
+            resolver(&["-r", "192.0.2.1"]),
+            "192.0.2.1:53".parse().unwrap()
+        );
+        assert_eq!(
+            resolver(&["--quic", "-r", "192.0.2.1"]),
+            "192.0.2.1:853".parse().unwrap()
+        );
+        assert_eq!(
+            resolver(&["--quic", "-r", "[2001:db8::1]:8853"]),
+            "[2001:db8::1]:8853".parse().unwrap()
+        );
+        assert_eq!(
+            resolver(&["--tls", "--resolver", "2001:db8::1"]),
+            "[2001:db8::1]:853".parse().unwrap()
+        );
+        assert_eq!(
+            resolver(&[
+                "--https",
+                "https://192.0.2.1:8443/dns-query",
+                "-r",
+                "192.0.2.2"
+            ]),
+            "192.0.2.2:8443".parse().unwrap()
+        );
+        assert!(parse_resolver("dns.google").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Following CNAME records to the records they're an alias for.
+use crate::{
+    message::{
+        name::same_name,
+        record::{Record, RecordData},
+        Message,
+    },
+    RecordType,
+};
+use anyhow::Result as AResult;
+
+/// Real chains are only a few CNAMEs long. This is the same limit that BIND uses.
+pub const MAX_CHAIN_LENGTH: usize = 16;
+
+/// The result of following a chain of CNAMEs.
+#[derive(Debug)]
+pub struct Chain {
+    /// The name that was queried, then each name it was an alias for, in order.
+    /// The last name is the canonical one.
+    pub names: Vec<String>,
+    /// The records of the requested type for the canonical name.
+    /// Empty if the name doesn't exist or has no records of that type.
+    pub records: Vec<Record>,
+    /// The last response received.
+    pub response: Message,
+    /// The responses received before the last one, if the resolver didn't send the whole
+    /// chain at once.
+    pub earlier_responses: Vec<Message>,
+}
+
+impl Chain {
+    /// Formats the chain like `a -> b -> c -> 1.2.3.4`.
+    pub fn as_arrows(&self) -> String {
+        let mut parts = self.names.clone();
+        if !self.records.is_empty() {
+            let records: Vec<_> = self.records.iter().map(|r| r.data.to_string()).collect();
+            parts.push(records.join(", "));
+        }
+        parts.join(" -> ")
+    }
+}
+
+/// Finds the records of the given type for a name, following any CNAMEs along the way.
+/// Resolvers normally include the whole chain in their answer, but if they don't, the rest
+/// of the chain is looked up by querying again (using `query`) for the CNAME's target.
+pub fn follow_cnames(
+    name: &str,
+    record_type: RecordType,
+    mut query: impl FnMut(&str, RecordType) -> AResult<Message>,
+) -> AResult<Chain> {
+    let mut names = vec![name.to_owned()];
+    let mut earlier_responses = Vec::new();
+    loop {
+        let queried = names.len();
+        let response = query(names.last().unwrap(), record_type)?;
+        loop {
+            let current = names.last().unwrap();
+            let records: Vec<Record> = response
+                .answer
+                .iter()
+                .filter(|r| same_name(&r.name, current) && r.data.as_type() == record_type)
+                .cloned()
+                .collect();
+            // Found them. Queries for CNAMEs stop here too, instead of following the CNAME.
+            if !records.is_empty() {
+                return Ok(Chain {
+                    names,
+                    records,
+                    response,
+                    earlier_responses,
+                });
+            }
+            let target = response.answer.iter().find_map(|r| match &r.data {
+                RecordData::Cname(target) if same_name(&r.name, current) => Some(target),
+                _ => None,
+            });
+            let Some(target) = target else { break };
+            if names.iter().any(|name| same_name(name, target)) {
+                anyhow::bail!("CNAME loop: {} -> {target}", names.join(" -> "));
+            }
+            if names.len() > MAX_CHAIN_LENGTH {
+                anyhow::bail!("CNAME chain is over the max of {MAX_CHAIN_LENGTH} aliases");
+            }
+            names.push(target.clone());
+        }
+        // If this response didn't lead to any new aliases, there's nothing more to find.
+        if names.len() == queried {
+            return Ok(Chain {
+                names,
+                records: Vec::new(),
+                response,
+                earlier_responses,
+            });
+        }
+        earlier_responses.push(response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::header::Header, test_utils::record, Entry};
+    use std::net::Ipv4Addr;
+
+    fn cname(name: &str, target: &str) -> Record {
+        record(name, RecordData::Cname(target.to_owned()))
+    }
+
+    fn response(name: &str, answer: Vec<Record>) -> Message {
+        let mut header = Header::new_query(1);
+        header.is_response = true;
+        Message {
+            header,
+            question: vec![Entry::new(name.to_owned(), RecordType::A)],
+            answer,
+            authority: Vec::new(),
+            additional: Vec::new(),
+            edns: None,
+        }
+    }
+
+    #[test]
+    fn test_requeries_cname_target() {
+        let mut queries = Vec::new();
+        let chain = follow_cnames("a.com.", RecordType::A, |name, _| {
+            queries.push(name.to_owned());
+            let answer = match name {
+                // The first response has two CNAMEs, but not the address.
+                "a.com." => vec![cname("a.com.", "b.com."), cname("B.com.", "c.com.")],
+                "c.com." => vec![record("c.com.", RecordData::A(Ipv4Addr::new(1, 2, 3, 4)))],
+                _ => unreachable!(),
+            };
+            Ok(response(name, answer))
+        })
+        .unwrap();
+        assert_eq!(queries, vec!["a.com.", "c.com."]);
+        assert_eq!(chain.names, vec!["a.com.", "b.com.", "c.com."]);
+        assert_eq!(chain.earlier_responses.len(), 1);
+        assert_eq!(chain.earlier_responses[0].question[0].name, "a.com.");
+        assert_eq!(chain.as_arrows(), "a.com. -> b.com. -> c.com. -> 1.2.3.4");
+    }
+
+    #[test]
+    fn test_no_records() {
+        let chain = follow_cnames("a.com.", RecordType::A, |name, _| {
+            Ok(response(name, vec![cname("a.com.", "b.com.")]))
+        });
+        // b.com. has no A records, so the second query gets nothing new.
+        let chain = chain.unwrap();
+        assert_eq!(chain.names, vec!["a.com.", "b.com."]);
+        assert!(chain.records.is_empty());
+    }
+
+    #[test]
+    fn test_loop() {
+        let err = follow_cnames("a.com.", RecordType::A, |name, _| {
+            let answer = match name {
+                "a.com." => vec![cname("a.com.", "b.com.")],
+                _ => vec![cname("b.com.", "a.com.")],
+            };
+            Ok(response(name, answer))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "CNAME loop: a.com. -> b.com. -> a.com.");
+    }
+
+    #[test]
+    fn test_chain_too_long() {
+        let result = follow_cnames("0.com.", RecordType::A, |name, _| {
+            let n: usize = name.trim_end_matches(".com.").parse().unwrap();
+            Ok(response(
+                name,
+                vec![cname(name, &format!("{}.com.", n + 1))],
+            ))
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_cname_query_is_not_followed() {
+        let chain = follow_cnames("a.com.", RecordType::Cname, |name, _| {
+            Ok(response(name, vec![cname("a.com.", "b.com.")]))
+        })
+        .unwrap();
+        assert_eq!(chain.names, vec!["a.com."]);
+        assert_eq!(chain.as_arrows(), "a.com. -> b.com.");
+    }
+}
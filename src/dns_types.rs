@@ -1,15 +1,30 @@
 //! Common DNS types that get used in several different parts of the codebase.
 use bitvec::prelude::*;
-use std::{fmt, str::FromStr};
+use serde::{Serialize, Serializer};
+use std::{fmt, fmt::Write, net::IpAddr, str::FromStr};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RecordType {
     A,
     Aaaa,
     Cname,
     Soa,
     Ns,
-    // TODO: Add more record types
+    Mx,
+    Txt,
+    Ptr,
+    Srv,
+    Dnskey,
+    Ds,
+    Rrsig,
+    Nsec,
+    Nsec3,
+    Nsec3param,
+    /// The EDNS pseudo-record. See [`crate::message::edns::Edns`].
+    Opt,
+    /// Any record type dingo doesn't understand yet, identified by its number.
+    /// Records of these types are handled generically, as described by RFC 3597.
+    Unknown(u16),
 }
 
 impl FromStr for RecordType {
@@ -22,8 +37,29 @@ impl FromStr for RecordType {
             "CNAME" => Self::Cname,
             "SOA" => Self::Soa,
             "NS" => Self::Ns,
-            other => return Err(format!("{other} is not a valid DNS record type")),
+            "MX" => Self::Mx,
+            "TXT" => Self::Txt,
+            "PTR" => Self::Ptr,
+            "SRV" => Self::Srv,
+            "DNSKEY" => Self::Dnskey,
+            "DS" => Self::Ds,
+            "RRSIG" => Self::Rrsig,
+            "NSEC" => Self::Nsec,
+            "NSEC3" => Self::Nsec3,
+            "NSEC3PARAM" => Self::Nsec3param,
+            // RFC 3597 lets any type be written as TYPE followed by its number.
+            other => match other.strip_prefix("TYPE").map(u16::from_str) {
+                Some(Ok(num)) => Self::from(num),
+                _ => return Err(format!("{other} is not a valid DNS record type")),
+            },
         };
+        // OPT only ever appears in a message's additional section, as EDNS metadata.
+        // It isn't real data, so it can't be asked for, or be in a zone.
+        if rt == Self::Opt {
+            return Err(format!(
+                "{s} is the EDNS pseudo-record type, not a real record type"
+            ));
+        }
         Ok(rt)
     }
 }
@@ -36,42 +72,86 @@ impl fmt::Display for RecordType {
             Self::Cname => "CNAME",
             Self::Soa => "SOA",
             Self::Ns => "NS",
+            Self::Mx => "MX",
+            Self::Txt => "TXT",
+            Self::Ptr => "PTR",
+            Self::Srv => "SRV",
+            Self::Dnskey => "DNSKEY",
+            Self::Ds => "DS",
+            Self::Rrsig => "RRSIG",
+            Self::Nsec => "NSEC",
+            Self::Nsec3 => "NSEC3",
+            Self::Nsec3param => "NSEC3PARAM",
+            Self::Opt => "OPT",
+            Self::Unknown(num) => return write!(f, "TYPE{num}"),
         };
         s.fmt(f)
     }
 }
 
+/// Record types are written as their name, e.g. "AAAA" or "TYPE65280", not their number.
+impl Serialize for RecordType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl RecordType {
     pub fn serialize<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) {
-        let type_num: u16 = match self {
-            Self::A => 1,
-            Self::Aaaa => 28,
-            Self::Cname => 5,
-            Self::Soa => 6,
-            Self::Ns => 2,
-        };
+        let type_num = u16::from(*self);
         bv.extend_from_bitslice(type_num.view_bits::<Msb0>())
     }
 }
 
-impl TryFrom<u16> for RecordType {
-    type Error = anyhow::Error;
+impl From<RecordType> for u16 {
+    fn from(record_type: RecordType) -> Self {
+        match record_type {
+            RecordType::A => 1,
+            RecordType::Aaaa => 28,
+            RecordType::Cname => 5,
+            RecordType::Soa => 6,
+            RecordType::Ns => 2,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Ptr => 12,
+            RecordType::Srv => 33,
+            RecordType::Dnskey => 48,
+            RecordType::Ds => 43,
+            RecordType::Rrsig => 46,
+            RecordType::Nsec => 47,
+            RecordType::Nsec3 => 50,
+            RecordType::Nsec3param => 51,
+            RecordType::Opt => 41,
+            RecordType::Unknown(num) => num,
+        }
+    }
+}
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        let record_type = match value {
+impl From<u16> for RecordType {
+    fn from(value: u16) -> Self {
+        match value {
             1 => Self::A,
             28 => Self::Aaaa,
             5 => Self::Cname,
             6 => Self::Soa,
             2 => Self::Ns,
-            other => anyhow::bail!("Invalid record type number {other:b}"),
-        };
-        Ok(record_type)
+            15 => Self::Mx,
+            16 => Self::Txt,
+            12 => Self::Ptr,
+            33 => Self::Srv,
+            48 => Self::Dnskey,
+            43 => Self::Ds,
+            46 => Self::Rrsig,
+            47 => Self::Nsec,
+            50 => Self::Nsec3,
+            51 => Self::Nsec3param,
+            41 => Self::Opt,
+            other => Self::Unknown(other),
+        }
     }
 }
 
-#[derive(Debug)]
-#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub enum Class {
     IN,
 }
@@ -85,12 +165,28 @@ impl fmt::Display for Class {
     }
 }
 
+impl FromStr for Class {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "IN" => Ok(Self::IN),
+            other => Err(format!("{other} is not a supported DNS class")),
+        }
+    }
+}
+
 impl Class {
     pub fn serialize<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) {
-        let type_num: u16 = match self {
-            Self::IN => 1,
-        };
-        bv.extend_from_bitslice(type_num.view_bits::<Msb0>())
+        bv.extend_from_bitslice(u16::from(*self).view_bits::<Msb0>())
+    }
+}
+
+impl From<Class> for u16 {
+    fn from(class: Class) -> Self {
+        match class {
+            Class::IN => 1,
+        }
     }
 }
 
@@ -105,3 +201,55 @@ impl TryFrom<u16> for Class {
         Ok(record_type)
     }
 }
+
+/// The domain name to query for PTR records when doing a reverse lookup of the given address.
+/// IPv4 addresses go under in-addr.arpa, one label per octet, in reverse order
+/// (see <https://datatracker.ietf.org/doc/html/rfc1035#section-3.5>).
+/// IPv6 addresses go under ip6.arpa, one label per nibble, in reverse order
+/// (see <https://datatracker.ietf.org/doc/html/rfc3596#section-2.5>).
+pub fn reverse_lookup_name(ip: IpAddr) -> String {
+    let mut name = String::new();
+    match ip {
+        IpAddr::V4(ip) => {
+            for octet in ip.octets().iter().rev() {
+                write!(name, "{octet}.").unwrap();
+            }
+            name.push_str("in-addr.arpa.");
+        }
+        IpAddr::V6(ip) => {
+            for octet in ip.octets().iter().rev() {
+                write!(name, "{:x}.{:x}.", octet & 0xf, octet >> 4).unwrap();
+            }
+            name.push_str("ip6.arpa.");
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_generic_record_type() {
+        assert_eq!("TYPE12345".parse(), Ok(RecordType::Unknown(12345)));
+        assert_eq!("type1".parse(), Ok(RecordType::A));
+        assert!("TYPE70000".parse::<RecordType>().is_err());
+        assert!("TYPO".parse::<RecordType>().is_err());
+        assert!("OPT".parse::<RecordType>().is_err());
+        assert!("TYPE41".parse::<RecordType>().is_err());
+        assert_eq!(RecordType::Unknown(12345).to_string(), "TYPE12345");
+    }
+
+    #[test]
+    fn test_reverse_lookup_name() {
+        assert_eq!(
+            reverse_lookup_name("1.2.3.4".parse().unwrap()),
+            "4.3.2.1.in-addr.arpa."
+        );
+        assert_eq!(
+            reverse_lookup_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        );
+    }
+}
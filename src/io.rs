@@ -1,36 +1,294 @@
-//! Doing network IO and printing to the terminal.
-use crate::message::{header::ResponseCode, Message, MAX_UDP_BYTES};
+//! Doing network IO.
+pub mod https;
+pub mod quic;
+#[cfg(test)]
+mod test_utils;
+pub mod tls;
+
+use crate::{
+    message::{
+        edns::{Edns, DEFAULT_UDP_PAYLOAD_SIZE},
+        header::Header,
+        Message,
+    },
+    RecordType,
+};
 use anyhow::{anyhow, Result as AResult};
+use https::{send_https, HttpsConfig};
+use quic::{send_quic, QuicConfig};
+use rand::Rng;
+use rustls::{ClientConnection, StreamOwned};
 use std::{
-    net::{SocketAddr, UdpSocket},
+    fmt,
+    future::Future,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream, UdpSocket},
+    thread,
     time::Duration,
 };
+use tls::TlsConfig;
+
+/// How long to wait for a response, unless told otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Gets debugging information about queries, one line at a time, e.g. to print it.
+pub type Log = fn(&str);
+
+/// Which protocol carries the DNS message to the resolver.
+#[derive(Debug, Clone)]
+pub enum Transport {
+    /// Plain UDP, falling back to TCP if the response was truncated.
+    Udp,
+    /// Always use TCP.
+    Tcp,
+    /// DNS-over-TLS, i.e. TCP inside a TLS session.
+    Tls(TlsConfig),
+    /// DNS-over-HTTPS, i.e. HTTP/2 requests inside a TLS session.
+    Https(HttpsConfig),
+    /// DNS-over-QUIC, i.e. each query on its own stream of a QUIC connection.
+    Quic(QuicConfig),
+}
+
+/// How long to wait for responses, and how many times to ask before giving up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// How long to wait for each response.
+    pub timeout: Duration,
+    /// How many times to send the query, including the first time.
+    pub tries: u32,
+    /// How long to wait before the first retry. Each retry after that waits twice as long as
+    /// the one before.
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    /// The same as dig: 3 tries, with 5 seconds to respond to each.
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            tries: 3,
+            backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+impl Retry {
+    /// How long to wait before the given retry (the first retry is 1).
+    fn delay(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(retry - 1))
+    }
+}
+
+/// The query couldn't be sent, or its response didn't arrive, e.g. because of a timeout or a
+/// dropped connection. Unlike other errors, these might not happen next time, so queries which
+/// fail with them are retried.
+#[derive(Debug)]
+pub struct TransportError(pub String);
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+fn transport_error(message: String) -> anyhow::Error {
+    TransportError(message).into()
+}
+
+/// I/O errors are transport errors, except for TLS errors, which rustls reports as I/O errors
+/// of the InvalidData kind. Those mean the server can't be trusted, which retrying won't fix.
+fn io_error(e: &std::io::Error, message: String) -> anyhow::Error {
+    if e.kind() == std::io::ErrorKind::InvalidData {
+        anyhow!(message)
+    } else {
+        transport_error(message)
+    }
+}
+
+/// Sends queries to a DNS resolver, and parses its responses.
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// The resolver to query.
+    pub resolver: SocketAddr,
+    pub transport: Transport,
+    /// The OPT record to send with each query, or None to not use EDNS.
+    pub edns: Option<Edns>,
+    pub retry: Retry,
+    /// Where to send debugging information about each query, if anywhere.
+    pub log: Option<Log>,
+}
+
+impl Client {
+    /// A client which queries the given resolver over UDP, with EDNS.
+    pub fn new(resolver: SocketAddr) -> Self {
+        Self {
+            resolver,
+            transport: Transport::Udp,
+            edns: Some(Edns::new(DEFAULT_UDP_PAYLOAD_SIZE)),
+            retry: Retry::default(),
+            log: None,
+        }
+    }
+
+    /// Asks the resolver for records of the given type and name, and parses the response.
+    /// Responses with an error code are still returned, so check `header.resp_code`.
+    /// If there's no response, or the connection fails, the query is retried, until it runs out
+    /// of tries. Other errors (e.g. an untrusted certificate, or a response with the wrong ID)
+    /// would just happen again, so they're returned straight away.
+    pub fn query(&self, name: &str, record_type: RecordType) -> AResult<Message> {
+        let mut attempts = Vec::new();
+        for attempt in 1..=self.retry.tries.max(1) {
+            if attempt > 1 {
+                let delay = self.retry.delay(attempt - 1);
+                debug(self.log, || format!("Retrying in {delay:?}"));
+                thread::sleep(delay);
+            }
+            // Each try gets a new ID, so a late response to an earlier try can't be mistaken
+            // for the response to this one.
+            let query_id = rand::thread_rng().gen();
+            match self.query_once(query_id, name, record_type) {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    let retryable = self.retry.tries > 1 && e.is::<TransportError>();
+                    if !retryable && attempts.is_empty() {
+                        return Err(e);
+                    }
+                    // Earlier tries are still reported, so a timeout isn't hidden by
+                    // whatever went wrong after it.
+                    attempts.push(format!("  try {attempt} with ID {query_id}: {e}"));
+                    if !retryable {
+                        break;
+                    }
+                }
+            }
+        }
+        Err(anyhow!(
+            "No response after {} tries:\n{}",
+            attempts.len(),
+            attempts.join("\n")
+        ))
+    }
+
+    fn query_once(&self, query_id: u16, name: &str, record_type: RecordType) -> AResult<Message> {
+        let msg = Message::new_query(query_id, name.to_owned(), record_type, self.edns.clone())?;
+        let (resp, len) = send_req(
+            msg,
+            self.resolver,
+            &self.transport,
+            self.retry.timeout,
+            self.log,
+        )?;
+        debug(self.log, || format!("Response size: {len} bytes"));
+        debug(self.log, || format!("{:?}", &resp[..len]));
+        let response_msg = Message::deserialize(resp[..len].to_vec())
+            .map_err(|e| anyhow!("Error parsing response: {e}"))?;
+        let received_query_id = response_msg.header.id;
+        if query_id != received_query_id {
+            anyhow::bail!("Mismatch between query IDs. Client sent {query_id} and received {received_query_id}");
+        }
+        Ok(response_msg)
+    }
+}
 
 /// Sends the given DNS message to the given resolver.
 /// Returns the binary response.
-pub fn send_req(msg: Message, resolver: SocketAddr, verbose: bool) -> AResult<(Vec<u8>, usize)> {
+pub fn send_req(
+    mut msg: Message,
+    resolver: SocketAddr,
+    transport: &Transport,
+    timeout: Duration,
+    log: Option<Log>,
+) -> AResult<(Vec<u8>, usize)> {
+    // HTTP requests and DoQ streams already match responses to queries, so their queries use
+    // ID 0, which makes DoH GET responses easier to cache. See
+    // <https://datatracker.ietf.org/doc/html/rfc8484#section-4.1> and
+    // <https://datatracker.ietf.org/doc/html/rfc9250#section-4.2.1>
+    // Callers still expect the response to have their ID though, so it's put back afterwards.
+    let query_id = msg.header.id;
+    if let Transport::Https(_) | Transport::Quic(_) = transport {
+        msg.header.id = 0;
+    }
+    let body = msg.serialize_bytes()?;
+    debug(log, || format!("Request size: {} bytes", body.len()));
+    match transport {
+        Transport::Tcp => send_tcp(&body, resolver, timeout, log),
+        Transport::Tls(tls) => send_tls(&body, resolver, tls, timeout, log),
+        Transport::Https(https) => {
+            let response = send_https(&body, resolver, https, timeout, log)?;
+            restore_query_id(response, query_id, "DoH server")
+        }
+        Transport::Quic(quic) => {
+            let response = send_quic(&body, resolver, quic, timeout, log)?;
+            restore_query_id(response, query_id, "DoQ server")
+        }
+        Transport::Udp => {
+            let max_response_bytes = msg.max_response_bytes();
+            let (resp, len) = send_udp(&body, resolver, max_response_bytes, timeout, log)?;
+            if !is_truncated(&resp[..len]) {
+                return Ok((resp, len));
+            }
+            // The response didn't fit in a UDP datagram, so the resolver sent back whatever
+            // it could, with the TC bit set. Ask again over TCP to get the whole thing.
+            debug(log, || {
+                "Response was truncated, retrying over TCP".to_owned()
+            });
+            send_tcp(&body, resolver, timeout, log)
+        }
+    }
+}
+
+/// Checks the response to a query sent with ID 0 has ID 0 too, then gives it the ID the
+/// caller used.
+fn restore_query_id(
+    (mut resp, len): (Vec<u8>, usize),
+    query_id: u16,
+    server: &str,
+) -> AResult<(Vec<u8>, usize)> {
+    let (_, header) =
+        Header::parse(&resp[..len]).map_err(|e| anyhow!("Error parsing response header: {e}"))?;
+    if header.id != 0 {
+        anyhow::bail!("{server} replied with message ID {}, not 0", header.id);
+    }
+    resp[..2].copy_from_slice(&query_id.to_be_bytes());
+    Ok((resp, len))
+}
+
+/// Sends the serialized DNS message over UDP.
+fn send_udp(
+    body: &[u8],
+    resolver: SocketAddr,
+    max_response_bytes: usize,
+    timeout: Duration,
+    log: Option<Log>,
+) -> AResult<(Vec<u8>, usize)> {
     // Connect to the DNS resolver
-    let local_addr = "0.0.0.0:0";
-    let socket = UdpSocket::bind(local_addr).expect("couldn't bind to a local address");
-    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
-    if verbose {
-        println!("Bound to local {}", socket.local_addr()?);
+    let local_addr = if resolver.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local_addr)
+        .map_err(|e| anyhow!("couldn't bind to a local address: {e}"))?;
+    socket.set_read_timeout(Some(timeout))?;
+    if let Ok(local_addr) = socket.local_addr() {
+        debug(log, || format!("Bound to local {local_addr}"));
     }
     socket
         .connect(resolver)
-        .expect("couldn't connect to the DNS resolver");
-    if verbose {
-        println!("Connected to remote {resolver}");
-    }
+        .map_err(|e| io_error(&e, format!("couldn't connect to the DNS resolver: {e}")))?;
+    debug(log, || format!("Connected to remote {resolver}"));
 
     // Send the DNS resolver the message
-    let body = msg.serialize_bytes()?;
-    if verbose {
-        println!("Request size: {} bytes", body.len());
-    }
-    let bytes_sent = socket.send(&body).expect("couldn't send data");
+    let bytes_sent = socket
+        .send(body)
+        .map_err(|e| io_error(&e, format!("couldn't send the query: {e}")))?;
     if bytes_sent != body.len() {
-        panic!("Only {bytes_sent} bytes, message was probably truncated");
+        anyhow::bail!(
+            "Only sent {bytes_sent} of {} bytes, the query was probably truncated",
+            body.len()
+        );
     }
 
     // Get the resolver's response.
@@ -38,59 +296,340 @@ pub fn send_req(msg: Message, resolver: SocketAddr, verbose: bool) -> AResult<(V
     // I was originally using an empty vector, but reading into an empty vector always
     // instantly succeeds (by writing nothing), so I was discarding the response.
     // See <https://users.rust-lang.org/t/empty-response-from-udp-recv-w-tokio-and-futures/20241/2>
-    let mut response_buf = vec![0; MAX_UDP_BYTES];
+    let mut response_buf = vec![0; max_response_bytes];
     match socket.recv(&mut response_buf) {
         Ok(received) => Ok((response_buf, received)),
-        Err(e) => Err(anyhow!("recv function failed: {:?}", e)),
+        Err(e) if is_timeout(&e) => Err(transport_error(format!("no response within {timeout:?}"))),
+        Err(e) => Err(io_error(&e, format!("couldn't receive the response: {e}"))),
     }
 }
 
-/// Parse the binary response into a DNS message, and print it nicely.
-pub fn print_resp(resp: Vec<u8>, len: usize, sent_query_id: u16, verbose: bool) -> AResult<()> {
-    if verbose {
-        println!("Response size: {len} bytes");
-        println!("{resp:?}");
-    }
+/// Sends the serialized DNS message over TCP.
+fn send_tcp(
+    body: &[u8],
+    resolver: SocketAddr,
+    timeout: Duration,
+    log: Option<Log>,
+) -> AResult<(Vec<u8>, usize)> {
+    let mut stream = connect_tcp(resolver, timeout, log)?;
+    send_framed(&mut stream, body, timeout)
+}
 
-    // Parse and validate the response.
-    let input = resp[..len].to_vec();
-    let response_msg = match Message::deserialize(input) {
-        Ok(msg) => msg,
-        Err(e) => anyhow::bail!("Error parsing response: {e}"),
-    };
-    let received_query_id = response_msg.header.id;
-    if sent_query_id != received_query_id {
-        eprintln!("Mismatch between query IDs. Client sent {sent_query_id} and received {received_query_id}")
+/// Sends the serialized DNS message over TLS. The TLS handshake happens when the query is
+/// first written, so certificate errors show up then.
+fn send_tls(
+    body: &[u8],
+    resolver: SocketAddr,
+    tls: &TlsConfig,
+    timeout: Duration,
+    log: Option<Log>,
+) -> AResult<(Vec<u8>, usize)> {
+    let stream = connect_tcp(resolver, timeout, log)?;
+    let conn = ClientConnection::new(tls.config.clone(), tls.server_name.clone())?;
+    let mut stream = StreamOwned::new(conn, stream);
+    let response = send_framed(&mut stream, body, timeout)?;
+    if let Some(version) = stream.conn.protocol_version() {
+        debug(log, || format!("TLS session used {version:?}"));
     }
-    match response_msg.header.resp_code {
-        ResponseCode::NoError => {}
-        err => anyhow::bail!("Error from resolver: {err}"),
-    };
+    Ok(response)
+}
+
+fn connect_tcp(resolver: SocketAddr, timeout: Duration, log: Option<Log>) -> AResult<TcpStream> {
+    let stream = TcpStream::connect_timeout(&resolver, timeout).map_err(|e| {
+        io_error(
+            &e,
+            format!("couldn't connect to the DNS resolver over TCP: {e}"),
+        )
+    })?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    debug(log, || format!("Connected to remote {resolver} over TCP"));
+    Ok(stream)
+}
 
-    // Reprint the question, why not?
-    println!("Questions:");
-    for question in response_msg.question.iter() {
-        println!("{question}");
+/// Passes a line of debugging information to the log, if there is one.
+pub(crate) fn debug(log: Option<Log>, line: impl FnOnce() -> String) {
+    if let Some(log) = log {
+        log(&line());
     }
+}
+
+/// Messages sent over TCP (and TLS and QUIC) are prefixed with a two byte length field, see
+/// <https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2>
+fn frame(body: &[u8]) -> AResult<Vec<u8>> {
+    let len = u16::try_from(body.len())
+        .map_err(|_| anyhow!("Request is {} bytes, too long for TCP", body.len()))?;
+    let mut framed = Vec::with_capacity(body.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(body);
+    Ok(framed)
+}
 
-    // Print records sent by the resolver.
-    if !response_msg.answer.is_empty() {
-        println!("Answers:");
-        for record in response_msg.answer {
-            println!("{}", record.as_dns_response());
+fn send_framed(
+    stream: &mut (impl Read + Write),
+    body: &[u8],
+    timeout: Duration,
+) -> AResult<(Vec<u8>, usize)> {
+    let framed = frame(body)?;
+    stream
+        .write_all(&framed)
+        .and_then(|()| stream.flush())
+        .map_err(|e| io_error(&e, format!("couldn't send the query: {e}")))?;
+
+    let mut len_buf = [0; 2];
+    stream.read_exact(&mut len_buf).map_err(|e| {
+        if is_timeout(&e) {
+            transport_error(format!("no response within {timeout:?}"))
+        } else {
+            io_error(&e, format!("couldn't read TCP response length: {e}"))
         }
+    })?;
+    let len = u16::from_be_bytes(len_buf) as usize;
+    let mut response_buf = vec![0; len];
+    stream
+        .read_exact(&mut response_buf)
+        .map_err(|e| io_error(&e, format!("couldn't read {len} byte TCP response: {e}")))?;
+    Ok((response_buf, len))
+}
+
+/// The HTTP/2 and QUIC libraries are async, so their transports run the query on a runtime
+/// just for it, giving up if the server takes too long.
+fn block_on<T>(
+    server: &str,
+    timeout: Duration,
+    future: impl Future<Output = AResult<T>>,
+) -> AResult<T> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| transport_error(format!("{server} didn't respond within {timeout:?}")))?
+    })
+}
+
+/// Sockets with a read timeout return one of these errors when it runs out, depending on the OS.
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// Checks the TC bit in the header of a binary response.
+fn is_truncated(resp: &[u8]) -> bool {
+    Header::parse(resp)
+        .map(|(_, header)| header.truncation)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MAX_UDP_BYTES;
+    use std::{net::TcpListener, thread};
+    use test_utils::{query, respond, response_bytes};
+
+    /// Accepts one TCP connection, reads a length-prefixed query, and replies with `resp`.
+    fn serve_one_tcp(listener: TcpListener, resp: Vec<u8>) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            respond(&mut stream, &resp).unwrap()
+        })
     }
-    if !response_msg.authority.is_empty() {
-        println!("Authority records:");
-        for record in response_msg.authority {
-            println!("{}", record.as_dns_response());
-        }
+
+    #[test]
+    fn test_tcp_uses_length_prefix() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = serve_one_tcp(listener, response_bytes());
+
+        let (resp, len) = send_req(query(), addr, &Transport::Tcp, DEFAULT_TIMEOUT, None).unwrap();
+        assert_eq!(resp[..len], response_bytes());
+        let received_query = server.join().unwrap();
+        assert_eq!(received_query, query().serialize_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_truncated_udp_retries_over_tcp() {
+        // The UDP and TCP servers have to share a port, just like a real resolver.
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let listener = TcpListener::bind(addr).unwrap();
+
+        // Over UDP, only send the header and question, with the TC bit set.
+        let mut truncated = response_bytes()[..39].to_vec();
+        truncated[2] |= 0b0000_0010;
+        truncated[7] = 0;
+        let udp_server = thread::spawn(move || {
+            let mut buf = [0; MAX_UDP_BYTES];
+            let (_, client) = udp.recv_from(&mut buf).unwrap();
+            udp.send_to(&truncated, client).unwrap();
+        });
+        let tcp_server = serve_one_tcp(listener, response_bytes());
+
+        let (resp, len) = send_req(query(), addr, &Transport::Udp, DEFAULT_TIMEOUT, None).unwrap();
+        udp_server.join().unwrap();
+        tcp_server.join().unwrap();
+        let msg = Message::deserialize(resp[..len].to_vec()).unwrap();
+        assert!(!msg.header.truncation);
+        assert_eq!(msg.answer.len(), 2);
+    }
+
+    #[test]
+    fn test_client_query() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut buf = [0; MAX_UDP_BYTES];
+            let (_, client) = udp.recv_from(&mut buf).unwrap();
+            // Reply with the same ID the client used.
+            let mut resp = response_bytes();
+            resp[..2].copy_from_slice(&buf[..2]);
+            udp.send_to(&resp, client).unwrap();
+        });
+
+        let msg = Client::new(addr)
+            .query("blog.adamchalmers.com", RecordType::A)
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(msg.question[0].name, "blog.adamchalmers.com.");
+        assert_eq!(msg.answer.len(), 2);
     }
-    if !response_msg.additional.is_empty() {
-        println!("Additional records:");
-        for record in response_msg.additional {
-            println!("{}", record.as_dns_response());
+
+    #[test]
+    fn test_client_retries_lost_packets() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            // Drop the first query, like a flaky network would.
+            let mut buf = [0; MAX_UDP_BYTES];
+            udp.recv_from(&mut buf).unwrap();
+            let first_id = u16::from_be_bytes([buf[0], buf[1]]);
+            let (_, client) = udp.recv_from(&mut buf).unwrap();
+            let mut resp = response_bytes();
+            resp[..2].copy_from_slice(&buf[..2]);
+            udp.send_to(&resp, client).unwrap();
+            (first_id, u16::from_be_bytes([buf[0], buf[1]]))
+        });
+
+        let mut client = Client::new(addr);
+        client.retry = Retry {
+            timeout: Duration::from_millis(200),
+            tries: 2,
+            backoff: Duration::from_millis(10),
+        };
+        let msg = client
+            .query("blog.adamchalmers.com", RecordType::A)
+            .unwrap();
+        assert_eq!(msg.answer.len(), 2);
+        let (first_id, second_id) = server.join().unwrap();
+        assert_ne!(first_id, second_id, "The retry should use a new ID");
+    }
+
+    #[test]
+    fn test_client_lists_every_try() {
+        // Nothing ever reads from this socket, so the queries all time out.
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = Client::new(udp.local_addr().unwrap());
+        client.retry = Retry {
+            timeout: Duration::from_millis(50),
+            tries: 3,
+            backoff: Duration::from_millis(10),
+        };
+        let err = client
+            .query("blog.adamchalmers.com", RecordType::A)
+            .unwrap_err()
+            .to_string();
+        let lines: Vec<_> = err.lines().collect();
+        assert_eq!(lines[0], "No response after 3 tries:");
+        for (i, line) in lines[1..].iter().enumerate() {
+            let try_n = format!("  try {} with ID ", i + 1);
+            assert!(line.starts_with(&try_n), "{line}");
+            assert!(line.ends_with(": no response within 50ms"), "{line}");
         }
+        assert_eq!(lines.len(), 4);
+
+        // Without retries, the error is left alone.
+        client.retry.tries = 1;
+        let err = client.query("blog.adamchalmers.com", RecordType::A);
+        assert_eq!(err.unwrap_err().to_string(), "no response within 50ms");
+    }
+
+    #[test]
+    fn test_client_only_retries_transport_errors() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            // Reply to every query with the wrong ID, and count them.
+            udp.set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            let mut buf = [0; MAX_UDP_BYTES];
+            let mut queries = 0;
+            while let Ok((_, client)) = udp.recv_from(&mut buf) {
+                queries += 1;
+                let mut resp = response_bytes();
+                let wrong_id = u16::from_be_bytes([buf[0], buf[1]]).wrapping_add(1);
+                resp[..2].copy_from_slice(&wrong_id.to_be_bytes());
+                udp.send_to(&resp, client).unwrap();
+            }
+            queries
+        });
+
+        let mut client = Client::new(addr);
+        client.retry.backoff = Duration::from_millis(10);
+        let err = client
+            .query("blog.adamchalmers.com", RecordType::A)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Mismatch between query IDs."), "{err}");
+        assert_eq!(server.join().unwrap(), 1);
+
+        // A bad response after a timeout stops the retries, but the timeout is still reported.
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            udp.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            let mut buf = [0; MAX_UDP_BYTES];
+            let mut queries = 0;
+            while let Ok((_, client)) = udp.recv_from(&mut buf) {
+                queries += 1;
+                if queries == 1 {
+                    continue;
+                }
+                let mut resp = response_bytes();
+                let wrong_id = u16::from_be_bytes([buf[0], buf[1]]).wrapping_add(1);
+                resp[..2].copy_from_slice(&wrong_id.to_be_bytes());
+                udp.send_to(&resp, client).unwrap();
+            }
+            queries
+        });
+
+        let mut client = Client::new(addr);
+        client.retry.timeout = Duration::from_millis(200);
+        client.retry.backoff = Duration::from_millis(10);
+        let err = client
+            .query("blog.adamchalmers.com", RecordType::A)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("No response after 2 tries:"), "{err}");
+        let tries: Vec<_> = err.lines().skip(1).collect();
+        assert_eq!(tries.len(), 2, "{err}");
+        assert!(tries[0].starts_with("  try 1 with ID "), "{err}");
+        assert!(tries[1].starts_with("  try 2 with ID "), "{err}");
+        assert!(tries[1].contains("Mismatch between query IDs."), "{err}");
+        assert_eq!(server.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let retry = Retry {
+            timeout: DEFAULT_TIMEOUT,
+            tries: 4,
+            backoff: Duration::from_millis(100),
+        };
+        let delays: Vec<_> = (1..4).map(|retry_n| retry.delay(retry_n)).collect();
+        assert_eq!(delays, [100, 200, 400].map(Duration::from_millis));
     }
-    Ok(())
 }
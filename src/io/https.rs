@@ -0,0 +1,372 @@
+//! DNS-over-HTTPS, from <https://datatracker.ietf.org/doc/html/rfc8484>: each query is an
+//! HTTP/2 request to a URL like <https://cloudflare-dns.com/dns-query>, and the response body
+//! is the DNS response. It's what browsers use, so it's handy to be able to test the same path.
+use super::{block_on, debug, io_error, tls::TlsConfig, transport_error, Log};
+use anyhow::Result as AResult;
+use bytes::Bytes;
+use data_encoding::BASE64URL_NOPAD;
+use http::{header, HeaderValue, Request, StatusCode, Uri};
+use std::{net::SocketAddr, str::FromStr, time::Duration};
+use tokio_rustls::TlsConnector;
+
+/// The media type of DNS messages in HTTP requests and responses.
+pub const DNS_MESSAGE: &str = "application/dns-message";
+
+/// The port DoH servers listen on, unless the URL says otherwise.
+pub const HTTPS_PORT: u16 = 443;
+
+/// Responses can't be bigger than this, because DNS messages have 16-bit lengths elsewhere.
+const MAX_RESPONSE_BYTES: usize = u16::MAX as usize;
+
+/// How to put the DNS query into the HTTP request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Base64url-encoded in the `dns` query parameter, which HTTP caches understand.
+    Get,
+    /// As the request body.
+    Post,
+}
+
+/// Which DoH server to send queries to, and how.
+#[derive(Debug, Clone)]
+pub struct HttpsConfig {
+    /// The URL to send requests to, e.g. <https://cloudflare-dns.com/dns-query>
+    pub url: Uri,
+    pub method: Method,
+    pub(crate) tls: TlsConfig,
+}
+
+impl HttpsConfig {
+    /// Requests will be sent over a TLS session started with `tls`, which should be for the
+    /// URL's host.
+    pub fn new(url: Uri, method: Method, tls: TlsConfig) -> Self {
+        Self {
+            url,
+            method,
+            tls: tls.with_alpn(b"h2"),
+        }
+    }
+}
+
+/// The URL's host, without the brackets around IPv6 addresses.
+pub fn url_host(url: &Uri) -> &str {
+    let host = url.host().unwrap_or_default();
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// The URL's port, or the default HTTPS port if it doesn't have one.
+pub fn url_port(url: &Uri) -> u16 {
+    url.port_u16().unwrap_or(HTTPS_PORT)
+}
+
+/// Parses the URL of a DoH server. It has to be HTTPS, because DoH is always encrypted.
+pub fn parse_url(url: &str) -> Result<Uri, String> {
+    let uri = Uri::from_str(url).map_err(|e| format!("{url} isn't a valid URL: {e}"))?;
+    if uri.scheme_str() != Some("https") {
+        return Err(format!("{url} isn't an https:// URL"));
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        return Err(format!("{url} doesn't have a host"));
+    }
+    if uri.path() == "/" {
+        return Err(format!(
+            "{url} doesn't have a path, DoH servers usually use /dns-query"
+        ));
+    }
+    Ok(uri)
+}
+
+/// Sends the serialized DNS message in an HTTP/2 request to the DoH server at `resolver`.
+/// The message's ID should already be 0.
+pub(crate) fn send_https(
+    body: &[u8],
+    resolver: SocketAddr,
+    https: &HttpsConfig,
+    timeout: Duration,
+    log: Option<Log>,
+) -> AResult<(Vec<u8>, usize)> {
+    let response = block_on("DoH server", timeout, exchange(body, resolver, https, log))?;
+    let len = response.len();
+    Ok((response, len))
+}
+
+async fn exchange(
+    body: &[u8],
+    resolver: SocketAddr,
+    https: &HttpsConfig,
+    log: Option<Log>,
+) -> AResult<Vec<u8>> {
+    let stream = tokio::net::TcpStream::connect(resolver)
+        .await
+        .map_err(|e| io_error(&e, format!("couldn't connect to the DoH server: {e}")))?;
+    debug(log, || format!("Connected to remote {resolver} over TCP"));
+    let stream = TlsConnector::from(https.tls.config.clone())
+        .connect(https.tls.server_name.clone(), stream)
+        .await
+        .map_err(|e| io_error(&e, e.to_string()))?;
+    if stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+        anyhow::bail!("The DoH server doesn't support HTTP/2");
+    }
+    let (client, connection) = h2::client::handshake(stream).await.map_err(h2_error)?;
+    // The connection does the actual IO, so it has to keep running while the request is sent.
+    tokio::spawn(connection);
+
+    let request = doh_request(body, &https.url, https.method)?;
+    debug(log, || {
+        format!("Sending HTTP/2 {} {}", request.method(), request.uri())
+    });
+    let mut client = client.ready().await.map_err(h2_error)?;
+    let (response, mut send) = client
+        .send_request(request, https.method == Method::Get)
+        .map_err(h2_error)?;
+    if https.method == Method::Post {
+        send.send_data(Bytes::copy_from_slice(body), true)
+            .map_err(h2_error)?;
+    }
+    let (parts, mut body) = response.await.map_err(h2_error)?.into_parts();
+    debug(log, || format!("HTTP status {}", parts.status));
+    if parts.status != StatusCode::OK {
+        anyhow::bail!("DoH server returned HTTP status {}", parts.status);
+    }
+    let content_type = parts.headers.get(header::CONTENT_TYPE);
+    if !content_type.is_some_and(is_dns_message) {
+        anyhow::bail!("DoH server's response has content type {content_type:?}, not {DNS_MESSAGE}");
+    }
+
+    let mut response = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(h2_error)?;
+        response.extend_from_slice(&chunk);
+        if response.len() > MAX_RESPONSE_BYTES {
+            anyhow::bail!("DoH server's response is over {MAX_RESPONSE_BYTES} bytes");
+        }
+        body.flow_control().release_capacity(chunk.len())?;
+    }
+    Ok(response)
+}
+
+/// HTTP/2 errors are transport errors if the connection broke, but not if the server refused
+/// the request.
+fn h2_error(e: h2::Error) -> anyhow::Error {
+    if e.is_io() {
+        transport_error(e.to_string())
+    } else {
+        e.into()
+    }
+}
+
+/// Checks the media type of a Content-Type header, ignoring any parameters (like
+/// `charset=utf-8`), see <https://datatracker.ietf.org/doc/html/rfc9110#section-8.3.1>
+fn is_dns_message(content_type: &HeaderValue) -> bool {
+    let Ok(content_type) = content_type.to_str() else {
+        return false;
+    };
+    let media_type = content_type.split(';').next().unwrap_or_default();
+    media_type.trim().eq_ignore_ascii_case(DNS_MESSAGE)
+}
+
+/// Makes the HTTP request for a DNS query, see
+/// <https://datatracker.ietf.org/doc/html/rfc8484#section-4.1>
+fn doh_request(body: &[u8], url: &Uri, method: Method) -> AResult<Request<()>> {
+    let request = Request::builder().header(header::ACCEPT, DNS_MESSAGE);
+    let request = match method {
+        Method::Get => {
+            // The URL might already have a query string, if so, add the DNS query to it.
+            let separator = if url.query().is_some() { '&' } else { '?' };
+            let dns = BASE64URL_NOPAD.encode(body);
+            request
+                .method(http::Method::GET)
+                .uri(format!("{url}{separator}dns={dns}"))
+        }
+        Method::Post => request
+            .method(http::Method::POST)
+            .uri(url)
+            .header(header::CONTENT_TYPE, DNS_MESSAGE)
+            .header(header::CONTENT_LENGTH, body.len()),
+    };
+    Ok(request.body(())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{
+        send_req,
+        test_utils::{query, response_bytes, test_ca},
+        Transport, DEFAULT_TIMEOUT,
+    };
+    use rustls::ServerConfig;
+    use std::{net::TcpListener, sync::Arc, thread};
+
+    #[test]
+    fn test_parse_url() {
+        let url = parse_url("https://[::1]:8443/dns-query").unwrap();
+        assert_eq!(url_host(&url), "::1");
+        assert_eq!(url_port(&url), 8443);
+        let url = parse_url("https://dns.google/dns-query").unwrap();
+        assert_eq!(url_host(&url), "dns.google");
+        assert_eq!(url_port(&url), 443);
+        assert_eq!(
+            parse_url("http://dns.google/dns-query").unwrap_err(),
+            "http://dns.google/dns-query isn't an https:// URL"
+        );
+        assert!(parse_url("https://dns.google").is_err());
+        assert!(parse_url("not a url").is_err());
+    }
+
+    #[test]
+    fn test_doh_request() {
+        // The example from RFC 8484 section 4.1.1, a query for www.example.com with ID 0.
+        let body =
+            crate::Message::new_query(0, "www.example.com".to_owned(), crate::RecordType::A, None)
+                .unwrap()
+                .serialize_bytes()
+                .unwrap();
+        let url = parse_url("https://dnsserver.example.net/dns-query").unwrap();
+        let get = doh_request(&body, &url, Method::Get).unwrap();
+        assert_eq!(get.method(), http::Method::GET);
+        assert_eq!(
+            get.uri(),
+            "https://dnsserver.example.net/dns-query?dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB"
+        );
+        assert_eq!(get.headers()[header::ACCEPT], DNS_MESSAGE);
+
+        let url = parse_url("https://dnsserver.example.net/query?ct").unwrap();
+        let get = doh_request(&body, &url, Method::Get).unwrap();
+        assert!(get.uri().query().unwrap().starts_with("ct&dns="));
+
+        let post = doh_request(&body, &url, Method::Post).unwrap();
+        assert_eq!(post.method(), http::Method::POST);
+        assert_eq!(post.uri(), &url);
+        assert_eq!(post.headers()[header::CONTENT_TYPE], DNS_MESSAGE);
+        assert_eq!(post.headers()[header::CONTENT_LENGTH], "33");
+    }
+
+    #[test]
+    fn test_is_dns_message() {
+        let is_dns_message = |value| is_dns_message(&HeaderValue::from_static(value));
+        assert!(is_dns_message("application/dns-message"));
+        assert!(is_dns_message("Application/DNS-Message"));
+        assert!(is_dns_message("application/dns-message; charset=utf-8"));
+        assert!(!is_dns_message("application/dns-json"));
+        assert!(!is_dns_message(
+            "text/html; charset=application/dns-message"
+        ));
+    }
+
+    /// What the DoH server received.
+    struct HttpsRequest {
+        method: String,
+        path: String,
+        content_type: Option<String>,
+        body: Vec<u8>,
+    }
+
+    /// Accepts one HTTP/2 request over TLS, and replies with `resp` and the given status.
+    fn serve_one_https(
+        listener: TcpListener,
+        config: Arc<ServerConfig>,
+        status: u16,
+        resp: Vec<u8>,
+    ) -> thread::JoinHandle<HttpsRequest> {
+        let mut config = (*config).clone();
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                listener.set_nonblocking(true).unwrap();
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = acceptor.accept(stream).await.unwrap();
+                let mut conn = h2::server::handshake(stream).await.unwrap();
+                let (request, mut respond) = conn.accept().await.unwrap().unwrap();
+                let (parts, mut body) = request.into_parts();
+                let mut request = HttpsRequest {
+                    method: parts.method.to_string(),
+                    path: parts.uri.path_and_query().unwrap().to_string(),
+                    content_type: parts
+                        .headers
+                        .get("content-type")
+                        .map(|v| v.to_str().unwrap().to_owned()),
+                    body: Vec::new(),
+                };
+                while let Some(chunk) = body.data().await {
+                    request.body.extend_from_slice(&chunk.unwrap());
+                }
+                let response = http::Response::builder()
+                    .status(status)
+                    .header("content-type", "application/dns-message; charset=utf-8")
+                    .body(())
+                    .unwrap();
+                let mut send = respond.send_response(response, false).unwrap();
+                send.send_data(resp.into(), true).unwrap();
+                // Keep the connection going until the client has read the response.
+                while conn.accept().await.is_some() {}
+                request
+            })
+        })
+    }
+
+    /// The query the DoH server should get: the same as `query()`, but with ID 0.
+    fn query_with_id_0() -> Vec<u8> {
+        let mut query = query();
+        query.header.id = 0;
+        query.serialize_bytes().unwrap()
+    }
+
+    /// Sends the query to a DoH server, using the test CA's certificate.
+    /// The server replies with ID 0, like real ones do.
+    fn query_https(method: Method, status: u16) -> (AResult<Vec<u8>>, HttpsRequest) {
+        let ca = test_ca();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut resp = response_bytes();
+        resp[..2].copy_from_slice(&[0, 0]);
+        let server = serve_one_https(listener, ca.server_config.clone(), status, resp);
+        let url = parse_url("https://doh.test/dns-query").unwrap();
+        let tls = TlsConfig::new("doh.test", Some(ca.ca_pem.as_bytes()), Vec::new()).unwrap();
+        let transport = Transport::Https(HttpsConfig::new(url, method, tls));
+        let result = send_req(query(), addr, &transport, DEFAULT_TIMEOUT, None);
+        let request = server.join().unwrap();
+        (result.map(|(resp, len)| resp[..len].to_vec()), request)
+    }
+
+    #[test]
+    fn test_https_post() {
+        // The caller gets the response with its own ID back.
+        let (resp, request) = query_https(Method::Post, 200);
+        assert_eq!(resp.unwrap(), response_bytes());
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/dns-query");
+        assert_eq!(
+            request.content_type.as_deref(),
+            Some("application/dns-message")
+        );
+        assert_eq!(request.body, query_with_id_0());
+    }
+
+    #[test]
+    fn test_https_get() {
+        let (resp, request) = query_https(Method::Get, 200);
+        assert_eq!(resp.unwrap(), response_bytes());
+        assert_eq!(request.method, "GET");
+        let dns = request.path.strip_prefix("/dns-query?dns=").unwrap();
+        assert_eq!(
+            data_encoding::BASE64URL_NOPAD
+                .decode(dns.as_bytes())
+                .unwrap(),
+            query_with_id_0()
+        );
+        assert!(request.body.is_empty());
+
+        let (resp, _) = query_https(Method::Get, 415);
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            "DoH server returned HTTP status 415 Unsupported Media Type"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! DNS-over-QUIC, from <https://datatracker.ietf.org/doc/html/rfc9250>: each query gets its own
+//! stream in a QUIC connection, so it's encrypted like DoT, but one slow response doesn't hold
+//! up the others.
+use super::{block_on, debug, frame, tls::TlsConfig, transport_error, Log};
+use anyhow::{anyhow, Result as AResult};
+use quinn::{crypto::rustls::QuicClientConfig, ClientConfig, ConnectionError, Endpoint, VarInt};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+/// The UDP port resolvers listen for DNS-over-QUIC on. It's the same number as DoT's TCP port.
+pub const DOQ_PORT: u16 = 853;
+
+/// Tells the server this QUIC connection is for DNS, see
+/// <https://datatracker.ietf.org/doc/html/rfc9250#section-4.1.1>
+pub const DOQ_ALPN: &[u8] = b"doq";
+
+/// The application error code for closing a connection when everything went fine.
+const DOQ_NO_ERROR: VarInt = VarInt::from_u32(0);
+
+/// How to start a QUIC connection with a resolver, and check it's the right one.
+#[derive(Debug, Clone)]
+pub struct QuicConfig {
+    pub(crate) tls: TlsConfig,
+}
+
+impl QuicConfig {
+    /// QUIC always uses TLS 1.3, with the same certificate checks as DoT.
+    pub fn new(tls: TlsConfig) -> Self {
+        Self {
+            tls: tls.with_alpn(DOQ_ALPN),
+        }
+    }
+}
+
+/// Sends the serialized DNS message on a new QUIC connection to the resolver.
+/// The message's ID should already be 0.
+pub(crate) fn send_quic(
+    body: &[u8],
+    resolver: SocketAddr,
+    quic: &QuicConfig,
+    timeout: Duration,
+    log: Option<Log>,
+) -> AResult<(Vec<u8>, usize)> {
+    let response = block_on("DoQ server", timeout, exchange(body, resolver, quic, log))?;
+    let len = response.len();
+    Ok((response, len))
+}
+
+async fn exchange(
+    body: &[u8],
+    resolver: SocketAddr,
+    quic: &QuicConfig,
+    log: Option<Log>,
+) -> AResult<Vec<u8>> {
+    let crypto = QuicClientConfig::try_from(quic.tls.config.clone())?;
+    let local_addr = if resolver.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let mut endpoint = Endpoint::client(local_addr.parse()?)?;
+    endpoint.set_default_client_config(ClientConfig::new(Arc::new(crypto)));
+    let connection = endpoint
+        .connect(resolver, &quic.tls.server_name.to_str())?
+        .await
+        .map_err(|e| {
+            let message = format!("couldn't connect to the DoQ server: {e}");
+            // Other connection errors are the handshake failing, e.g. on the certificate.
+            match e {
+                ConnectionError::TimedOut | ConnectionError::Reset => transport_error(message),
+                _ => anyhow!(message),
+            }
+        })?;
+    debug(log, || format!("Connected to remote {resolver} over QUIC"));
+
+    // The query uses the same length prefix as TCP. Finishing the stream tells the server
+    // there won't be any more queries on it.
+    let (mut send, mut recv) = connection
+        .open_bi()
+        .await
+        .map_err(|e| transport_error(format!("couldn't open a QUIC stream: {e}")))?;
+    send.write_all(&frame(body)?)
+        .await
+        .map_err(|e| transport_error(format!("couldn't send the query: {e}")))?;
+    send.finish()?;
+    let mut len_buf = [0; 2];
+    recv.read_exact(&mut len_buf)
+        .await
+        .map_err(|e| transport_error(format!("couldn't read QUIC response length: {e}")))?;
+    let len = u16::from_be_bytes(len_buf) as usize;
+    let mut response = vec![0; len];
+    recv.read_exact(&mut response)
+        .await
+        .map_err(|e| transport_error(format!("couldn't read {len} byte QUIC response: {e}")))?;
+    // Let the server know it can forget about this connection, instead of waiting for it to
+    // time out.
+    connection.close(DOQ_NO_ERROR, b"");
+    endpoint.wait_idle().await;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::{
+            send_req,
+            test_utils::{query, response_bytes, test_ca},
+            Transport, DEFAULT_TIMEOUT,
+        },
+        message::MAX_UDP_BYTES,
+    };
+    use rustls::ServerConfig;
+    use std::{net::UdpSocket, thread};
+
+    /// Accepts one QUIC connection, reads a query from one stream, and replies with `resp`.
+    /// Returns the query, and the ALPN protocol the client asked for.
+    fn serve_one_quic(
+        socket: UdpSocket,
+        config: Arc<ServerConfig>,
+        resp: Vec<u8>,
+    ) -> thread::JoinHandle<(Vec<u8>, Option<Vec<u8>>)> {
+        let mut config = (*config).clone();
+        config.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+        let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(config).unwrap();
+        let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let endpoint = quinn::Endpoint::new(
+                    quinn::EndpointConfig::default(),
+                    Some(config),
+                    socket,
+                    Arc::new(quinn::TokioRuntime),
+                )
+                .unwrap();
+                let connection = endpoint.accept().await.unwrap().await.unwrap();
+                let alpn = connection
+                    .handshake_data()
+                    .unwrap()
+                    .downcast::<quinn::crypto::rustls::HandshakeData>()
+                    .unwrap()
+                    .protocol;
+                let (mut send, mut recv) = connection.accept_bi().await.unwrap();
+                // The client finishes the stream after its query, so this doesn't wait forever.
+                let query = recv.read_to_end(MAX_UDP_BYTES).await.unwrap();
+                send.write_all(&frame(&resp).unwrap()).await.unwrap();
+                send.finish().unwrap();
+                connection.closed().await;
+                (query[2..].to_vec(), alpn)
+            })
+        })
+    }
+
+    /// Sends the query to a DoQ server, using the test CA's certificate.
+    fn query_quic(resp: Vec<u8>) -> (AResult<Vec<u8>>, Vec<u8>) {
+        let ca = test_ca();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server = serve_one_quic(socket, ca.server_config.clone(), resp);
+        let tls = TlsConfig::new("doq.test", Some(ca.ca_pem.as_bytes()), Vec::new()).unwrap();
+        let transport = Transport::Quic(QuicConfig::new(tls));
+        let result = send_req(query(), addr, &transport, DEFAULT_TIMEOUT, None);
+        let (received_query, alpn) = server.join().unwrap();
+        assert_eq!(alpn.as_deref(), Some(DOQ_ALPN));
+        (
+            result.map(|(resp, len)| resp[..len].to_vec()),
+            received_query,
+        )
+    }
+
+    #[test]
+    fn test_quic() {
+        // The query is sent with ID 0, but the caller gets the response with its own ID back.
+        let mut resp = response_bytes();
+        resp[..2].copy_from_slice(&[0, 0]);
+        let (resp, received_query) = query_quic(resp);
+        assert_eq!(resp.unwrap(), response_bytes());
+        let mut expected = query();
+        expected.header.id = 0;
+        assert_eq!(received_query, expected.serialize_bytes().unwrap());
+
+        let (resp, _) = query_quic(response_bytes());
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            "DoQ server replied with message ID 33, not 0"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! A stub resolver and certificates, shared by the tests of each transport.
+use crate::{message::Message, RecordType};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair, PublicKeyData};
+use ring::digest;
+use rustls::{
+    pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+    ServerConfig,
+};
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+/// A response to a query for blog.adamchalmers.com, with two A records.
+pub fn response_bytes() -> Vec<u8> {
+    vec![
+        0, 33, 129, 128, 0, 1, 0, 2, 0, 0, 0, 0, // Header
+        4, 98, 108, 111, 103, 12, 97, 100, 97, 109, 99, 104, 97, 108, 109, 101, 114, 115, 3, 99,
+        111, 109, 0, 0, 1, 0, 1, // Question
+        192, 12, 0, 1, 0, 1, 0, 0, 0, 179, 0, 4, 104, 19, 237, 120, // Answer #1
+        192, 12, 0, 1, 0, 1, 0, 0, 0, 179, 0, 4, 104, 19, 238, 120, // Answer #2
+    ]
+}
+
+/// The query `response_bytes` is the response to.
+pub fn query() -> Message {
+    Message::new_query(33, "blog.adamchalmers.com.".to_owned(), RecordType::A, None).unwrap()
+}
+
+/// Reads a length-prefixed query from the stream, and replies with `resp`, like a resolver
+/// over TCP (or TLS, or a QUIC stream) would. Returns the query.
+pub fn respond(stream: &mut (impl Read + Write), resp: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut len_buf = [0; 2];
+    stream.read_exact(&mut len_buf)?;
+    let mut query = vec![0; u16::from_be_bytes(len_buf) as usize];
+    stream.read_exact(&mut query)?;
+    stream.write_all(&(resp.len() as u16).to_be_bytes())?;
+    stream.write_all(resp)?;
+    stream.flush()?;
+    Ok(query)
+}
+
+/// A CA, and a certificate it issued for a DoT server called dot.test, which is also a DoH
+/// server called doh.test and a DoQ server called doq.test.
+pub struct TestCa {
+    pub ca_pem: String,
+    pub server_config: Arc<ServerConfig>,
+    /// The SHA-256 digest of the server's public key, for pinning.
+    pub server_spki: [u8; 32],
+}
+
+pub fn test_ca() -> TestCa {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+    let issuer = Issuer::new(ca_params, ca_key);
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec![
+        "dot.test".to_owned(),
+        "doh.test".to_owned(),
+        "doq.test".to_owned(),
+    ])
+    .unwrap()
+    .signed_by(&key, &issuer)
+    .unwrap();
+    let server_config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            )
+            .unwrap();
+    let spki = digest::digest(&digest::SHA256, &key.subject_public_key_info());
+    TestCa {
+        ca_pem: ca_cert.pem(),
+        server_config: Arc::new(server_config),
+        server_spki: spki.as_ref().try_into().unwrap(),
+    }
+}
@@ -0,0 +1,257 @@
+//! DNS-over-TLS, from <https://datatracker.ietf.org/doc/html/rfc7858>: the same length-prefixed
+//! messages as TCP, but inside a TLS session, so nobody in between can read or change them.
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result as AResult};
+use data_encoding::BASE64;
+use ring::digest;
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::ring::default_provider,
+    pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+/// The port resolvers listen for DNS-over-TLS on.
+pub const DOT_PORT: u16 = 853;
+
+/// How to start a TLS session with a resolver, and check it's the right one.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// The name to send with SNI. The resolver's certificate must be valid for it.
+    pub server_name: ServerName<'static>,
+    pub(crate) config: Arc<ClientConfig>,
+}
+
+impl TlsConfig {
+    /// Trusts the CA certificates in `ca_pem`, or if that's None, the Mozilla root store.
+    /// If there are any `spki_pins`, the certificate chain must also include one of those
+    /// public keys. Pins are SHA-256 digests of the key's SubjectPublicKeyInfo, see
+    /// <https://datatracker.ietf.org/doc/html/rfc7858#section-4.2>
+    pub fn new(
+        server_name: &str,
+        ca_pem: Option<&[u8]>,
+        spki_pins: Vec<[u8; 32]>,
+    ) -> AResult<Self> {
+        let server_name = ServerName::try_from(server_name.to_owned())
+            .map_err(|e| anyhow!("{server_name} isn't a valid TLS server name: {e}"))?;
+        let mut roots = RootCertStore::empty();
+        match ca_pem {
+            Some(pem) => {
+                for cert in CertificateDer::pem_slice_iter(pem) {
+                    let cert = cert.map_err(|e| anyhow!("Couldn't read the CA bundle: {e}"))?;
+                    roots
+                        .add(cert)
+                        .map_err(|e| anyhow!("Invalid certificate in the CA bundle: {e}"))?;
+                }
+                if roots.is_empty() {
+                    anyhow::bail!("The CA bundle doesn't have any certificates in it");
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        let provider = Arc::new(default_provider());
+        let verifier =
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()?;
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+                verifier,
+                spki_pins,
+            }))
+            .with_no_client_auth();
+        Ok(Self {
+            server_name,
+            config: Arc::new(config),
+        })
+    }
+
+    /// Offers the given protocol during the handshake, using ALPN. DoT doesn't need this, but
+    /// protocols built on top of TLS like HTTP/2 do.
+    pub fn with_alpn(mut self, protocol: &[u8]) -> Self {
+        Arc::make_mut(&mut self.config).alpn_protocols = vec![protocol.to_vec()];
+        self
+    }
+}
+
+/// Parses an SPKI pin, written in base64 like `dig +tls-spki` and Unbound's config take them.
+pub fn parse_spki_pin(pin: &str) -> Result<[u8; 32], String> {
+    let bytes = BASE64
+        .decode(pin.as_bytes())
+        .map_err(|e| format!("SPKI pin {pin} isn't valid base64: {e}"))?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        format!(
+            "SPKI pin {pin} is {} bytes, but SHA-256 digests are 32",
+            bytes.len()
+        )
+    })
+}
+
+/// Checks certificates with the usual WebPKI rules, then checks the chain has a pinned key.
+#[derive(Debug)]
+struct PinnedVerifier {
+    verifier: Arc<WebPkiServerVerifier>,
+    spki_pins: Vec<[u8; 32]>,
+}
+
+impl PinnedVerifier {
+    fn is_pinned(&self, cert: &CertificateDer<'_>) -> bool {
+        let Ok(cert) = webpki::EndEntityCert::try_from(cert) else {
+            return false;
+        };
+        let spki = cert.subject_public_key_info();
+        let digest = digest::digest(&digest::SHA256, spki.as_ref());
+        self.spki_pins.iter().any(|pin| pin == digest.as_ref())
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let mut chain = std::iter::once(end_entity).chain(intermediates);
+        if !self.spki_pins.is_empty() && !chain.any(|cert| self.is_pinned(cert)) {
+            return Err(rustls::Error::General(
+                "none of the certificates have a pinned public key".to_owned(),
+            ));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{
+        send_req,
+        test_utils::{query, respond, response_bytes, test_ca, TestCa},
+        Transport, DEFAULT_TIMEOUT,
+    };
+    use anyhow::Result as AResult;
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn test_parse_spki_pin() {
+        let pin = "MnLdGiqUGYhtRRGkJtaRsKl8zCXCZSpfUDT4jGpjRHo=";
+        assert_eq!(parse_spki_pin(pin).unwrap()[..2], [0x32, 0x72]);
+        assert!(parse_spki_pin("not base64").is_err());
+        assert_eq!(
+            parse_spki_pin("AAAA").unwrap_err(),
+            "SPKI pin AAAA is 3 bytes, but SHA-256 digests are 32"
+        );
+    }
+
+    /// Accepts one TLS connection, reads a length-prefixed query, and replies with `resp`.
+    /// Returns the query, and the server name the client sent with SNI.
+    fn serve_one_tls(
+        listener: TcpListener,
+        config: Arc<ServerConfig>,
+        resp: Vec<u8>,
+    ) -> thread::JoinHandle<std::io::Result<(Vec<u8>, Option<String>)>> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            let conn = ServerConnection::new(config).unwrap();
+            let mut stream = StreamOwned::new(conn, stream);
+            let query = respond(&mut stream, &resp)?;
+            let server_name = stream.conn.server_name().map(str::to_owned);
+            Ok((query, server_name))
+        })
+    }
+
+    /// Sends the query to a TLS server using the test CA's certificate.
+    fn query_tls(ca: &TestCa, client_config: TlsConfig) -> AResult<Vec<u8>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = serve_one_tls(listener, ca.server_config.clone(), response_bytes());
+        let result = send_req(
+            query(),
+            addr,
+            &Transport::Tls(client_config),
+            DEFAULT_TIMEOUT,
+            None,
+        );
+        if let Ok((received_query, server_name)) = server.join().unwrap() {
+            assert_eq!(received_query, query().serialize_bytes().unwrap());
+            assert_eq!(server_name.as_deref(), Some("dot.test"));
+        }
+        result.map(|(resp, len)| resp[..len].to_vec())
+    }
+
+    #[test]
+    fn test_tls() {
+        let ca = test_ca();
+        let config = TlsConfig::new("dot.test", Some(ca.ca_pem.as_bytes()), Vec::new()).unwrap();
+        assert_eq!(query_tls(&ca, config).unwrap(), response_bytes());
+
+        // The certificate has to be for the server name.
+        let config = TlsConfig::new("other.test", Some(ca.ca_pem.as_bytes()), Vec::new()).unwrap();
+        let err = query_tls(&ca, config).unwrap_err();
+        assert!(
+            format!("{err}").contains("certificate not valid for name"),
+            "{err}"
+        );
+
+        // And from a trusted CA.
+        let other_ca = test_ca();
+        let config =
+            TlsConfig::new("dot.test", Some(other_ca.ca_pem.as_bytes()), Vec::new()).unwrap();
+        let err = query_tls(&ca, config).unwrap_err();
+        assert!(
+            format!("{err}").contains("invalid peer certificate"),
+            "{err}"
+        );
+        assert!(TlsConfig::new("dot.test", Some(b"not a certificate"), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_tls_spki_pins() {
+        let ca = test_ca();
+        let pins = vec![[0; 32], ca.server_spki];
+        let config = TlsConfig::new("dot.test", Some(ca.ca_pem.as_bytes()), pins).unwrap();
+        assert_eq!(query_tls(&ca, config).unwrap(), response_bytes());
+
+        let config = TlsConfig::new("dot.test", Some(ca.ca_pem.as_bytes()), vec![[0; 32]]).unwrap();
+        let err = query_tls(&ca, config).unwrap_err();
+        assert!(format!("{err}").contains("pinned public key"), "{err}");
+    }
+}
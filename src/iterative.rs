@@ -0,0 +1,561 @@
+//! Resolving names iteratively, starting from the root servers and following referrals,
+//! the way a recursive resolver does. See <https://datatracker.ietf.org/doc/html/rfc1034#section-5.3.3>
+use crate::{
+    io::{send_req, Log, Transport, DEFAULT_TIMEOUT},
+    message::{
+        header::ResponseCode,
+        name::{is_subdomain, same_name},
+        record::RecordData,
+        Message,
+    },
+    RecordType,
+};
+use anyhow::{anyhow, Result as AResult};
+use rand::Rng;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+/// Where to find the root servers, from <https://www.internic.net/domain/named.root>.
+/// Only the IPv4 addresses are used, because not every network has IPv6.
+pub const ROOT_HINTS: [(&str, Ipv4Addr); 13] = [
+    ("a.root-servers.net.", Ipv4Addr::new(198, 41, 0, 4)),
+    ("b.root-servers.net.", Ipv4Addr::new(170, 247, 170, 2)),
+    ("c.root-servers.net.", Ipv4Addr::new(192, 33, 4, 12)),
+    ("d.root-servers.net.", Ipv4Addr::new(199, 7, 91, 13)),
+    ("e.root-servers.net.", Ipv4Addr::new(192, 203, 230, 10)),
+    ("f.root-servers.net.", Ipv4Addr::new(192, 5, 5, 241)),
+    ("g.root-servers.net.", Ipv4Addr::new(192, 112, 36, 4)),
+    ("h.root-servers.net.", Ipv4Addr::new(198, 97, 190, 53)),
+    ("i.root-servers.net.", Ipv4Addr::new(192, 36, 148, 17)),
+    ("j.root-servers.net.", Ipv4Addr::new(192, 58, 128, 30)),
+    ("k.root-servers.net.", Ipv4Addr::new(193, 0, 14, 129)),
+    ("l.root-servers.net.", Ipv4Addr::new(199, 7, 83, 42)),
+    ("m.root-servers.net.", Ipv4Addr::new(202, 12, 27, 33)),
+];
+
+/// A resolution normally takes a handful of queries. This stops misconfigured (or malicious)
+/// zones sending the resolver round in circles forever.
+const MAX_QUERIES: usize = 64;
+
+/// Following a referral can mean looking up a name server's address, which can mean
+/// following more referrals, and so on. Stop if that goes too deep.
+const MAX_GLUE_DEPTH: usize = 4;
+
+/// A name server, and the address it's being queried at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameServer {
+    pub name: String,
+    pub addr: IpAddr,
+}
+
+impl fmt::Display for NameServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.addr)
+    }
+}
+
+/// One step of an iterative resolution, for showing users how the answer was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceStep {
+    /// A server for `zone` said to ask the servers for a closer zone instead.
+    Referral {
+        zone: String,
+        server: NameServer,
+        to_zone: String,
+        name_servers: Vec<String>,
+    },
+    /// A server for `zone` gave the final response.
+    Answer { zone: String, server: NameServer },
+    /// None of the name servers in a referral had glue records, so one of their addresses
+    /// had to be looked up separately.
+    LookupNameServer { name: String },
+    /// Querying a server failed, so the next one was tried.
+    Failed { server: NameServer, error: String },
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Referral {
+                zone,
+                server,
+                to_zone,
+                name_servers,
+            } => {
+                write!(f, "{zone} -> {to_zone} via {server}")?;
+                for ns in name_servers {
+                    write!(f, "\n    {to_zone} NS {ns}")?;
+                }
+                Ok(())
+            }
+            Self::Answer { zone, server } => write!(f, "{zone} answered by {server}"),
+            Self::LookupNameServer { name } => write!(f, "Looking up the address of {name}"),
+            Self::Failed { server, error } => write!(f, "{server} failed: {error}"),
+        }
+    }
+}
+
+/// The result of an iterative resolution.
+#[derive(Debug)]
+pub struct Resolution {
+    /// The response from the last server queried, which was authoritative for the name.
+    pub response: Message,
+    /// Every step taken to get the response.
+    pub trace: Vec<TraceStep>,
+}
+
+/// Resolves names by itself, instead of asking a recursive resolver to.
+#[derive(Debug, Clone)]
+pub struct IterativeResolver {
+    /// The servers to start from.
+    pub roots: Vec<NameServer>,
+    /// Which port to send queries to. Real name servers all use 53.
+    pub port: u16,
+    pub transport: Transport,
+    /// How long to wait for each server to respond, before trying the next one.
+    pub timeout: Duration,
+    /// Where to send debugging information about each query, if anywhere.
+    pub log: Option<Log>,
+}
+
+impl Default for IterativeResolver {
+    /// Starts from the real root servers.
+    fn default() -> Self {
+        Self {
+            roots: ROOT_HINTS
+                .iter()
+                .map(|(name, ip)| NameServer {
+                    name: name.to_string(),
+                    addr: IpAddr::V4(*ip),
+                })
+                .collect(),
+            port: 53,
+            transport: Transport::Udp,
+            timeout: DEFAULT_TIMEOUT,
+            log: None,
+        }
+    }
+}
+
+impl IterativeResolver {
+    /// Finds the records of the given type and name, by following referrals from the roots
+    /// down to a server which is authoritative for the name.
+    pub fn resolve(&self, name: &str, record_type: RecordType) -> AResult<Resolution> {
+        let mut lookup = Lookup {
+            resolver: self,
+            queries: 0,
+            trace: Vec::new(),
+        };
+        let response = lookup.resolve(name, record_type, 0)?;
+        Ok(Resolution {
+            response,
+            trace: lookup.trace,
+        })
+    }
+}
+
+/// The state of one call to [`IterativeResolver::resolve`].
+struct Lookup<'a> {
+    resolver: &'a IterativeResolver,
+    queries: usize,
+    trace: Vec<TraceStep>,
+}
+
+impl Lookup<'_> {
+    fn resolve(&mut self, name: &str, record_type: RecordType, depth: usize) -> AResult<Message> {
+        let mut zone = ".".to_owned();
+        let mut servers = self.resolver.roots.clone();
+        loop {
+            let (server, response) = self.query_any(&servers, name, record_type)?;
+
+            // Anything other than a referral is the final response: an answer, NXDOMAIN,
+            // or no data.
+            let is_referral = response.header.resp_code == ResponseCode::NoError
+                && response.answer.is_empty()
+                && !response.header.authoritative_answer;
+            let referral = response
+                .authority
+                .iter()
+                .find_map(|record| match record.data {
+                    RecordData::Ns(_) => Some(record.name.clone()),
+                    _ => None,
+                });
+            let to_zone = match referral {
+                Some(to_zone) if is_referral => to_zone,
+                _ => {
+                    self.trace.push(TraceStep::Answer { zone, server });
+                    return Ok(response);
+                }
+            };
+            // Each referral must get closer to the name, or the resolver could go in circles.
+            if !is_subdomain(name, &to_zone) || is_subdomain(&zone, &to_zone) {
+                anyhow::bail!("{server} gave a referral to {to_zone}, which isn't closer to {name} than {zone}");
+            }
+
+            let name_servers: Vec<String> = response
+                .authority
+                .iter()
+                .filter(|record| is_subdomain(&record.name, &to_zone))
+                .filter_map(|record| match &record.data {
+                    RecordData::Ns(ns) => Some(ns.clone()),
+                    _ => None,
+                })
+                .collect();
+            servers = glue(&response, &name_servers, &zone);
+            self.trace.push(TraceStep::Referral {
+                zone,
+                server,
+                to_zone: to_zone.clone(),
+                name_servers: name_servers.clone(),
+            });
+            if servers.is_empty() {
+                servers = self.look_up_name_servers(&name_servers, depth)?;
+            }
+            zone = to_zone;
+        }
+    }
+
+    /// Tries each server in turn, until one responds.
+    fn query_any(
+        &mut self,
+        servers: &[NameServer],
+        name: &str,
+        record_type: RecordType,
+    ) -> AResult<(NameServer, Message)> {
+        for server in servers {
+            match self.query(server, name, record_type) {
+                Ok(response) => return Ok((server.clone(), response)),
+                Err(e) => self.trace.push(TraceStep::Failed {
+                    server: server.clone(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+        Err(anyhow!("None of the name servers responded about {name}"))
+    }
+
+    fn query(
+        &mut self,
+        server: &NameServer,
+        name: &str,
+        record_type: RecordType,
+    ) -> AResult<Message> {
+        self.queries += 1;
+        if self.queries > MAX_QUERIES {
+            anyhow::bail!("Gave up after {MAX_QUERIES} queries");
+        }
+        let query_id = rand::thread_rng().gen();
+        let mut msg = Message::new_query(query_id, name.to_owned(), record_type, None)?;
+        // Name servers should answer from their own data, not go off and ask other servers.
+        msg.header.recursion_desired = false;
+        let addr = SocketAddr::new(server.addr, self.resolver.port);
+        let resolver = &self.resolver;
+        let (resp, len) = send_req(
+            msg,
+            addr,
+            &resolver.transport,
+            resolver.timeout,
+            resolver.log,
+        )?;
+        let response = Message::deserialize(resp[..len].to_vec())?;
+        if response.header.id != query_id {
+            anyhow::bail!(
+                "Mismatch between query IDs. Sent {query_id} and received {}",
+                response.header.id
+            );
+        }
+        Ok(response)
+    }
+
+    /// Finds the address of one of the name servers, when the referral didn't include glue.
+    fn look_up_name_servers(
+        &mut self,
+        name_servers: &[String],
+        depth: usize,
+    ) -> AResult<Vec<NameServer>> {
+        if depth >= MAX_GLUE_DEPTH {
+            anyhow::bail!("Too many nested lookups of name server addresses");
+        }
+        for name in name_servers {
+            self.trace
+                .push(TraceStep::LookupNameServer { name: name.clone() });
+            let Ok(response) = self.resolve(name, RecordType::A, depth + 1) else {
+                continue;
+            };
+            let servers = glue(&response, std::slice::from_ref(name), name);
+            if !servers.is_empty() {
+                return Ok(servers);
+            }
+        }
+        Err(anyhow!(
+            "Couldn't find the address of any name server in {name_servers:?}"
+        ))
+    }
+}
+
+/// Gets the addresses of the given name servers from the records in a response.
+/// Only addresses for names in `zone`, the zone of the server that sent the response, are
+/// used: a server can't vouch for names outside its own zone, so it could be poisoning the
+/// resolver with them. Names elsewhere in its zone are fine though, e.g. the com. servers can
+/// give the address of ns.bar.com. along with a referral to foo.com.
+/// IPv4 addresses come first, because not every network has IPv6.
+fn glue(response: &Message, name_servers: &[String], zone: &str) -> Vec<NameServer> {
+    let mut servers: Vec<NameServer> = response
+        .answer
+        .iter()
+        .chain(&response.additional)
+        .filter(|record| is_subdomain(&record.name, zone))
+        .filter_map(|record| match record.data {
+            RecordData::A(ip) => Some(NameServer {
+                name: record.name.clone(),
+                addr: IpAddr::V4(ip),
+            }),
+            RecordData::Aaaa(ip) => Some(NameServer {
+                name: record.name.clone(),
+                addr: IpAddr::V6(ip),
+            }),
+            _ => None,
+        })
+        .filter(|ns| name_servers.iter().any(|name| same_name(name, &ns.name)))
+        .collect();
+    servers.sort_by_key(|ns| ns.addr.is_ipv6());
+    servers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{header::Header, MAX_UDP_BYTES},
+        test_utils::record,
+        Entry, Record,
+    };
+    use std::{net::UdpSocket, thread, time::Duration};
+
+    fn ns(zone: &str, name: &str) -> Record {
+        record(zone, RecordData::Ns(name.to_owned()))
+    }
+
+    fn a(name: &str, ip: [u8; 4]) -> Record {
+        record(name, RecordData::A(ip.into()))
+    }
+
+    /// The records a stub server sends back: answer, authority, additional.
+    type Sections = (Vec<Record>, Vec<Record>, Vec<Record>);
+
+    /// Runs a stub name server on the given address, which responds using `respond`.
+    /// A response with answers is marked authoritative.
+    fn stub_server(
+        addr: SocketAddr,
+        respond: impl Fn(&Entry) -> Sections + Send + 'static,
+    ) -> thread::JoinHandle<()> {
+        let socket = UdpSocket::bind(addr).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; MAX_UDP_BYTES];
+            while let Ok((len, client)) = socket.recv_from(&mut buf) {
+                let query = Message::deserialize(buf[..len].to_vec()).unwrap();
+                assert!(!query.header.recursion_desired);
+                let (answer, authority, additional) = respond(&query.question[0]);
+                let mut header: Header = query.header.clone();
+                header.is_response = true;
+                header.authoritative_answer = !answer.is_empty();
+                let response = Message {
+                    header,
+                    question: query.question,
+                    answer,
+                    authority,
+                    additional,
+                    edns: None,
+                };
+                socket
+                    .send_to(&response.serialize_bytes().unwrap(), client)
+                    .unwrap();
+            }
+        })
+    }
+
+    // Stub servers all share one port, because the resolver uses the same port for every name
+    // server, so they need different loopback addresses. Only Linux has all of 127.0.0.0/8
+    // without configuring it.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_follows_referrals_and_looks_up_missing_glue() {
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr = |last: u8| SocketAddr::from(([127, 0, 0, last], port));
+
+        // The root delegates com. and net. to the TLD server, with glue.
+        let root = stub_server(addr(1), |q| {
+            let tld = if q.name.ends_with("net.") {
+                "net."
+            } else {
+                "com."
+            };
+            let tld_server = format!("ns.{tld}");
+            (
+                vec![],
+                vec![ns(tld, &tld_server)],
+                vec![a(&tld_server, [127, 0, 0, 2])],
+            )
+        });
+        // The TLD server delegates example.com. to a name server in net., and knows the
+        // address of that name server itself. The glue for it is outside example.com., so
+        // it has to be ignored, and the address looked up properly.
+        let tld = stub_server(addr(2), |q| {
+            if q.name == "ns1.example.net." {
+                (vec![a("ns1.example.net.", [127, 0, 0, 3])], vec![], vec![])
+            } else {
+                (
+                    vec![],
+                    vec![ns("example.com.", "ns1.example.net.")],
+                    vec![a("ns1.example.net.", [127, 0, 0, 99])],
+                )
+            }
+        });
+        let example = stub_server(addr(3), |_| {
+            (vec![a("www.example.com.", [192, 0, 2, 1])], vec![], vec![])
+        });
+
+        let resolver = IterativeResolver {
+            roots: vec![NameServer {
+                name: "root.".to_owned(),
+                addr: addr(1).ip(),
+            }],
+            port,
+            transport: Transport::Udp,
+            timeout: DEFAULT_TIMEOUT,
+            log: None,
+        };
+        let resolution = resolver.resolve("www.example.com.", RecordType::A).unwrap();
+        assert_eq!(
+            resolution.response.answer,
+            vec![a("www.example.com.", [192, 0, 2, 1])]
+        );
+        let server = |name: &str, last| NameServer {
+            name: name.to_owned(),
+            addr: addr(last).ip(),
+        };
+        assert_eq!(
+            resolution.trace,
+            vec![
+                TraceStep::Referral {
+                    zone: ".".to_owned(),
+                    server: server("root.", 1),
+                    to_zone: "com.".to_owned(),
+                    name_servers: vec!["ns.com.".to_owned()],
+                },
+                TraceStep::Referral {
+                    zone: "com.".to_owned(),
+                    server: server("ns.com.", 2),
+                    to_zone: "example.com.".to_owned(),
+                    name_servers: vec!["ns1.example.net.".to_owned()],
+                },
+                TraceStep::LookupNameServer {
+                    name: "ns1.example.net.".to_owned()
+                },
+                TraceStep::Referral {
+                    zone: ".".to_owned(),
+                    server: server("root.", 1),
+                    to_zone: "net.".to_owned(),
+                    name_servers: vec!["ns.net.".to_owned()],
+                },
+                TraceStep::Answer {
+                    zone: "net.".to_owned(),
+                    server: server("ns.net.", 2),
+                },
+                TraceStep::Answer {
+                    zone: "example.com.".to_owned(),
+                    server: server("ns1.example.net.", 3),
+                },
+            ]
+        );
+        for server in [root, tld, example] {
+            server.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_glue() {
+        let response = Message {
+            header: Header::new_query(1),
+            question: Vec::new(),
+            answer: Vec::new(),
+            authority: Vec::new(),
+            additional: vec![
+                record(
+                    "ns1.example.com.",
+                    RecordData::Aaaa("2001:db8::1".parse().unwrap()),
+                ),
+                a("ns1.example.com.", [192, 0, 2, 1]),
+                a("ns2.example.com.", [192, 0, 2, 2]),
+                a("ns.example.net.", [192, 0, 2, 3]),
+                a("ns.bar.com.", [192, 0, 2, 4]),
+            ],
+            edns: None,
+        };
+        let name_servers = [
+            "ns1.example.com.".to_owned(),
+            "ns.example.net.".to_owned(),
+            "ns.bar.com.".to_owned(),
+        ];
+        let addrs = |zone| -> Vec<IpAddr> {
+            glue(&response, &name_servers, zone)
+                .into_iter()
+                .map(|ns| ns.addr)
+                .collect()
+        };
+        // From the example.com. servers, only glue under example.com. counts.
+        assert_eq!(
+            addrs("example.com."),
+            vec![
+                IpAddr::from([192, 0, 2, 1]),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+        // The com. servers can give glue for a sibling zone's name server too.
+        assert_eq!(
+            addrs("com."),
+            vec![
+                IpAddr::from([192, 0, 2, 1]),
+                IpAddr::from([192, 0, 2, 4]),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_referral_must_get_closer() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        drop(socket);
+        // This server keeps referring back to the root.
+        let server = stub_server(addr, |_| {
+            (
+                vec![],
+                vec![ns(".", "root.")],
+                vec![a("root.", [127, 0, 0, 1])],
+            )
+        });
+        let resolver = IterativeResolver {
+            roots: vec![NameServer {
+                name: "root.".to_owned(),
+                addr: addr.ip(),
+            }],
+            port: addr.port(),
+            transport: Transport::Udp,
+            timeout: DEFAULT_TIMEOUT,
+            log: None,
+        };
+        assert!(resolver.resolve("example.com.", RecordType::A).is_err());
+        server.join().unwrap();
+    }
+}
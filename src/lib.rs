@@ -0,0 +1,35 @@
+//! Dingo's DNS types, wire format parser/serializer and client, for use in other programs.
+//!
+//! ```no_run
+//! use dingo::{Client, RecordType};
+//!
+//! let client = Client::new("1.1.1.1:53".parse().unwrap());
+//! let response = client.query("adamchalmers.com.", RecordType::A).unwrap();
+//! for record in response.answer {
+//!     println!("{}", record.as_dns_response());
+//! }
+//! ```
+pub mod cname;
+pub mod dns_types;
+pub mod io;
+pub mod iterative;
+pub mod message;
+mod parse;
+pub mod proxy;
+pub mod server;
+#[cfg(test)]
+mod test_utils;
+pub mod validate;
+pub mod zone;
+
+pub use dns_types::{Class, RecordType};
+pub use io::{Client, Transport};
+pub use iterative::IterativeResolver;
+pub use message::{
+    edns::Edns,
+    error::ParseError,
+    header::{Header, Opcode, ResponseCode},
+    question::Entry,
+    record::{Record, RecordData},
+    Message,
+};
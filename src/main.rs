@@ -1,28 +1,239 @@
-use crate::{
-    cli::AppArgs,
-    dns_types::{Class, RecordType},
-    message::Message,
+use crate::cli::{AppArgs, Command, OutputFormat, ProxyArgs, ServeArgs};
+use dingo::{
+    cname::{follow_cnames, Chain},
+    io::Log,
+    iterative::TraceStep,
+    proxy::Proxy,
+    server::{Server, Zone},
+    validate::{TrustAnchor, Validation, Validator},
+    zone::read_zone_file,
+    Client, IterativeResolver, Message, RecordType, Transport,
+};
+use print::ResolverResponse;
+use std::{
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use rand::Rng;
 
 mod cli;
-mod dns_types;
-mod io;
-mod message;
-mod parse;
+mod print;
 
 const VERBOSE: bool = false;
 
+/// Where the library sends debugging information about each query: stdout, if VERBOSE is set.
+fn log() -> Option<Log> {
+    if VERBOSE {
+        Some(|line| println!("{line}"))
+    } else {
+        None
+    }
+}
+
 fn main() {
+    match Command::parse().unwrap() {
+        Command::Query(args) => query(args),
+        Command::Serve(args) => {
+            if let Err(e) = serve(args) {
+                exit_with_error(e);
+            }
+        }
+        Command::Proxy(args) => {
+            if let Err(e) = proxy(args) {
+                exit_with_error(e);
+            }
+        }
+    }
+}
+
+/// Forwards queries to the upstream resolvers until dingo is killed.
+fn proxy(args: ProxyArgs) -> anyhow::Result<()> {
+    let mut proxy = Proxy::new(args.upstreams, args.transport);
+    proxy.retry.timeout = args.timeout.unwrap_or(proxy.retry.timeout);
+    proxy.retry.tries = args.tries.unwrap_or(proxy.retry.tries);
+    let mut server = Server::bind(proxy, args.listen)?;
+    server.log = Some(|line| eprintln!("{line}"));
+    println!("Proxying on {}", server.local_addr()?);
+    server.run()
+}
+
+/// Serves the zone file until dingo is killed.
+fn serve(args: ServeArgs) -> anyhow::Result<()> {
+    let zone = Zone::new(read_zone_file(&args.zone, &args.origin)?)?;
+    let origin = zone.origin.clone();
+    let mut server = Server::bind(zone, args.listen)?;
+    server.log = Some(|line| eprintln!("{line}"));
+    println!("Serving {origin} on {}", server.local_addr()?);
+    server.run()
+}
+
+/// Looks up a name, and prints the response.
+fn query(args: AppArgs) {
+    if args.resolvers.len() > 1 {
+        return compare(args);
+    }
     let AppArgs {
         name,
         record_type,
+        mut resolvers,
+        edns,
+        retry,
+        trace,
+        validate,
+        format,
+    } = args;
+    let (resolver, transport) = resolvers.remove(0);
+    let iterative = IterativeResolver {
+        // Authoritative servers don't do encrypted DNS, so --trace always uses plain DNS.
+        transport: match transport {
+            Transport::Tls(_) | Transport::Https(_) | Transport::Quic(_) => Transport::Udp,
+            ref transport => transport.clone(),
+        },
+        timeout: retry.timeout,
+        log: log(),
+        ..Default::default()
+    };
+    let client = Client {
         resolver,
-    } = AppArgs::parse().unwrap();
-    let query_id = rand::thread_rng().gen();
-    let msg = Message::new_query(query_id, name, record_type).unwrap();
-    let (resp, len) = io::send_req(msg, resolver, VERBOSE).unwrap();
-    if let Err(e) = io::print_resp(resp, len, query_id, VERBOSE) {
-        println!("Error: {e}");
+        transport,
+        edns,
+        retry,
+        log: log(),
+    };
+    // Which server sent the last response, and how long it took, for dig's footer.
+    let mut last_query = (resolver, Duration::ZERO);
+    let query = |name: &str, record_type: RecordType| -> anyhow::Result<Message> {
+        let start = Instant::now();
+        if !trace {
+            let response = client.query(name, record_type)?;
+            last_query = (resolver, start.elapsed());
+            return Ok(response);
+        }
+        let resolution = iterative.resolve(name, record_type)?;
+        last_query.1 = start.elapsed();
+        if let Some(TraceStep::Answer { server, .. }) = resolution.trace.last() {
+            last_query.0 = SocketAddr::new(server.addr, iterative.port);
+        }
+        let steps: Vec<_> = resolution.trace.iter().map(|s| s.to_string()).collect();
+        match format {
+            OutputFormat::Text => println!("{}\n", steps.join("\n")),
+            // dig output is a zone file, so anything that isn't a record has to be a comment.
+            OutputFormat::Dig => {
+                for line in steps.iter().flat_map(|step| step.lines()) {
+                    println!(";; {line}");
+                }
+                println!();
+            }
+            // Keep stdout as valid JSON.
+            OutputFormat::Json => eprintln!("{}\n", steps.join("\n")),
+        }
+        Ok(resolution.response)
+    };
+    let result = follow_cnames(&name, record_type, query).and_then(|chain| {
+        let validation = validate
+            .as_ref()
+            .map(|anchor| validate_chain(&client, anchor.clone(), &chain));
+        match format {
+            OutputFormat::Text => {
+                if chain.names.len() > 1 {
+                    println!("CNAME chain: {}", chain.as_arrows());
+                }
+                print::print_resp(chain.response)?
+            }
+            OutputFormat::Json => print::print_json(&chain.response, validation.as_ref())?,
+            OutputFormat::Dig => {
+                let (server, query_time) = last_query;
+                println!("{}", print::dig_output(&chain.response, server, query_time));
+            }
+        }
+        if let Some(validation) = validation {
+            match format {
+                OutputFormat::Text => println!("DNSSEC: {validation}"),
+                OutputFormat::Dig => println!(";; DNSSEC: {validation}"),
+                // It's already in the JSON.
+                OutputFormat::Json => {}
+            }
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        exit_with_error(e);
     }
 }
+
+/// Asks every resolver the same question at once, and prints their responses side by side.
+fn compare(args: AppArgs) {
+    let AppArgs {
+        name,
+        record_type,
+        resolvers,
+        edns,
+        retry,
+        format,
+        ..
+    } = args;
+    let responses: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = resolvers
+            .into_iter()
+            .map(|(resolver, transport)| {
+                let client = Client {
+                    resolver,
+                    transport,
+                    edns: edns.clone(),
+                    retry,
+                    log: log(),
+                };
+                let name = &name;
+                scope.spawn(move || {
+                    let start = Instant::now();
+                    let query = |name: &str, record_type| client.query(name, record_type);
+                    let response = follow_cnames(name, record_type, query).map(|c| c.response);
+                    ResolverResponse {
+                        resolver,
+                        latency: start.elapsed(),
+                        response,
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    match format {
+        OutputFormat::Text => println!("{}", print::comparison_output(&responses)),
+        OutputFormat::Json => {
+            if let Err(e) = print::print_comparison_json(&responses) {
+                exit_with_error(e);
+            }
+        }
+        // Like dig does when it's given several queries, print each response in turn.
+        OutputFormat::Dig => {
+            for r in responses {
+                match r.response {
+                    Ok(response) => {
+                        println!("{}\n", print::dig_output(&response, r.resolver, r.latency))
+                    }
+                    Err(e) => println!(";; {}: {e}\n", r.resolver),
+                }
+            }
+        }
+    }
+}
+
+/// Prints the error to stderr, so it can't be mistaken for output (e.g. JSON) on stdout, and
+/// exits with a non-zero status so scripts can tell something went wrong.
+fn exit_with_error(e: anyhow::Error) -> ! {
+    eprintln!("Error: {e:#}");
+    std::process::exit(1)
+}
+
+/// Checks the DNSSEC signatures of every response in the CNAME chain, following the chain of
+/// trust down from the trust anchor by asking the resolver for each zone's DS and DNSKEY
+/// records.
+fn validate_chain(client: &Client, anchor: TrustAnchor, chain: &Chain) -> Validation {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as u32;
+    let query = |name: &str, record_type| client.query(name, record_type);
+    Validator::new(anchor, now, query).validate_chain(&chain.earlier_responses, &chain.response)
+}
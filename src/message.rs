@@ -1,47 +1,61 @@
+pub mod dnssec;
+pub mod edns;
+pub mod error;
 pub mod header;
+pub mod name;
 mod parser_utils;
-mod question;
+pub mod question;
 pub mod record;
 
 use crate::{
     dns_types::Class,
     message::{question::Entry, record::Record},
-    parse::parse_label,
+    parse::{many_until_empty, parse_label},
     RecordType,
 };
-use anyhow::Result as AResult;
+use anyhow::{anyhow, Result as AResult};
 use bitvec::prelude::*;
+use edns::{Edns, OPT_RECORD_TYPE};
+use error::{fail, parse_all_rdata, PResult, ParseError, ParseErrorKind, Section};
 use header::Header;
+use name::{escape_label, name_to_labels, NameCompressor};
 use nom::{
-    combinator::{map, map_res, peek},
-    error::Error,
-    multi::{count, length_value},
+    combinator::{map, peek, rest},
+    multi::{count, length_data},
     number::complete::{be_u16, be_u32, be_u8},
     sequence::tuple,
-    IResult,
 };
+use serde::Serialize;
 use std::{
     io::Read,
     net::{Ipv4Addr, Ipv6Addr},
 };
 
-use self::record::{RecordData, SoaData};
+use self::{
+    dnssec::{
+        parse_type_bitmaps, DnskeyData, DsData, Nsec3Data, Nsec3ParamData, NsecData, RrsigData,
+    },
+    record::{MxData, RecordData, SoaData, SrvData},
+};
 
 /// Defined by the spec
 /// UDP messages    512 octets or less
+/// (unless both sides use EDNS, see [`Edns::udp_payload_size`])
 pub(crate) const MAX_UDP_BYTES: usize = 512;
 
 /// Defined by the spec
 /// labels          63 octets or less
-const MAX_LABEL_BYTES: usize = 63;
+pub(crate) const MAX_LABEL_BYTES: usize = 63;
 
 /// Defined by the spec
 /// names           255 octets or less
-const MAX_NAME_BYTES: usize = 255;
+pub(crate) const MAX_NAME_BYTES: usize = 255;
 
-const MAX_RECURSION_DEPTH: u8 = 20;
+/// Names can be compressed by pointing to other names, which can point to other names, etc.
+/// Real messages only need a few, so this is just to stop malicious messages wasting time.
+const MAX_COMPRESSION_POINTERS: usize = 20;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 pub struct Message {
     /// The header section is always present.  The header includes fields that
     /// specify which of the remaining sections are present, and also specify
@@ -64,40 +78,71 @@ pub struct Message {
     /// which relate to the query, but are not strictly answers for the
     /// question.
     pub additional: Vec<Record>,
+    /// The EDNS OPT pseudo-record. It's sent in the additional section, but it isn't
+    /// really a record, so it gets stored separately.
+    pub edns: Option<Edns>,
 }
 
 impl Message {
-    pub(crate) fn new_query(
+    pub fn new_query(
         id: u16,
         domain_name: String,
         record_type: RecordType,
+        edns: Option<Edns>,
     ) -> AResult<Self> {
-        let name_len = domain_name.len();
-        if name_len > MAX_NAME_BYTES {
-            anyhow::bail!(
-                "Domain name is {name_len} bytes, which is over the max of {MAX_NAME_BYTES}"
-            );
+        // Checks the labels and name aren't too long.
+        name_to_labels(&domain_name)?;
+        let mut domain_name = domain_name;
+        if !domain_name.ends_with('.') {
+            domain_name.push('.');
         }
-        let labels: Vec<_> = domain_name.split('.').map(|a| a.to_owned()).collect();
-        if labels.iter().any(|label| label.len() > MAX_LABEL_BYTES) {
-            anyhow::bail!(
-                "One of the labels in your domain is over the max of {MAX_LABEL_BYTES} bytes"
-            );
+        let mut header = Header::new_query(id);
+        if edns.is_some() {
+            header.additional_records_count = 1;
         }
         let msg = Message {
-            header: Header::new_query(id),
-            question: vec![Entry::new(labels, record_type)],
+            header,
+            question: vec![Entry::new(domain_name, record_type)],
             answer: Vec::new(),
             authority: Vec::new(),
             additional: Vec::new(),
+            edns,
         };
         Ok(msg)
     }
 
+    /// Serialize the whole message. The bitvec must be empty, because name compression needs to
+    /// know where each name is, relative to the start of the message.
     fn serialize_bits<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) -> AResult<()> {
-        self.header.serialize(bv);
+        // The counts in the header must match the sections, so work them out from the sections,
+        // rather than trusting whoever built this message to have kept them in sync.
+        let count = |section: &str, len: usize| {
+            u16::try_from(len).map_err(|_| anyhow!("Too many entries ({len}) in the {section}"))
+        };
+        let mut header = self.header.clone();
+        header.question_count = count("question section", self.question.len())?;
+        header.answer_count = count("answer section", self.answer.len())?;
+        header.name_server_count = count("authority section", self.authority.len())?;
+        header.additional_records_count = count(
+            "additional section",
+            self.additional.len() + usize::from(self.edns.is_some()),
+        )?;
+        header.serialize(bv);
+
+        let mut names = NameCompressor::default();
         for q in &self.question {
-            q.serialize(bv)?;
+            q.serialize(&mut names, bv)?;
+        }
+        for record in self
+            .answer
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            record.serialize(&mut names, bv)?;
+        }
+        if let Some(edns) = &self.edns {
+            edns.serialize(bv)?;
         }
         Ok(())
     }
@@ -105,19 +150,37 @@ impl Message {
     pub fn serialize_bytes(&self) -> AResult<Vec<u8>> {
         let mut bv = BitVec::<usize, Msb0>::new();
         self.serialize_bits(&mut bv)?;
-        let mut msg_bytes = Vec::with_capacity(MAX_UDP_BYTES);
+        let mut msg_bytes = Vec::with_capacity(self.max_response_bytes());
         bv.as_bitslice().read_to_end(&mut msg_bytes).unwrap();
         Ok(msg_bytes)
     }
 
-    pub fn deserialize(input: Vec<u8>) -> anyhow::Result<Self> {
+    /// How big a response to this message can be, when it's sent over UDP.
+    pub fn max_response_bytes(&self) -> usize {
+        match &self.edns {
+            Some(edns) => MAX_UDP_BYTES.max(edns.udp_payload_size.into()),
+            None => MAX_UDP_BYTES,
+        }
+    }
+
+    pub fn deserialize(input: Vec<u8>) -> Result<Self, ParseError> {
         let mp = MsgParser { input };
-        let slice = &mp.input[..];
-        let msg: Message = mp.parse_message(slice).unwrap().1;
-        Ok(msg)
+        mp.parse_message()
     }
 }
 
+/// Parses RDATA of the given type on its own, outside of any message. It mustn't contain any
+/// compression pointers, because there's nothing for them to point at.
+pub fn deserialize_rdata(
+    record_type: RecordType,
+    rdata: Vec<u8>,
+) -> Result<RecordData, ParseError> {
+    let mp = MsgParser { input: rdata };
+    let result =
+        parse_all_rdata(&mp.input, mp.parse_rdata(record_type)).map(|data| (&[][..], data));
+    mp.in_section(Section::Answer, result).map(|(_, data)| data)
+}
+
 struct MsgParser {
     input: Vec<u8>,
 }
@@ -125,11 +188,10 @@ struct MsgParser {
 impl MsgParser {
     /// Returns a parser that can parse DNS record data of the given record type.
     fn parse_rdata<'i>(
-        &self,
+        &'i self,
         record_type: RecordType,
-    ) -> impl FnMut(&'i [u8]) -> IResult<&'i [u8], RecordData> + '_ {
+    ) -> impl FnMut(&'i [u8]) -> PResult<'i, RecordData> + 'i {
         move |i| {
-            let recursion_depth = 0;
             let record = match record_type {
                 RecordType::A => map(tuple((be_u8, be_u8, be_u8, be_u8)), |(a, b, c, d)| {
                     RecordData::A(Ipv4Addr::new(a, b, c, d))
@@ -142,17 +204,17 @@ impl MsgParser {
                         RecordData::Aaaa(Ipv6Addr::new(a, b, c, d, e, f, g, h))
                     },
                 )(i)?,
-                RecordType::Cname => {
-                    map(|i| self.parse_name(i, recursion_depth), RecordData::Cname)(i)?
-                }
-                RecordType::Ns => map(|i| self.parse_name(i, recursion_depth), RecordData::Ns)(i)?,
+                RecordType::Cname => map(|i| self.parse_name(i), RecordData::Cname)(i)?,
+                RecordType::Ns => map(|i| self.parse_name(i), RecordData::Ns)(i)?,
+                RecordType::Ptr => map(|i| self.parse_name(i), RecordData::Ptr)(i)?,
                 RecordType::Soa => {
-                    let (i, mname) = self.parse_name(i, recursion_depth)?;
-                    let (i, rname) = self.parse_name(i, recursion_depth)?;
+                    let (i, mname) = self.parse_name(i)?;
+                    let (i, rname) = self.parse_name(i)?;
                     let (i, serial) = be_u32(i)?;
                     let (i, refresh) = be_u32(i)?;
                     let (i, retry) = be_u32(i)?;
                     let (i, expire) = be_u32(i)?;
+                    let (i, minimum) = be_u32(i)?;
                     let rd = SoaData {
                         mname,
                         rname,
@@ -160,21 +222,143 @@ impl MsgParser {
                         refresh,
                         retry,
                         expire,
+                        minimum,
                     };
                     (i, RecordData::Soa(rd))
                 }
+                RecordType::Mx => {
+                    let (i, preference) = be_u16(i)?;
+                    let (i, exchange) = self.parse_name(i)?;
+                    (
+                        i,
+                        RecordData::Mx(MxData {
+                            preference,
+                            exchange,
+                        }),
+                    )
+                }
+                RecordType::Srv => {
+                    let (i, (priority, weight, port)) = tuple((be_u16, be_u16, be_u16))(i)?;
+                    let (i, target) = self.parse_name(i)?;
+                    let rd = SrvData {
+                        priority,
+                        weight,
+                        port,
+                        target,
+                    };
+                    (i, RecordData::Srv(rd))
+                }
+                // TXT RDATA is one or more <character-string>s, each of which is a length
+                // byte followed by that many bytes. Keep reading them until the RDATA runs out.
+                RecordType::Txt => map(
+                    many_until_empty(length_data(be_u8)),
+                    |strings: Vec<&[u8]>| {
+                        RecordData::Txt(strings.into_iter().map(|s| s.to_vec()).collect())
+                    },
+                )(i)?,
+                RecordType::Dnskey => {
+                    let (i, (flags, protocol, algorithm)) = tuple((be_u16, be_u8, be_u8))(i)?;
+                    let (i, public_key) = rest(i)?;
+                    let rd = DnskeyData {
+                        flags,
+                        protocol,
+                        algorithm,
+                        public_key: public_key.to_vec(),
+                    };
+                    (i, RecordData::Dnskey(rd))
+                }
+                RecordType::Ds => {
+                    let (i, (key_tag, algorithm, digest_type)) = tuple((be_u16, be_u8, be_u8))(i)?;
+                    let (i, digest) = rest(i)?;
+                    let rd = DsData {
+                        key_tag,
+                        algorithm,
+                        digest_type,
+                        digest: digest.to_vec(),
+                    };
+                    (i, RecordData::Ds(rd))
+                }
+                RecordType::Rrsig => {
+                    let (i, type_covered) = map(be_u16, RecordType::from)(i)?;
+                    let (i, (algorithm, labels)) = tuple((be_u8, be_u8))(i)?;
+                    let (i, (original_ttl, expiration, inception)) =
+                        tuple((be_u32, be_u32, be_u32))(i)?;
+                    let (i, key_tag) = be_u16(i)?;
+                    let (i, signer_name) = self.parse_name(i)?;
+                    let (i, signature) = rest(i)?;
+                    let rd = RrsigData {
+                        type_covered,
+                        algorithm,
+                        labels,
+                        original_ttl,
+                        expiration,
+                        inception,
+                        key_tag,
+                        signer_name,
+                        signature: signature.to_vec(),
+                    };
+                    (i, RecordData::Rrsig(rd))
+                }
+                RecordType::Nsec => {
+                    let (i, next_domain_name) = self.parse_name(i)?;
+                    let (i, types) = parse_type_bitmaps(i)?;
+                    let rd = NsecData {
+                        next_domain_name,
+                        types,
+                    };
+                    (i, RecordData::Nsec(rd))
+                }
+                RecordType::Nsec3 => {
+                    let (i, (hash_algorithm, flags, iterations)) =
+                        tuple((be_u8, be_u8, be_u16))(i)?;
+                    let (i, salt) = length_data(be_u8)(i)?;
+                    let (i, next_hashed_owner) = length_data(be_u8)(i)?;
+                    let (i, types) = parse_type_bitmaps(i)?;
+                    let rd = Nsec3Data {
+                        hash_algorithm,
+                        flags,
+                        iterations,
+                        salt: salt.to_vec(),
+                        next_hashed_owner: next_hashed_owner.to_vec(),
+                        types,
+                    };
+                    (i, RecordData::Nsec3(rd))
+                }
+                RecordType::Nsec3param => {
+                    let (i, (hash_algorithm, flags, iterations)) =
+                        tuple((be_u8, be_u8, be_u16))(i)?;
+                    let (i, salt) = length_data(be_u8)(i)?;
+                    let rd = Nsec3ParamData {
+                        hash_algorithm,
+                        flags,
+                        iterations,
+                        salt: salt.to_vec(),
+                    };
+                    (i, RecordData::Nsec3param(rd))
+                }
+                // RFC 3597: treat the RDATA of unknown types as an opaque blob.
+                RecordType::Unknown(rtype) => map(rest, |bytes: &[u8]| RecordData::Unknown {
+                    rtype,
+                    bytes: bytes.to_vec(),
+                })(i)?,
+                // OPT pseudo-records are only valid in the additional section, where they
+                // get parsed by `parse_additional` instead.
+                RecordType::Opt => return fail(i, ParseErrorKind::OptOutsideAdditional),
             };
             Ok(record)
         }
     }
 
     /// Parse a domain name.
-    fn parse_name<'i>(
-        &self,
-        mut input: &'i [u8],
-        recursion_depth: u8,
-    ) -> IResult<&'i [u8], String> {
+    fn parse_name<'i>(&'i self, mut input: &'i [u8]) -> PResult<'i, String> {
         let mut name = String::new();
+        // Where parsing should carry on from, once the name is finished.
+        // Normally that's right after the name's last label, but if the name used a pointer,
+        // it's right after the first pointer.
+        let mut after_name = None;
+        let mut pointers_followed = Vec::new();
+        // Count the length of the name in its uncompressed form, including length bytes.
+        let mut name_len = 0;
         loop {
             let (i, first_byte) = peek(be_u8)(input)?;
             input = i;
@@ -187,22 +371,29 @@ impl MsgParser {
                 let dereference_pointer = |ptr| (ptr - ((POINTER_HEADER as u16) << 8)) as usize;
                 let (i, next_label_offset) = map(be_u16, dereference_pointer)(input)?;
 
-                if recursion_depth >= MAX_RECURSION_DEPTH {
-                    panic!("too many DNS message compression indirections!")
+                if next_label_offset >= self.input.len() {
+                    return fail(input, ParseErrorKind::PointerOutOfBounds(next_label_offset));
                 }
+                if pointers_followed.contains(&next_label_offset) {
+                    return fail(input, ParseErrorKind::PointerLoop(next_label_offset));
+                }
+                if pointers_followed.len() >= MAX_COMPRESSION_POINTERS {
+                    return fail(input, ParseErrorKind::TooManyPointers);
+                }
+                pointers_followed.push(next_label_offset);
+                after_name.get_or_insert(i);
 
-                // Now, just parse a name from that offset.
-                let (_, pointed_label) = self
-                    .parse_name(&self.input[next_label_offset..], recursion_depth + 1)
-                    .unwrap();
-                name += &pointed_label;
-                input = i;
-                break;
+                // Now, just carry on parsing labels from that offset.
+                input = &self.input[next_label_offset..];
             } else {
                 // This label is a literal.
                 let (i, label) = parse_label(input)?;
+                name_len += label.len() + 1;
+                if name_len > MAX_NAME_BYTES {
+                    return fail(input, ParseErrorKind::NameTooLong);
+                }
                 input = i;
-                name += &label;
+                name += &escape_label(label);
                 // Domain names end with a zero-length terminal label.
                 // (that's why in `dig` the names always end in an unnecessary dot,
                 // e.g. adamchalmers.com.)
@@ -212,23 +403,28 @@ impl MsgParser {
                 name.push('.');
             }
         }
-        Ok((input, name))
+        // The root domain has no labels, but it's written as a single dot.
+        if name.is_empty() {
+            name.push('.');
+        }
+        Ok((after_name.unwrap_or(input), name))
     }
 
-    fn parse_record<'i>(&self, input: &'i [u8]) -> IResult<&'i [u8], Record, Error<&'i [u8]>> {
-        let (input, name) = self.parse_name(input, 0)?;
-        let (input, record_type) = map_res(be_u16, RecordType::try_from)(input)?;
-        let (input, class) = map_res(be_u16, Class::try_from)(input)?;
+    fn parse_record<'i>(&'i self, input: &'i [u8]) -> PResult<'i, Record> {
+        let (input, name) = self.parse_name(input)?;
+        let (input, record_type) = map(be_u16, RecordType::from)(input)?;
+        let (rest, class_num) = be_u16(input)?;
+        let Ok(class) = Class::try_from(class_num) else {
+            return fail(input, ParseErrorKind::UnknownClass(class_num));
+        };
+        let input = rest;
         // RFC defines the max TTL as "positive values of a signed 32 bit number."
-        let max_ttl: isize = i32::MAX.try_into().unwrap();
-        let (input, ttl) = map_res(be_u32, |ttl| {
-            if (ttl as isize) > max_ttl {
-                Err(format!("TTL {ttl} is too large"))
-            } else {
-                Ok(ttl)
-            }
-        })(input)?;
-        let (i, data) = length_value(be_u16, self.parse_rdata(record_type))(input)?;
+        let (rest, ttl) = be_u32(input)?;
+        if ttl > i32::MAX as u32 {
+            return fail(input, ParseErrorKind::TtlTooLarge(ttl));
+        }
+        let (i, rdata) = length_data(be_u16)(rest)?;
+        let data = parse_all_rdata(rdata, self.parse_rdata(record_type))?;
         Ok((
             i,
             Record {
@@ -240,36 +436,100 @@ impl MsgParser {
         ))
     }
 
-    fn parse_message<'i>(&self, i: &'i [u8]) -> IResult<&'i [u8], Message, Error<&'i [u8]>> {
+    /// The additional section can hold the EDNS OPT pseudo-record as well as normal records.
+    fn parse_additional<'i>(&'i self, input: &'i [u8]) -> PResult<'i, Additional> {
+        // The OPT record uses the CLASS and TTL fields differently to every other record,
+        // so check the record type before deciding how to parse it.
+        let (_, (_, record_type)) = peek(tuple((|i| self.parse_name(i), be_u16)))(input)?;
+        if record_type == OPT_RECORD_TYPE {
+            map(Edns::deserialize, Additional::Opt)(input)
+        } else {
+            map(|i| self.parse_record(i), Additional::Record)(input)
+        }
+    }
+
+    /// Converts errors from the nom parsers into a [`ParseError`], which says where the error was.
+    fn in_section<'i, O>(
+        &self,
+        section: Section,
+        result: PResult<'i, O>,
+    ) -> Result<(&'i [u8], O), ParseError> {
+        result.map_err(|e| {
+            let (offset, kind) = match e {
+                nom::Err::Error(e) | nom::Err::Failure(e) => {
+                    // All the parsers operate on slices of the original message,
+                    // so the offset is just the distance between the two slices.
+                    let offset = e.input.as_ptr() as usize - self.input.as_ptr() as usize;
+                    (offset, e.kind)
+                }
+                nom::Err::Incomplete(_) => (self.input.len(), ParseErrorKind::UnexpectedEnd),
+            };
+            ParseError {
+                offset,
+                section,
+                kind,
+            }
+        })
+    }
+
+    fn parse_message(&self) -> Result<Message, ParseError> {
+        let i = &self.input[..];
         // The Header parser requires parsing individual bits, because the RFC stores some boolean
         // flags as single bits, and some numbers as 4-bit numbers.
         // So, first convert the input from bytestream to bitstream, then run the Header parser,
         // then convert the bitstream back to a bystream for the following steps.
-        let (i, header) = nom::bits::bits(Header::deserialize)(i)?;
+        let (i, header) = self.in_section(Section::Header, Header::parse(i))?;
 
         // Parse the right number of question sections.
-        let (i, question) = count(question::Entry::deserialize, header.question_count.into())(i)?;
+        let (i, question) = self.in_section(
+            Section::Question,
+            count(question::Entry::deserialize, header.question_count.into())(i),
+        )?;
 
         // After the question comes the DNS records themselves. Parse the right number of each kind!
-        let (i, answer) = count(|i| self.parse_record(i), header.answer_count.into())(i)?;
-        let (i, authority) = count(|i| self.parse_record(i), header.name_server_count.into())(i)?;
-        let (i, additional) = count(
-            |i| self.parse_record(i),
-            header.additional_records_count.into(),
-        )(i)?;
-        Ok((
-            i,
-            Message {
-                header,
-                question,
-                answer,
-                authority,
-                additional,
-            },
-        ))
+        let (i, answer) = self.in_section(
+            Section::Answer,
+            count(|i| self.parse_record(i), header.answer_count.into())(i),
+        )?;
+        let (i, authority) = self.in_section(
+            Section::Authority,
+            count(|i| self.parse_record(i), header.name_server_count.into())(i),
+        )?;
+        let mut additional = Vec::new();
+        let mut edns = None;
+        let mut i = i;
+        for _ in 0..header.additional_records_count {
+            let entry;
+            (i, entry) = self.in_section(Section::Additional, self.parse_additional(i))?;
+            match entry {
+                Additional::Record(record) => additional.push(record),
+                Additional::Opt(_) if edns.is_some() => {
+                    return Err(ParseError {
+                        offset: i.as_ptr() as usize - self.input.as_ptr() as usize,
+                        section: Section::Additional,
+                        kind: ParseErrorKind::DuplicateOpt,
+                    })
+                }
+                Additional::Opt(opt) => edns = Some(opt),
+            }
+        }
+        Ok(Message {
+            header,
+            question,
+            answer,
+            authority,
+            additional,
+            edns,
+        })
     }
 }
 
+/// One entry from the additional section.
+enum Additional {
+    Record(Record),
+    Opt(Edns),
+}
+
 #[cfg(test)]
 mod tests {
     use std::net::Ipv4Addr;
@@ -277,6 +537,7 @@ mod tests {
     use crate::{
         dns_types::Class,
         message::record::{Record, RecordData},
+        test_utils::record,
     };
 
     use super::*;
@@ -294,6 +555,18 @@ mod tests {
         let msg = Message::deserialize(response_msg).unwrap();
         assert_eq!(msg.header.name_server_count, 1);
         assert_eq!(msg.authority.len(), 1);
+        assert_eq!(
+            msg.authority[0].data,
+            RecordData::Soa(SoaData {
+                mname: "dns1.p03.nsone.net.".to_owned(),
+                rname: "hostmaster.nsone.net.".to_owned(),
+                serial: 1643623120,
+                refresh: 43200,
+                retry: 600,
+                expire: 604800,
+                minimum: 300,
+            })
+        );
     }
 
     #[test]
@@ -339,4 +612,445 @@ mod tests {
         let actual_answers = actual_msg.answer;
         assert_eq!(actual_answers, expected_answers)
     }
+
+    #[test]
+    fn test_parse_msg_with_mx_records() {
+        let response_msg = vec![
+            0, 33, 129, 128, 0, 1, 0, 2, 0, 0, 0, 0, // Header (12 bytes)
+            12, 97, 100, 97, 109, 99, 104, 97, 108, 109, 101, 114, 115, // adamchalmers
+            3, 99, 111, 109, // com
+            0,   // .
+            0, 15, 0, 1, // class, type
+            192, 12, // Answer #1: name, which is a pointer to byte 12.
+            0, 15, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 9, // rdata length
+            0, 20, // preference
+            4, 109, 97, 105, 108, 192, 12, // exchange: mail, then a pointer to byte 12.
+            192, 12, // Answer #2: name, which is a pointer to byte 12.
+            0, 15, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 4, // rdata length
+            0, 10, // preference
+            192, 12, // exchange: a pointer to byte 12.
+        ];
+        let msg = Message::deserialize(response_msg).unwrap();
+        let mx: Vec<_> = msg
+            .answer
+            .into_iter()
+            .map(|record| match record.data {
+                RecordData::Mx(mx) => mx,
+                other => panic!("expected MX, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            mx,
+            vec![
+                MxData {
+                    preference: 20,
+                    exchange: "mail.adamchalmers.com.".to_owned()
+                },
+                MxData {
+                    preference: 10,
+                    exchange: "adamchalmers.com.".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let response_msg = vec![
+            0, 33, 129, 131, 0, 1, 0, 1, 0, 1, 0, 0, // Header (12 bytes), with NXDOMAIN
+            3, 99, 111, 109, 0, // com.
+            0, 16, 0, 1, // class, type
+            192, 12, // Answer #1: name, which is a pointer to byte 12.
+            0, 16, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 6, // rdata length
+            2, 104, 105, // "hi"
+            2, 34, 255, // '"', then a non-UTF-8 byte
+            192, 12, // Authority #1: name, which is a pointer to byte 12.
+            0, 6, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 28, // rdata length
+            1, 97, 192, 12, // mname: a.com.
+            1, 98, 192, 12, // rname: b.com.
+            0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, // serial, refresh, retry, expire
+            0, 0, 0, 5, // minimum
+        ];
+        let msg = Message::deserialize(response_msg).unwrap();
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["header"]["resp_code"], "NXDOMAIN");
+        assert_eq!(json["header"]["opcode"], "QUERY");
+        assert_eq!(json["header"]["recursion_desired"], true);
+        assert_eq!(
+            json["question"],
+            serde_json::json!([{"name": "com.", "record_type": "TXT", "record_qclass": "IN"}])
+        );
+        assert_eq!(
+            json["answer"],
+            serde_json::json!([{
+                "name": "com.",
+                "class": "IN",
+                "ttl": 300,
+                "type": "TXT",
+                "data": ["hi", r#"\"\255"#],
+            }])
+        );
+        assert_eq!(
+            json["authority"][0]["data"],
+            serde_json::json!({
+                "mname": "a.com.",
+                "rname": "b.com.",
+                "serial": 1,
+                "refresh": 2,
+                "retry": 3,
+                "expire": 4,
+                "minimum": 5,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_msg_with_txt_record() {
+        let response_msg = vec![
+            0, 33, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // Header (12 bytes)
+            3, 99, 111, 109, 0, // com.
+            0, 16, 0, 1, // class, type
+            192, 12, // Answer #1: name, which is a pointer to byte 12.
+            0, 16, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 12, // rdata length
+            3, 97, 98, 99, // "abc"
+            0,  // ""
+            6, 100, 34, 101, 0, 255, 102, // 'd', '"', 'e', then two non-UTF-8 bytes, then 'f'
+        ];
+        let msg = Message::deserialize(response_msg).unwrap();
+        let record = &msg.answer[0];
+        assert_eq!(
+            record.data,
+            RecordData::Txt(vec![
+                b"abc".to_vec(),
+                Vec::new(),
+                vec![100, 34, 101, 0, 255, 102]
+            ])
+        );
+        assert_eq!(
+            record.as_dns_response(),
+            r#"TXT: "abc" "" "d\"e\000\255f" (TTL 300)"#
+        );
+    }
+
+    #[test]
+    fn test_txt_round_trip() {
+        let response_msg = vec![
+            0, 33, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // Header (12 bytes)
+            3, 99, 111, 109, 0, // com.
+            0, 16, 0, 1, // class, type
+            192, 12, // Answer #1: name, which is a pointer to byte 12.
+            0, 16, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 11, // rdata length
+            0,  // ""
+            9, 118, 61, 115, 112, 102, 49, 0, 255,
+            34, // "v=spf1", a NUL, a non-UTF-8 byte, '"'
+        ];
+        let msg = Message::deserialize(response_msg.clone()).unwrap();
+        assert_eq!(
+            msg.answer[0].data,
+            RecordData::Txt(vec![Vec::new(), b"v=spf1\0\xff\"".to_vec()])
+        );
+        // Every string, even empty or binary ones, is written back exactly as it was.
+        let bytes = msg.serialize_bytes().unwrap();
+        assert_eq!(bytes, response_msg);
+        assert_eq!(Message::deserialize(bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_soa_rname_with_dot() {
+        let response_msg = vec![
+            0, 33, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // Header (12 bytes)
+            3, 99, 111, 109, 0, // com.
+            0, 6, 0, 1, // class, type
+            192, 12, // Answer #1: name, which is a pointer to byte 12.
+            0, 6, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 38, // rdata length
+            2, 110, 115, 192, 12, // mname: ns.com.
+            10, 106, 111, 104, 110, 46, 115, 109, 105, 116, 104, // rname: "john.smith"
+            192, 12, // then a pointer to "com."
+            0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5, // the timers
+        ];
+        let msg = Message::deserialize(response_msg).unwrap();
+        let RecordData::Soa(soa) = &msg.answer[0].data else {
+            panic!("expected an SOA record, got {:?}", msg.answer[0].data);
+        };
+        // The dot inside the first label gets escaped, so it isn't mistaken for a separator.
+        assert_eq!(soa.rname, "john\\.smith.com.");
+        assert_eq!(soa.rname_as_email(), "john.smith@com");
+        assert_eq!(soa.minimum, 5);
+    }
+
+    #[test]
+    fn test_parse_msg_with_srv_record() {
+        let response_msg = vec![
+            0, 33, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // Header (12 bytes)
+            5, 95, 108, 100, 97, 112, // _ldap
+            4, 95, 116, 99, 112, // _tcp
+            3, 99, 111, 109, 0, // com.
+            0, 33, 0, 1, // class, type
+            192, 12, // Answer #1: name, which is a pointer to byte 12.
+            0, 33, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 12, // rdata length
+            0, 10, 0, 60, 1, 133, // priority, weight, port
+            3, 100, 99, 49, 192, 23, // target: dc1, then a pointer to "com."
+        ];
+        let msg = Message::deserialize(response_msg).unwrap();
+        assert_eq!(msg.answer[0].name, "_ldap._tcp.com.");
+        assert_eq!(
+            msg.answer[0].data,
+            RecordData::Srv(SrvData {
+                priority: 10,
+                weight: 60,
+                port: 389,
+                target: "dc1.com.".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_msg_with_unknown_record_type() {
+        let response_msg = vec![
+            0, 33, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // Header (12 bytes)
+            3, 99, 111, 109, 0, // com.
+            255, 0, 0, 1, // class, type
+            192, 12, // Answer #1: name, which is a pointer to byte 12.
+            255, 0, 0, 1, // class, type
+            0, 0, 1, 44, // TTL (u32)
+            0, 4, // rdata length
+            10, 0, 0, 1, // rdata
+        ];
+        let msg = Message::deserialize(response_msg).unwrap();
+        let record = &msg.answer[0];
+        assert_eq!(
+            record.data,
+            RecordData::Unknown {
+                rtype: 65280,
+                bytes: vec![10, 0, 0, 1]
+            }
+        );
+        assert_eq!(
+            record.as_dns_response(),
+            r"TYPE65280: \# 4 0a000001 (TTL 300)"
+        );
+    }
+
+    #[test]
+    fn test_parse_msg_with_opt_record() {
+        let response_msg = vec![
+            0, 33, 129, 128, 0, 1, 0, 1, 0, 0, 0, 1, // Header (12 bytes)
+            3, 99, 111, 109, 0, // com.
+            0, 1, 0, 1, // class, type
+            192, 12, // Answer #1: name, which is a pointer to byte 12.
+            0, 1, 0, 1, // class, type
+            0, 0, 0, 179, // TTL (u32)
+            0, 4, // rdata length
+            104, 19, 237, 120, // rdata, an IPv4
+            0,   // OPT record: root name
+            0, 41, // type
+            4, 208, // UDP payload size
+            0, 0, 128, 0, // extended RCODE, version, DO flag
+            0, 0, // rdata length
+        ];
+        let msg = Message::deserialize(response_msg).unwrap();
+        assert_eq!(msg.answer.len(), 1);
+        assert!(msg.additional.is_empty());
+        let edns = msg.edns.unwrap();
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert!(edns.dnssec_ok);
+    }
+
+    #[test]
+    fn test_query_with_edns() {
+        let msg = Message::new_query(
+            33,
+            "com.".to_owned(),
+            RecordType::A,
+            Some(Edns::new(edns::DEFAULT_UDP_PAYLOAD_SIZE)),
+        )
+        .unwrap();
+        let bytes = msg.serialize_bytes().unwrap();
+        // ARCOUNT
+        assert_eq!(bytes[10..12], [0, 1]);
+        // The OPT record comes right after the question.
+        assert_eq!(bytes[21..], [0, 0, 41, 4, 208, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(msg.max_response_bytes(), 1232);
+    }
+
+    #[test]
+    fn test_serialize_response_round_trip() {
+        let mut header = Header::new_query(33);
+        header.is_response = true;
+        header.authoritative_answer = true;
+        let msg = Message {
+            header,
+            question: vec![Entry::new("example.com.".to_owned(), RecordType::Mx)],
+            answer: vec![
+                record(
+                    "example.com.",
+                    RecordData::Mx(MxData {
+                        preference: 10,
+                        exchange: "mail.example.com.".to_owned(),
+                    }),
+                ),
+                record(
+                    "example.com.",
+                    RecordData::Txt(vec![b"v=spf1 -all".to_vec(), vec![0, 255]]),
+                ),
+            ],
+            authority: vec![record(
+                "example.com.",
+                RecordData::Soa(SoaData {
+                    mname: "ns1.example.com.".to_owned(),
+                    rname: r"john\.smith.example.com.".to_owned(),
+                    serial: 2024010101,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 604800,
+                    minimum: 300,
+                }),
+            )],
+            additional: vec![
+                record(
+                    "mail.example.com.",
+                    RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                ),
+                record(
+                    "_ldap._tcp.example.com.",
+                    RecordData::Srv(SrvData {
+                        priority: 0,
+                        weight: 5,
+                        port: 389,
+                        target: "dc1.example.com.".to_owned(),
+                    }),
+                ),
+                record(
+                    "example.com.",
+                    RecordData::Unknown {
+                        rtype: 65280,
+                        bytes: vec![1, 2, 3],
+                    },
+                ),
+            ],
+            edns: Some(Edns::new(edns::DEFAULT_UDP_PAYLOAD_SIZE)),
+        };
+        let bytes = msg.serialize_bytes().unwrap();
+        // The answer's name is a pointer back to the name in the question.
+        assert_eq!(bytes[29..31], [192, 12]);
+
+        let mut expected = msg.clone();
+        expected.header.answer_count = 2;
+        expected.header.name_server_count = 1;
+        expected.header.additional_records_count = 4;
+        assert_eq!(Message::deserialize(bytes).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_labels_with_dots_are_escaped() {
+        let msg =
+            Message::deserialize(response_with_answer_name(&[3, 97, 46, 98, 192, 12])).unwrap();
+        assert_eq!(msg.answer[0].name, r"a\.b.com.");
+        // And they survive a round trip.
+        let bytes = msg.serialize_bytes().unwrap();
+        assert_eq!(bytes[21..27], [3, 97, 46, 98, 192, 12]);
+    }
+
+    /// A response with one A record for com., whose name is the given bytes.
+    fn response_with_answer_name(name: &[u8]) -> Vec<u8> {
+        let mut msg = vec![
+            0, 33, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, // Header (12 bytes)
+            3, 99, 111, 109, 0, // com.
+            0, 1, 0, 1, // class, type
+        ];
+        msg.extend_from_slice(name);
+        msg.extend_from_slice(&[
+            0, 1, 0, 1, // class, type
+            0, 0, 0, 179, // TTL (u32)
+            0, 4, // rdata length
+            104, 19, 237, 120, // rdata, an IPv4
+        ]);
+        msg
+    }
+
+    fn parse_err(msg: Vec<u8>) -> ParseError {
+        Message::deserialize(msg).unwrap_err()
+    }
+
+    #[test]
+    fn test_err_pointer_out_of_bounds() {
+        let err = parse_err(response_with_answer_name(&[192, 200]));
+        assert_eq!(
+            err,
+            ParseError {
+                offset: 21,
+                section: Section::Answer,
+                kind: ParseErrorKind::PointerOutOfBounds(200),
+            }
+        );
+    }
+
+    #[test]
+    fn test_err_pointer_loop() {
+        // The name is a pointer to itself.
+        let err = parse_err(response_with_answer_name(&[192, 21]));
+        assert_eq!(err.kind, ParseErrorKind::PointerLoop(21));
+        assert_eq!(err.section, Section::Answer);
+        // A label, then a pointer back to that label.
+        let err = parse_err(response_with_answer_name(&[1, 97, 192, 21]));
+        assert_eq!(err.kind, ParseErrorKind::PointerLoop(21));
+    }
+
+    #[test]
+    fn test_err_label_too_long() {
+        let err = parse_err(response_with_answer_name(&[64, 97, 0]));
+        assert_eq!(
+            err,
+            ParseError {
+                offset: 21,
+                section: Section::Answer,
+                kind: ParseErrorKind::LabelTooLong(64),
+            }
+        );
+    }
+
+    #[test]
+    fn test_err_truncated_rdata() {
+        let mut msg = response_with_answer_name(&[192, 12]);
+        // Say the IPv4 address is only 3 bytes long.
+        let rdlength_offset = msg.len() - 5;
+        msg[rdlength_offset] = 3;
+        msg.pop();
+        let err = parse_err(msg);
+        assert_eq!(err.kind, ParseErrorKind::TruncatedRdata);
+        assert_eq!(err.section, Section::Answer);
+    }
+
+    #[test]
+    fn test_err_truncated_message() {
+        let mut msg = response_with_answer_name(&[192, 12]);
+        msg.truncate(30);
+        assert_eq!(
+            parse_err(msg),
+            ParseError {
+                offset: 27,
+                section: Section::Answer,
+                kind: ParseErrorKind::UnexpectedEnd,
+            }
+        );
+        let err = parse_err(vec![0, 33, 129]);
+        assert_eq!(err.section, Section::Header);
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedEnd);
+    }
 }
@@ -0,0 +1,424 @@
+//! The RDATA of the DNSSEC record types, from <https://datatracker.ietf.org/doc/html/rfc4034>
+//! and (for NSEC3) <https://datatracker.ietf.org/doc/html/rfc5155>.
+use std::fmt;
+
+use anyhow::{anyhow, Result as AResult};
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER};
+use nom::{bytes::complete::take, number::complete::be_u8};
+use serde::{Serialize, Serializer};
+
+use super::{
+    error::{fail, PResult, ParseErrorKind},
+    record::serialize_hex,
+};
+use crate::RecordType;
+
+/// A public key that signs a zone's records.
+/// See <https://datatracker.ietf.org/doc/html/rfc4034#section-2>
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DnskeyData {
+    /// Bit 7 is set for zone keys, and bit 15 for key signing keys (a.k.a. secure entry points).
+    pub flags: u16,
+    /// Always 3.
+    pub protocol: u8,
+    /// Which signing algorithm the key is for, e.g. 8 for RSA/SHA-256.
+    pub algorithm: u8,
+    #[serde(serialize_with = "serialize_base64")]
+    pub public_key: Vec<u8>,
+}
+
+impl DnskeyData {
+    /// Only zone keys can be used to check RRSIGs.
+    pub fn is_zone_key(&self) -> bool {
+        self.flags & 0x0100 != 0
+    }
+
+    /// Key signing keys are the ones DS records in the parent zone usually point at.
+    pub fn is_secure_entry_point(&self) -> bool {
+        self.flags & 0x0001 != 0
+    }
+
+    /// The RDATA in wire format.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut rdata = Vec::with_capacity(4 + self.public_key.len());
+        rdata.extend_from_slice(&self.flags.to_be_bytes());
+        rdata.push(self.protocol);
+        rdata.push(self.algorithm);
+        rdata.extend_from_slice(&self.public_key);
+        rdata
+    }
+
+    /// A checksum of the key, which DS and RRSIG records use to say which key they mean.
+    /// See <https://datatracker.ietf.org/doc/html/rfc4034#appendix-B>
+    pub fn key_tag(&self) -> u16 {
+        let mut acc: u32 = 0;
+        for (i, byte) in self.to_wire().into_iter().enumerate() {
+            acc += if i % 2 == 0 {
+                u32::from(byte) << 8
+            } else {
+                u32::from(byte)
+            };
+        }
+        acc += (acc >> 16) & 0xFFFF;
+        (acc & 0xFFFF) as u16
+    }
+}
+
+/// Presentation format: `flags protocol algorithm base64-key`
+impl fmt::Display for DnskeyData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.flags,
+            self.protocol,
+            self.algorithm,
+            BASE64.encode(&self.public_key)
+        )
+    }
+}
+
+/// A digest of a child zone's DNSKEY, stored in the parent zone, which links the two zones
+/// in the chain of trust. See <https://datatracker.ietf.org/doc/html/rfc4034#section-5>
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DsData {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    /// Which hash function made the digest, e.g. 2 for SHA-256.
+    pub digest_type: u8,
+    #[serde(serialize_with = "serialize_hex")]
+    pub digest: Vec<u8>,
+}
+
+/// Presentation format: `key-tag algorithm digest-type hex-digest`
+impl fmt::Display for DsData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.key_tag,
+            self.algorithm,
+            self.digest_type,
+            HEXUPPER.encode(&self.digest)
+        )
+    }
+}
+
+/// A signature over all the records of one type at one name (an RRset).
+/// See <https://datatracker.ietf.org/doc/html/rfc4034#section-3>
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RrsigData {
+    pub type_covered: RecordType,
+    pub algorithm: u8,
+    /// How many labels the owner name had, not counting the root or a leading wildcard.
+    pub labels: u8,
+    /// The TTL of the RRset, as it is in the zone. Caches count TTLs down, so it's needed to
+    /// check the signature.
+    pub original_ttl: u32,
+    /// When the signature stops being valid, in seconds since the Unix epoch.
+    pub expiration: u32,
+    /// When the signature starts being valid, in seconds since the Unix epoch.
+    pub inception: u32,
+    pub key_tag: u16,
+    /// The zone whose DNSKEY made the signature.
+    pub signer_name: String,
+    #[serde(serialize_with = "serialize_base64")]
+    pub signature: Vec<u8>,
+}
+
+/// Presentation format: `type algorithm labels original-ttl expiration inception key-tag
+/// signer base64-signature`, with the times written as `YYYYMMDDHHmmSS`.
+impl fmt::Display for RrsigData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {} {} {}",
+            self.type_covered,
+            self.algorithm,
+            self.labels,
+            self.original_ttl,
+            format_timestamp(self.expiration),
+            format_timestamp(self.inception),
+            self.key_tag,
+            self.signer_name,
+            BASE64.encode(&self.signature)
+        )
+    }
+}
+
+/// Proves which names and types don't exist, by naming the next name in the zone and the
+/// types at this one. See <https://datatracker.ietf.org/doc/html/rfc4034#section-4>
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct NsecData {
+    pub next_domain_name: String,
+    pub types: Vec<RecordType>,
+}
+
+/// Presentation format: `next-name TYPE...`
+impl fmt::Display for NsecData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.next_domain_name)?;
+        write_types(f, &self.types)
+    }
+}
+
+/// Like NSEC, but using hashed names, so the zone's names can't be listed by walking the
+/// chain. See <https://datatracker.ietf.org/doc/html/rfc5155#section-3>
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Nsec3Data {
+    /// Always 1, for SHA-1.
+    pub hash_algorithm: u8,
+    /// Bit 0 is opt-out, i.e. this NSEC3 might skip over unsigned delegations.
+    pub flags: u8,
+    /// How many extra times the name was hashed.
+    pub iterations: u16,
+    #[serde(serialize_with = "serialize_hex")]
+    pub salt: Vec<u8>,
+    #[serde(serialize_with = "serialize_base32hex")]
+    pub next_hashed_owner: Vec<u8>,
+    pub types: Vec<RecordType>,
+}
+
+impl Nsec3Data {
+    pub fn opt_out(&self) -> bool {
+        self.flags & 1 != 0
+    }
+}
+
+/// Presentation format: `algorithm flags iterations hex-salt base32hex-next-hash TYPE...`
+impl fmt::Display for Nsec3Data {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.hash_algorithm,
+            self.flags,
+            self.iterations,
+            format_salt(&self.salt),
+            BASE32HEX_NOPAD.encode(&self.next_hashed_owner)
+        )?;
+        write_types(f, &self.types)
+    }
+}
+
+/// The hashing parameters a zone's NSEC3 records use.
+/// See <https://datatracker.ietf.org/doc/html/rfc5155#section-4>
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Nsec3ParamData {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    #[serde(serialize_with = "serialize_hex")]
+    pub salt: Vec<u8>,
+}
+
+/// Presentation format: `algorithm flags iterations hex-salt`
+impl fmt::Display for Nsec3ParamData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.hash_algorithm,
+            self.flags,
+            self.iterations,
+            format_salt(&self.salt)
+        )
+    }
+}
+
+fn write_types(f: &mut fmt::Formatter<'_>, types: &[RecordType]) -> fmt::Result {
+    for record_type in types {
+        write!(f, " {record_type}")?;
+    }
+    Ok(())
+}
+
+/// Empty salts are written as `-`.
+fn format_salt(salt: &[u8]) -> String {
+    if salt.is_empty() {
+        "-".to_owned()
+    } else {
+        HEXUPPER.encode(salt)
+    }
+}
+
+fn serialize_base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64.encode(bytes))
+}
+
+fn serialize_base32hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE32HEX_NOPAD.encode(bytes))
+}
+
+/// Parses the type bitmaps NSEC and NSEC3 records use to list types.
+/// Types are split into windows of 256. Each window that has any types is written as the
+/// window number, then the length of its bitmap, then a bitmap where bit N means type
+/// (window * 256 + N) is there. See <https://datatracker.ietf.org/doc/html/rfc4034#section-4.1.2>
+pub(crate) fn parse_type_bitmaps(mut input: &[u8]) -> PResult<'_, Vec<RecordType>> {
+    let mut types = Vec::new();
+    let mut previous_window = None;
+    while !input.is_empty() {
+        let (i, window) = be_u8(input)?;
+        let (i, len) = be_u8(i)?;
+        if previous_window.is_some_and(|prev| window <= prev) || !(1..=32).contains(&len) {
+            return fail(input, ParseErrorKind::InvalidTypeBitmap);
+        }
+        let (i, bitmap) = take(len)(i)?;
+        for (byte_num, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    let num = u16::from(window) << 8 | (byte_num * 8 + bit) as u16;
+                    types.push(RecordType::from(num));
+                }
+            }
+        }
+        previous_window = Some(window);
+        input = i;
+    }
+    Ok((input, types))
+}
+
+/// Writes types as type bitmaps, the opposite of [`parse_type_bitmaps`].
+pub(crate) fn type_bitmaps(types: &[RecordType]) -> Vec<u8> {
+    let mut nums: Vec<u16> = types.iter().map(|&t| u16::from(t)).collect();
+    nums.sort_unstable();
+    nums.dedup();
+    let mut out = Vec::new();
+    for window in nums.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let mut bitmap = [0u8; 32];
+        for num in window {
+            let low = (num & 0xFF) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+        }
+        let len = window.last().map_or(0, |num| (num & 0xFF) as usize / 8 + 1);
+        out.push((window[0] >> 8) as u8);
+        out.push(len as u8);
+        out.extend_from_slice(&bitmap[..len]);
+    }
+    out
+}
+
+/// Formats seconds since the Unix epoch as `YYYYMMDDHHmmSS` in UTC, like RRSIG times are
+/// written. See <https://datatracker.ietf.org/doc/html/rfc4034#section-3.2>
+pub fn format_timestamp(secs: u32) -> String {
+    let (year, month, day) = civil_from_days(i64::from(secs / 86400));
+    let secs_of_day = secs % 86400;
+    format!(
+        "{year:04}{month:02}{day:02}{:02}{:02}{:02}",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Parses an RRSIG time, written either as `YYYYMMDDHHmmSS` or as seconds since the epoch.
+/// The opposite of [`format_timestamp`].
+pub fn parse_timestamp(s: &str) -> AResult<u32> {
+    let invalid = || anyhow!("{s} is not a valid timestamp");
+    if s.len() != 14 {
+        return s.parse().map_err(|_| invalid());
+    }
+    if !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let field = |range: std::ops::Range<usize>| s[range].parse::<i64>().unwrap();
+    let (year, month, day) = (field(0..4), field(4..6), field(6..8));
+    let (hour, minute, second) = (field(8..10), field(10..12), field(12..14));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+    let days = days_from_civil(year, month, day);
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    u32::try_from(secs).map_err(|_| invalid())
+}
+
+/// Converts days since the Unix epoch into a (year, month, day) date, using
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months are counted from March, so the leap day is at the end of the year.
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The opposite of [`civil_from_days`].
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(format_timestamp(0), "19700101000000");
+        assert_eq!(format_timestamp(1709251199), "20240229235959");
+        assert_eq!(parse_timestamp("20240229235959").unwrap(), 1709251199);
+        assert_eq!(parse_timestamp("1709251199").unwrap(), 1709251199);
+        assert_eq!(parse_timestamp("21060207062815").unwrap(), u32::MAX);
+        assert!(parse_timestamp("21060207062816").is_err());
+        assert!(parse_timestamp("20241301000000").is_err());
+    }
+
+    #[test]
+    fn test_type_bitmaps() {
+        // The example from RFC 4034 section 4.3.
+        let types = vec![
+            RecordType::A,
+            RecordType::Mx,
+            RecordType::Rrsig,
+            RecordType::Nsec,
+            RecordType::Unknown(1234),
+        ];
+        let expected = vec![
+            0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03, // Window 0
+            0x04, 0x1b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0x20, // Window 4
+        ];
+        assert_eq!(type_bitmaps(&types), expected);
+        assert_eq!(parse_type_bitmaps(&expected).unwrap().1, types);
+        // Windows must be in increasing order.
+        assert!(parse_type_bitmaps(&[4, 1, 0x20, 0, 1, 0x40]).is_err());
+    }
+
+    #[test]
+    fn test_key_tag() {
+        // The root zone's 2017 key signing key.
+        let key = DnskeyData {
+            flags: 257,
+            protocol: 3,
+            algorithm: 8,
+            public_key: BASE64
+                .decode(
+                    b"AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLYA4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+                )
+                .unwrap(),
+        };
+        assert_eq!(key.key_tag(), 20326);
+        assert!(key.is_zone_key() && key.is_secure_entry_point());
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    message::{
+        error::{fail, parse_all_rdata, PResult, ParseErrorKind},
+        record::serialize_hex,
+    },
+    parse::many_until_empty,
+};
+use anyhow::{anyhow, Result as AResult};
+use bitvec::prelude::*;
+use nom::{
+    multi::length_data,
+    number::complete::{be_u16, be_u8},
+};
+use serde::Serialize;
+use std::fmt;
+
+/// RFC 1035 limited UDP messages to 512 bytes. EDNS lets clients advertise a bigger buffer,
+/// and 1232 bytes is the size recommended by DNS Flag Day 2020 to avoid IP fragmentation.
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// The record type number reserved for the OPT pseudo-record.
+pub(crate) const OPT_RECORD_TYPE: u16 = 41;
+
+/// EDNS(0) extends DNS messages via an OPT pseudo-record in the additional section.
+/// The OPT record reuses the CLASS and TTL fields of a normal record for its own purposes.
+/// Structure is defined at <https://datatracker.ietf.org/doc/html/rfc6891#section-6.1.2>
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Edns {
+    /// The largest UDP payload the sender can reassemble. Stored in the CLASS field.
+    pub udp_payload_size: u16,
+    /// The upper 8 bits of the extended 12-bit RCODE. The lower 4 bits are in the header.
+    pub extended_rcode: u8,
+    /// EDNS version. Only version 0 is defined.
+    pub version: u8,
+    /// The DNSSEC OK bit: the sender can handle DNSSEC records in the response.
+    pub dnssec_ok: bool,
+    /// Zero or more options, e.g. cookies or client subnets.
+    pub options: Vec<EdnsOption>,
+}
+
+/// One variable-length option from the OPT record's RDATA.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct EdnsOption {
+    /// Assigned by IANA, see <https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-11>
+    pub code: u16,
+    #[serde(serialize_with = "serialize_hex")]
+    pub data: Vec<u8>,
+}
+
+impl Edns {
+    /// EDNS version 0 with no options, advertising the given UDP payload size.
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    /// Serialize the OPT record and write it into the stream of bits.
+    pub fn serialize<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) -> AResult<()> {
+        // The OPT record is always owned by the root domain.
+        bv.extend_from_bitslice(0u8.view_bits::<Msb0>());
+        bv.extend_from_bitslice(OPT_RECORD_TYPE.view_bits::<Msb0>());
+        bv.extend_from_bitslice(self.udp_payload_size.view_bits::<Msb0>());
+        bv.extend_from_bitslice(self.extended_rcode.view_bits::<Msb0>());
+        bv.extend_from_bitslice(self.version.view_bits::<Msb0>());
+        bv.push(self.dnssec_ok);
+        // The rest of the flags field is reserved, and must be zero.
+        bv.extend_from_bitslice(bits![0; 15]);
+
+        let opt_lens = self
+            .options
+            .iter()
+            .map(|opt| {
+                u16::try_from(opt.data.len()).map_err(|_| {
+                    anyhow!(
+                        "EDNS option {} is {} bytes, which is too long",
+                        opt.code,
+                        opt.data.len()
+                    )
+                })
+            })
+            .collect::<AResult<Vec<_>>>()?;
+        let rdata_len: usize = opt_lens.iter().map(|&len| 4 + usize::from(len)).sum();
+        let rdata_len = u16::try_from(rdata_len)
+            .map_err(|_| anyhow!("EDNS options are {rdata_len} bytes, which is too long"))?;
+        bv.extend_from_bitslice(rdata_len.view_bits::<Msb0>());
+        for (opt, opt_len) in self.options.iter().zip(opt_lens) {
+            bv.extend_from_bitslice(opt.code.view_bits::<Msb0>());
+            bv.extend_from_bitslice(opt_len.view_bits::<Msb0>());
+            for byte in &opt.data {
+                bv.extend_from_bitslice(byte.view_bits::<Msb0>());
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses an OPT record. The caller must already have checked that the record type is OPT.
+    pub(crate) fn deserialize(i: &[u8]) -> PResult<'_, Self> {
+        // The name must be the root domain, i.e. a single zero-length label.
+        let (rest, name_len) = be_u8(i)?;
+        if name_len != 0 {
+            return fail(i, ParseErrorKind::OptNotRoot);
+        }
+        let (i, _record_type) = be_u16(rest)?;
+        let (i, udp_payload_size) = be_u16(i)?;
+        let (i, extended_rcode) = be_u8(i)?;
+        let (i, version) = be_u8(i)?;
+        let (i, flags) = be_u16(i)?;
+        let (i, rdata) = length_data(be_u16)(i)?;
+        let options = parse_all_rdata(rdata, many_until_empty(EdnsOption::deserialize))?;
+        let edns = Self {
+            udp_payload_size,
+            extended_rcode,
+            version,
+            dnssec_ok: flags & 0x8000 != 0,
+            options,
+        };
+        Ok((i, edns))
+    }
+}
+
+impl EdnsOption {
+    fn deserialize(i: &[u8]) -> PResult<'_, Self> {
+        let (i, code) = be_u16(i)?;
+        let (i, data) = length_data(be_u16)(i)?;
+        Ok((
+            i,
+            Self {
+                code,
+                data: data.to_vec(),
+            },
+        ))
+    }
+}
+
+impl fmt::Display for Edns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = if self.dnssec_ok { "do" } else { "" };
+        write!(
+            f,
+            "version {}, flags: {flags}; udp: {}",
+            self.version, self.udp_payload_size
+        )?;
+        for opt in &self.options {
+            write!(f, "\nOption {}: ", opt.code)?;
+            for byte in &opt.data {
+                write!(f, "{byte:02x}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_round_trip() {
+        let edns = Edns {
+            udp_payload_size: 4096,
+            extended_rcode: 1,
+            version: 0,
+            dnssec_ok: true,
+            options: vec![EdnsOption {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            }],
+        };
+        let mut bv = BitVec::<u8, Msb0>::new();
+        edns.serialize(&mut bv).unwrap();
+        let mut buf = Vec::new();
+        bv.as_bitslice().read_to_end(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                0, // root name
+                0, 41, // type
+                16, 0, // UDP payload size
+                1, 0, 128, 0, // extended RCODE, version, flags
+                0, 12, // RDLENGTH
+                0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8, // option
+            ]
+        );
+        let (rest, parsed) = Edns::deserialize(&buf).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed, edns);
+    }
+
+    #[test]
+    fn test_option_too_long() {
+        let mut edns = Edns::new(DEFAULT_UDP_PAYLOAD_SIZE);
+        edns.options.push(EdnsOption {
+            code: 65001,
+            data: vec![0; 70000],
+        });
+        let err = edns.serialize(&mut BitVec::<u8, Msb0>::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "EDNS option 65001 is 70000 bytes, which is too long"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Errors from parsing DNS messages.
+use nom::error::{ErrorKind, FromExternalError};
+use std::fmt;
+
+/// A DNS message couldn't be parsed.
+/// Says where in the message the problem was, and what the problem was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Offset of the problem, in bytes from the start of the message.
+    pub offset: usize,
+    /// Which section of the message was being parsed.
+    pub section: Section,
+    /// What went wrong.
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (in the {} section, at byte {})",
+            self.kind, self.section, self.offset
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The sections of a DNS message, in the order they appear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Header,
+    Question,
+    Answer,
+    Authority,
+    Additional,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Header => "header",
+            Self::Question => "question",
+            Self::Answer => "answer",
+            Self::Authority => "authority",
+            Self::Additional => "additional",
+        };
+        s.fmt(f)
+    }
+}
+
+/// Everything that can go wrong while parsing a DNS message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The message ended in the middle of a field.
+    UnexpectedEnd,
+    /// A record or question had a class that dingo doesn't know.
+    UnknownClass(u16),
+    /// A label's length byte used one of the reserved label types, i.e. it was over 63
+    /// but didn't start with the two bits that mark a compression pointer.
+    LabelTooLong(u8),
+    /// A name was longer than the 255 bytes RFC 1035 allows.
+    NameTooLong,
+    /// A compression pointer pointed outside the message.
+    PointerOutOfBounds(usize),
+    /// Following compression pointers led back to a pointer that was already followed.
+    PointerLoop(usize),
+    /// A name used too many compression pointers.
+    TooManyPointers,
+    /// The RDATA ended before the record type's fields were all read.
+    TruncatedRdata,
+    /// The RDATA had bytes left over after all the record type's fields were read.
+    RdataTooLong,
+    /// A TTL was over the max (2^31 - 1) allowed by RFC 2181.
+    TtlTooLarge(u32),
+    /// An OPT pseudo-record wasn't owned by the root domain.
+    OptNotRoot,
+    /// An OPT pseudo-record was somewhere other than the additional section.
+    OptOutsideAdditional,
+    /// The additional section had more than one OPT pseudo-record.
+    DuplicateOpt,
+    /// An NSEC or NSEC3 type bitmap's windows were out of order, or had an invalid length.
+    InvalidTypeBitmap,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "message ended unexpectedly"),
+            Self::UnknownClass(class) => write!(f, "unknown class {class}"),
+            Self::LabelTooLong(len) => {
+                write!(
+                    f,
+                    "DNS name labels must be <=63 bytes but this one is {len}"
+                )
+            }
+            Self::NameTooLong => write!(f, "name is over the max of 255 bytes"),
+            Self::PointerOutOfBounds(ptr) => {
+                write!(f, "compression pointer to {ptr} is outside the message")
+            }
+            Self::PointerLoop(ptr) => write!(f, "compression pointer to {ptr} forms a loop"),
+            Self::TooManyPointers => write!(f, "too many compression pointers"),
+            Self::TruncatedRdata => write!(f, "record data is too short for its type"),
+            Self::RdataTooLong => write!(f, "record data is too long for its type"),
+            Self::TtlTooLarge(ttl) => write!(f, "TTL {ttl} is too large"),
+            Self::OptNotRoot => write!(f, "OPT record must be owned by the root domain"),
+            Self::OptOutsideAdditional => {
+                write!(f, "OPT record must be in the additional section")
+            }
+            Self::DuplicateOpt => write!(f, "message has more than one OPT record"),
+            Self::InvalidTypeBitmap => write!(f, "type bitmap is invalid"),
+        }
+    }
+}
+
+/// The error type used by the nom parsers for DNS messages. It keeps the remaining input, so
+/// that the offset can be worked out once parsing has failed.
+#[derive(Debug)]
+pub(crate) struct NomError<I> {
+    pub input: I,
+    pub kind: ParseErrorKind,
+}
+
+/// Result of parsing DNS message bytes.
+pub(crate) type PResult<'i, O> = nom::IResult<&'i [u8], O, NomError<&'i [u8]>>;
+
+impl<I> nom::error::ParseError<I> for NomError<I> {
+    fn from_error_kind(input: I, _kind: ErrorKind) -> Self {
+        // The parsers only use nom's generic errors when they run out of input.
+        // Everything else gets a specific error kind, see `fail`.
+        Self {
+            input,
+            kind: ParseErrorKind::UnexpectedEnd,
+        }
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<I> FromExternalError<I, ParseErrorKind> for NomError<I> {
+    fn from_external_error(input: I, _kind: ErrorKind, e: ParseErrorKind) -> Self {
+        Self { input, kind: e }
+    }
+}
+
+/// The header is parsed bit-by-bit, so its errors need converting back to bytes.
+impl<'i> nom::ErrorConvert<NomError<&'i [u8]>> for NomError<(&'i [u8], usize)> {
+    fn convert(self) -> NomError<&'i [u8]> {
+        NomError {
+            input: self.input.0,
+            kind: self.kind,
+        }
+    }
+}
+
+/// Stop parsing, because the input at `input` is invalid.
+pub(crate) fn fail<I, O>(input: I, kind: ParseErrorKind) -> nom::IResult<I, O, NomError<I>> {
+    Err(nom::Err::Failure(NomError { input, kind }))
+}
+
+/// Runs the parser over the whole of some RDATA. Running out of RDATA halfway through a
+/// field, or having some RDATA left over at the end, are both errors.
+pub(crate) fn parse_all_rdata<'i, O>(
+    rdata: &'i [u8],
+    mut parser: impl FnMut(&'i [u8]) -> PResult<'i, O>,
+) -> Result<O, nom::Err<NomError<&'i [u8]>>> {
+    let (rest, out) = parser(rdata).map_err(|e| {
+        e.map(|mut e| {
+            if e.kind == ParseErrorKind::UnexpectedEnd {
+                e.kind = ParseErrorKind::TruncatedRdata;
+            }
+            e
+        })
+    })?;
+    if !rest.is_empty() {
+        return Err(nom::Err::Failure(NomError {
+            input: rest,
+            kind: ParseErrorKind::RdataTooLong,
+        }));
+    }
+    Ok(out)
+}
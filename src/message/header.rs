@@ -1,28 +1,36 @@
-use crate::message::parser_utils::*;
+use crate::message::{
+    error::{NomError, PResult},
+    parser_utils::*,
+};
 use bitvec::prelude::*;
 use nom::IResult;
+use serde::{Serialize, Serializer};
 
 /// RFC 1035 defines DNS headers as 12 bytes long.
 const EXPECTED_SIZE_BYTES: usize = 12;
 
 /// All DNS messages start with a Header (both queries and responses!)
 /// Structure is defined at <https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1>
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 pub struct Header {
     /// A 16 bit identifier assigned by the program that generates any kind of query.  This identifier is copied the corresponding reply and can be used by the requester to match up replies to outstanding queries.
     pub id: u16,
     /// A one bit field that specifies whether this message is a query (0), or a response (1).
-    is_query: bool,
+    pub is_response: bool,
     /// A four bit field that specifies kind of query in this message.  This value is set by the originator of a query and copied into the response.
-    opcode: Opcode,
+    pub opcode: Opcode,
     /// This bit is valid in responses, and specifies that the responding name server is an authority for the domain name in question section. Note that the contents of the answer section may have multiple owner names because of aliases. The AA bit corresponds to the name which matches the query name, or the first owner name in the answer section.
-    authoritative_answer: bool,
+    pub authoritative_answer: bool,
     /// Specifies that this message was truncated due to length greater than that permitted on the transmission channel.
-    truncation: bool,
+    pub truncation: bool,
     /// This bit may be set in a query and is copied into the response.  If RD is set, it directs the name server to pursue the query recursively. Recursive query support is optional.
-    recursion_desired: bool,
+    pub recursion_desired: bool,
     /// This be (sic) is set or cleared in a response, and denotes whether recursive query support is available in the name server.
-    recursion_available: bool,
+    pub recursion_available: bool,
+    /// Authentic Data: set in a response when the resolver considers all RRsets in the Answer section and relevant negative response RRs in the Authority section to be authentic. See <https://datatracker.ietf.org/doc/html/rfc4035#section-3.2.3>
+    pub authentic_data: bool,
+    /// Checking Disabled: set in a query to indicate that pending (non-authenticated) data is acceptable to the resolver sending the query. See <https://datatracker.ietf.org/doc/html/rfc4035#section-3.2.2>
+    pub checking_disabled: bool,
     pub resp_code: ResponseCode,
     /// Number of entries in the question section.
     pub question_count: u16,
@@ -39,12 +47,14 @@ impl Header {
     pub fn new_query(id: u16) -> Self {
         Self {
             id,
-            is_query: false,
+            is_response: false,
             opcode: Opcode::Query,
             authoritative_answer: Default::default(),
             truncation: false,
             recursion_desired: true,
             recursion_available: Default::default(),
+            authentic_data: false,
+            checking_disabled: false,
             resp_code: ResponseCode::NoError, // This doesn't matter for a query
             // In a query, there will be 1 question and no records.
             question_count: 1,
@@ -58,7 +68,7 @@ impl Header {
     pub fn serialize<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) {
         let initial_length_bits = bv.len();
         bv.extend_from_bitslice(self.id.view_bits::<Msb0>());
-        bv.push(self.is_query);
+        bv.push(self.is_response);
         self.opcode.serialize(bv);
         bv.push(self.authoritative_answer);
         bv.push(self.truncation);
@@ -66,7 +76,10 @@ impl Header {
         bv.push(self.recursion_available);
         // the Z field, reserved for future use.
         // Must be zero in all queries and responses.
-        bv.extend_from_bitslice(bits![0; 3]);
+        // It used to be three bits, but RFC 2535 took the last two for DNSSEC.
+        bv.push(false);
+        bv.push(self.authentic_data);
+        bv.push(self.checking_disabled);
         self.resp_code.serialize(bv);
         bv.extend_from_bitslice(self.question_count.view_bits::<Msb0>());
         bv.extend_from_bitslice(self.answer_count.view_bits::<Msb0>());
@@ -76,9 +89,12 @@ impl Header {
         assert_eq!(bits_written, 8 * EXPECTED_SIZE_BYTES);
     }
 
-    pub fn deserialize(i: BitInput) -> IResult<BitInput, Self> {
-        use nom::combinator::map_res;
+    /// Parse a header from the start of a DNS message.
+    pub(crate) fn parse(i: &[u8]) -> PResult<'_, Self> {
+        nom::bits::bits(Self::deserialize)(i)
+    }
 
+    pub(crate) fn deserialize(i: BitInput) -> IResult<BitInput, Self, NomError<BitInput>> {
         // From RFC 1035, section 4.1.1
         // The header contains the following fields:
         //
@@ -87,7 +103,7 @@ impl Header {
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
         // |                      ID                       |
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
-        // |QR|   Opcode  |AA|TC|RD|RA|   Z    |   RCODE   |
+        // |QR|   Opcode  |AA|TC|RD|RA| Z|AD|CD|   RCODE   |
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
         // |                    QDCOUNT                    |
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
@@ -99,30 +115,31 @@ impl Header {
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
         let (i, id) = take_u16(i)?;
         let (i, qr) = take_bit(i)?;
-        let (i, opcode) = map_res(take_nibble, Opcode::try_from)(i)?;
+        let (i, opcode) = take_nibble(i)?;
         let (i, aa) = take_bit(i)?;
         let (i, tc) = take_bit(i)?;
         let (i, rd) = take_bit(i)?;
-        let (mut i, ra) = take_bit(i)?;
-        for _ in 0..3 {
-            let z;
-            (i, z) = take_bit(i)?;
-            assert!(!z);
-        }
-        let (i, rcode) = map_res(take_nibble, ResponseCode::try_from)(i)?;
+        let (i, ra) = take_bit(i)?;
+        // The Z bit must be zero, but be liberal in what we accept, and ignore it.
+        let (i, _z) = take_bit(i)?;
+        let (i, ad) = take_bit(i)?;
+        let (i, cd) = take_bit(i)?;
+        let (i, rcode) = take_nibble(i)?;
         let (i, qdcount) = take_u16(i)?;
         let (i, ancount) = take_u16(i)?;
         let (i, nscount) = take_u16(i)?;
         let (i, arcount) = take_u16(i)?;
         let header = Header {
             id,
-            is_query: qr,
-            opcode,
+            is_response: qr,
+            opcode: opcode.into(),
             authoritative_answer: aa,
             truncation: tc,
             recursion_desired: rd,
             recursion_available: ra,
-            resp_code: rcode,
+            authentic_data: ad,
+            checking_disabled: cd,
+            resp_code: rcode.into(),
             question_count: qdcount,
             answer_count: ancount,
             name_server_count: nscount,
@@ -134,43 +151,68 @@ impl Header {
 
 /// A four bit field that specifies kind of query in this message.
 /// This value is set by the originator of a query and copied into the response.
-#[derive(Debug)]
-enum Opcode {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
     /// 0: a standard query (QUERY)
     Query,
     /// 1: an inverse query (IQUERY)
     InverseQuery,
     /// 2: a server status request (STATUS)
     Status,
+    /// Any other opcode, e.g. 4 (NOTIFY) or 5 (UPDATE), identified by its number.
+    Other(u8),
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Query => "QUERY",
+            Self::InverseQuery => "IQUERY",
+            Self::Status => "STATUS",
+            Self::Other(num) => return write!(f, "OPCODE{num}"),
+        };
+        s.fmt(f)
+    }
 }
 
-impl TryFrom<u8> for Opcode {
-    type Error = anyhow::Error;
+/// Opcodes are written as their name, e.g. "QUERY" or "OPCODE5", like dig does.
+impl Serialize for Opcode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        let op = match value {
+impl From<u8> for Opcode {
+    fn from(value: u8) -> Self {
+        match value {
             0 => Self::Query,
             1 => Self::InverseQuery,
             2 => Self::Status,
-            other => anyhow::bail!("Unknown opcode {other}"),
-        };
-        Ok(op)
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        match opcode {
+            Opcode::Query => 0,
+            Opcode::InverseQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Other(num) => num,
+        }
     }
 }
 
 impl Opcode {
     fn serialize<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) {
-        match self {
-            Self::Query => bv.extend_from_bitslice(bits![u8, Msb0; 0; 4]),
-            Self::InverseQuery => bv.extend_from_bitslice(bits![u8, Msb0; 0, 0, 0, 1]),
-            Self::Status => bv.extend_from_bitslice(bits![u8, Msb0; 0, 0, 1, 0]),
-        }
+        // Only the low four bits fit in the header.
+        bv.extend_from_bitslice(&u8::from(*self).view_bits::<Msb0>()[4..]);
     }
 }
 
 /// This field is set by the DNS resolver and indicates if the DNS query was successful or erroneous.
-#[derive(Debug)]
-#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResponseCode {
     NoError,
     /// The name server was unable to interpret the query
@@ -193,18 +235,35 @@ pub enum ResponseCode {
     /// or a name server may not wish to perform
     /// a particular operation (e.g., zone
     Refused,
+    /// Any other response code, e.g. 9 (NOTAUTH), identified by its number.
+    Other(u8),
 }
 
 impl ResponseCode {
-    fn serialize<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) {
-        match self {
-            Self::NoError => bv.extend_from_bitslice(bits![u8, Msb0; 0; 4]),
-            Self::FormatError => bv.extend_from_bitslice(bits![u8, Msb0; 0, 0, 0, 1]),
-            Self::ServerFailure => bv.extend_from_bitslice(bits![u8, Msb0; 0, 0, 1, 0]),
-            Self::NameError => bv.extend_from_bitslice(bits![u8, Msb0; 0, 0, 1, 1]),
-            Self::NotImplemented => bv.extend_from_bitslice(bits![u8, Msb0; 0, 1, 0, 0]),
-            Self::Refused => bv.extend_from_bitslice(bits![u8, Msb0; 0, 1, 0, 1]),
+    /// The short name for the response code, as used by `dig`, e.g. NXDOMAIN.
+    pub fn mnemonic(&self) -> String {
+        let s = match self {
+            Self::NoError => "NOERROR",
+            Self::FormatError => "FORMERR",
+            Self::ServerFailure => "SERVFAIL",
+            Self::NameError => "NXDOMAIN",
+            Self::NotImplemented => "NOTIMP",
+            Self::Refused => "REFUSED",
+            Self::Other(num) => return format!("RCODE{num}"),
         };
+        s.to_owned()
+    }
+
+    fn serialize<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) {
+        // Only the low four bits fit in the header.
+        bv.extend_from_bitslice(&u8::from(*self).view_bits::<Msb0>()[4..]);
+    }
+}
+
+/// Response codes are written as their mnemonic, e.g. "NXDOMAIN" or "RCODE9".
+impl Serialize for ResponseCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.mnemonic())
     }
 }
 
@@ -216,26 +275,38 @@ impl std::fmt::Display for ResponseCode {
             Self::ServerFailure => "The name server was unable to process this query due to a problem with the name server.",
             Self::NameError => "Domain name referenced in the query does not exist",
             Self::NotImplemented => "The name server does not support the requested kind of query",
-            Self::Refused => "The name server refuses to perform the specified operation for policy reasons.  For example, a name server may not wish to provide the information to the particular requester, or a name server may not wish to perform a particular operation"
+            Self::Refused => "The name server refuses to perform the specified operation for policy reasons.  For example, a name server may not wish to provide the information to the particular requester, or a name server may not wish to perform a particular operation",
+            Self::Other(num) => return write!(f, "Response code {num} (RCODE{num})"),
         };
         s.fmt(f)
     }
 }
 
-impl TryFrom<u8> for ResponseCode {
-    type Error = anyhow::Error;
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        let op = match value {
+impl From<u8> for ResponseCode {
+    fn from(value: u8) -> Self {
+        match value {
             0 => Self::NoError,
             1 => Self::FormatError,
             2 => Self::ServerFailure,
             3 => Self::NameError,
             4 => Self::NotImplemented,
             5 => Self::Refused,
-            other => anyhow::bail!("Unknown response code {other}"),
-        };
-        Ok(op)
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<ResponseCode> for u8 {
+    fn from(rcode: ResponseCode) -> Self {
+        match rcode {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormatError => 1,
+            ResponseCode::ServerFailure => 2,
+            ResponseCode::NameError => 3,
+            ResponseCode::NotImplemented => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::Other(num) => num,
+        }
     }
 }
 
@@ -263,11 +334,43 @@ mod tests {
             99, 104, 97, 108, 109, 101, 114, 115, 3, 99, 111, 109, 0, 0, 1, 0, 1,
         ];
 
-        pub fn deser(i: &[u8]) -> IResult<&[u8], Header> {
-            nom::bits::bits(Header::deserialize)(i)
-        }
-        let (_i, h): (&[u8], Header) = deser(&i).unwrap();
+        let (_i, h): (&[u8], Header) = Header::parse(&i).unwrap();
         assert_eq!(h.id, 33);
         assert_eq!(h.resp_code, ResponseCode::ServerFailure);
     }
+
+    #[test]
+    fn test_header_bits() {
+        // AD and CD are the last two bits of what RFC 1035 called the Z field.
+        let mut header = Header::new_query(1);
+        header.checking_disabled = true;
+        let mut bv = BitVec::<u8, Msb0>::new();
+        header.serialize(&mut bv);
+        let mut bytes = bv.into_vec();
+        assert_eq!(bytes[3], 0b0001_0000);
+
+        bytes[3] = 0b0010_0000;
+        let (_, header) = Header::parse(&bytes).unwrap();
+        assert!(header.authentic_data);
+        assert!(!header.checking_disabled);
+    }
+
+    #[test]
+    fn test_other_opcode_and_rcode() {
+        // An UPDATE (opcode 5) response with NOTAUTH (rcode 9).
+        let i = [0, 7, 0b1010_1000, 0b0000_1001, 0, 0, 0, 0, 0, 0, 0, 0];
+        let (_, h) = Header::parse(&i).unwrap();
+        assert_eq!(h.opcode, Opcode::Other(5));
+        assert_eq!(h.resp_code, ResponseCode::Other(9));
+        assert_eq!(h.opcode.to_string(), "OPCODE5");
+        assert_eq!(h.resp_code.mnemonic(), "RCODE9");
+        assert_eq!(
+            serde_json::to_value(&h).unwrap()["resp_code"],
+            serde_json::json!("RCODE9")
+        );
+
+        let mut bv = BitVec::<u8, Msb0>::new();
+        h.serialize(&mut bv);
+        assert_eq!(bv.into_vec(), i);
+    }
 }
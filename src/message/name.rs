@@ -0,0 +1,293 @@
+//! Converting domain names between their text form and their wire format.
+//!
+//! Names are stored as text, in the "presentation format" that zone files and `dig` use, e.g.
+//! `blog.adamchalmers.com.`. Labels on the wire can contain any byte, even dots, so in text
+//! those bytes get escaped: `\.` for a dot, `\\` for a backslash, and `\DDD` (decimal) for
+//! anything that isn't printable ASCII.
+use crate::message::{MAX_LABEL_BYTES, MAX_NAME_BYTES};
+use anyhow::{anyhow, Result as AResult};
+use bitvec::prelude::*;
+use std::collections::HashMap;
+
+/// Formats the bytes of one label as text, escaping any special characters.
+pub fn escape_label(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            b'!'..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\{byte:03}")),
+        }
+    }
+    out
+}
+
+/// Splits a name into its labels, without unescaping them.
+/// The root label (i.e. the trailing dot) isn't included.
+pub fn split_labels(name: &str) -> Vec<&str> {
+    if name == "." {
+        return Vec::new();
+    }
+    let mut labels = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, ch) in name.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '.' => {
+                labels.push(&name[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < name.len() {
+        labels.push(&name[start..]);
+    }
+    labels
+}
+
+/// Is `name` equal to `zone`, or somewhere underneath it? Names are compared case-insensitively.
+/// The root zone can be written as either `.` or an empty string.
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    let name = split_labels(name);
+    let zone = split_labels(zone);
+    name.len() >= zone.len()
+        && name
+            .iter()
+            .rev()
+            .zip(zone.iter().rev())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Are the two names the same? Names are compared case-insensitively, and the trailing dot
+/// is optional.
+pub fn same_name(a: &str, b: &str) -> bool {
+    let (a, b) = (split_labels(a), split_labels(b));
+    a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Turns one escaped label back into the bytes it represents.
+pub fn unescape_label(label: &str) -> AResult<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(label.len());
+    let mut chars = label.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            if !ch.is_ascii() {
+                anyhow::bail!("Label {label} isn't ASCII, which DNS names must be");
+            }
+            bytes.push(ch as u8);
+            continue;
+        }
+        match chars.next() {
+            Some(digit) if digit.is_ascii_digit() => {
+                let digits: String = std::iter::once(digit)
+                    .chain(chars.by_ref().take(2))
+                    .collect();
+                let byte = digits
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|_| digits.len() == 3)
+                    .ok_or_else(|| anyhow!("Invalid escape \\{digits} in label {label}"))?;
+                bytes.push(byte);
+            }
+            Some(escaped) if escaped.is_ascii() => bytes.push(escaped as u8),
+            _ => anyhow::bail!("Invalid escape at the end of label {label}"),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Converts a name into the bytes of each of its labels, checking they're not too long.
+/// Relative names are treated as if they were fully-qualified.
+pub fn name_to_labels(name: &str) -> AResult<Vec<Vec<u8>>> {
+    let labels = split_labels(name)
+        .into_iter()
+        .map(unescape_label)
+        .collect::<AResult<Vec<_>>>()?;
+    if let Some(label) = labels.iter().find(|label| label.len() > MAX_LABEL_BYTES) {
+        anyhow::bail!(
+            "Label {} is {} bytes, which is over the max of {MAX_LABEL_BYTES}",
+            escape_label(label),
+            label.len()
+        );
+    }
+    if labels.iter().any(|label| label.is_empty()) {
+        anyhow::bail!("Name {name} has an empty label");
+    }
+    // Every label has a length byte, and there's also the zero-length root label at the end.
+    let wire_len: usize = labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1;
+    if wire_len > MAX_NAME_BYTES {
+        anyhow::bail!("Name {name} is {wire_len} bytes, which is over the max of {MAX_NAME_BYTES}");
+    }
+    Ok(labels)
+}
+
+/// Writes names into a message, using Message Compression
+/// (<https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.4>) to point back to names
+/// that were already written, instead of repeating them.
+#[derive(Default)]
+pub struct NameCompressor {
+    /// Where each name (or suffix of a name) was written, as an offset from the start of the
+    /// message. Keys are lowercase, because DNS names are case-insensitive.
+    offsets: HashMap<String, u16>,
+    /// Write every name uncompressed and in lowercase, for DNSSEC.
+    canonical: bool,
+}
+
+impl NameCompressor {
+    /// Writes names in the canonical form that DNSSEC signs: uncompressed, and in lowercase.
+    /// See <https://datatracker.ietf.org/doc/html/rfc4034#section-6.2>
+    pub fn canonical() -> Self {
+        Self {
+            offsets: HashMap::new(),
+            canonical: true,
+        }
+    }
+
+    /// Writes a name, using a pointer to an earlier copy of (part of) it if possible.
+    /// The bitvec must contain the message from its start, so offsets are right.
+    pub fn write_name<T: BitStore>(&mut self, name: &str, bv: &mut BitVec<T, Msb0>) -> AResult<()> {
+        self.write(name, bv, true, self.canonical)
+    }
+
+    /// Writes a name without compression. Some record types (e.g. SRV) require this.
+    /// Later names can still point back to this one, though.
+    pub fn write_name_uncompressed<T: BitStore>(
+        &mut self,
+        name: &str,
+        bv: &mut BitVec<T, Msb0>,
+    ) -> AResult<()> {
+        self.write(name, bv, false, self.canonical)
+    }
+
+    /// Writes a name without compression, and in its original case even in canonical form.
+    /// NSEC's next domain name is the only name like this, see
+    /// <https://datatracker.ietf.org/doc/html/rfc6840#section-5.1>
+    pub fn write_name_keeping_case<T: BitStore>(
+        &mut self,
+        name: &str,
+        bv: &mut BitVec<T, Msb0>,
+    ) -> AResult<()> {
+        self.write(name, bv, false, false)
+    }
+
+    fn write<T: BitStore>(
+        &mut self,
+        name: &str,
+        bv: &mut BitVec<T, Msb0>,
+        compress: bool,
+        lowercase: bool,
+    ) -> AResult<()> {
+        let labels = name_to_labels(name)?;
+        for i in 0..labels.len() {
+            let suffix = labels[i..]
+                .iter()
+                .map(|label| escape_label(label).to_ascii_lowercase())
+                .collect::<Vec<_>>()
+                .join(".");
+            if compress && !self.canonical {
+                if let Some(offset) = self.offsets.get(&suffix) {
+                    const POINTER_HEADER: u16 = 0b11000000 << 8;
+                    bv.extend_from_bitslice((POINTER_HEADER | offset).view_bits::<Msb0>());
+                    return Ok(());
+                }
+            }
+            // Pointers only have 14 bits, so names further into the message can't be targets.
+            let offset = bv.len() / 8;
+            if offset < 0x4000 {
+                self.offsets.entry(suffix).or_insert(offset as u16);
+            }
+            let label = &labels[i];
+            bv.extend_from_bitslice((label.len() as u8).view_bits::<Msb0>());
+            for byte in label {
+                let byte = if lowercase {
+                    byte.to_ascii_lowercase()
+                } else {
+                    *byte
+                };
+                bv.extend_from_bitslice(byte.view_bits::<Msb0>());
+            }
+        }
+        // The root label.
+        bv.extend_from_bitslice(0u8.view_bits::<Msb0>());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_escaping_round_trip() {
+        let label = b"a.b\\c d\x07";
+        let escaped = escape_label(label);
+        assert_eq!(escaped, r"a\.b\\c\032d\007");
+        assert_eq!(unescape_label(&escaped).unwrap(), label);
+        assert_eq!(split_labels(r"a\.b.com."), vec![r"a\.b", "com"]);
+        assert_eq!(split_labels("."), Vec::<&str>::new());
+        assert!(unescape_label(r"bad\25").is_err());
+    }
+
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("www.Example.com.", "example.COM."));
+        assert!(is_subdomain("example.com.", "example.com"));
+        assert!(is_subdomain("example.com.", "."));
+        assert!(is_subdomain(".", ""));
+        assert!(!is_subdomain("example.com.", "www.example.com."));
+        assert!(!is_subdomain("badexample.com.", "example.com."));
+        assert!(same_name("Example.com", "example.COM."));
+        assert!(!same_name("example.com.", "www.example.com."));
+    }
+
+    #[test]
+    fn test_canonical() {
+        // Canonical names are never compressed, and always lowercase.
+        let mut canonical = BitVec::<u8, Msb0>::new();
+        let mut names = NameCompressor::canonical();
+        names.write_name("WWW.Example.", &mut canonical).unwrap();
+        names.write_name("example.", &mut canonical).unwrap();
+        assert_eq!(
+            canonical.into_vec(),
+            b"\x03www\x07example\x00\x07example\x00".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut bv = BitVec::<u8, Msb0>::new();
+        let mut names = NameCompressor::default();
+        names.write_name("blog.example.com.", &mut bv).unwrap();
+        names.write_name("WWW.Example.com.", &mut bv).unwrap();
+        names.write_name("example.com.", &mut bv).unwrap();
+        names.write_name_uncompressed("com.", &mut bv).unwrap();
+        let mut buf = Vec::new();
+        bv.as_bitslice().read_to_end(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                4, b'b', b'l', b'o', b'g', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c',
+                b'o', b'm', 0, // blog.example.com.
+                3, b'W', b'W', b'W', 0b11000000, 5, // WWW, then pointer to example.com.
+                0b11000000, 5, // pointer to example.com.
+                3, b'c', b'o', b'm', 0, // com., uncompressed
+            ]
+        );
+    }
+
+    #[test]
+    fn test_name_too_long() {
+        let long_label = "a".repeat(64);
+        assert!(name_to_labels(&format!("{long_label}.com.")).is_err());
+        let long_name = format!("{}.", vec!["a".repeat(63); 4].join("."));
+        assert!(name_to_labels(&long_name).is_err());
+        assert!(name_to_labels("a..com.").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use nom::{bits::complete::take, IResult};
+use nom::{bits::complete::take, error::ParseError, IResult};
 
 /// Newtype around a very common type in Nom.
 /// Represents a binary sequence which can be parsed one bit at a time.
@@ -6,30 +6,30 @@ use nom::{bits::complete::take, IResult};
 /// is currently being read.
 ///
 /// For example, you might start with 16 bits, pointing at the 0th bit:
-///```
+///```text
 /// 1111000011001100
 /// ^
 /// ```
 /// Nom represents this using the BitInput type as:
-/// ```
+/// ```text
 /// ([0b11110000, 0b11001100], 0)
 ///     ^
 /// ```
 /// Lets say you parsed 3 bits from there. After that, the BitInput would be
 ///
-/// ```
+/// ```text
 /// ([0b11110000, 0b11001100], 3)
 ///        ^
 /// ```
 /// After reading another six bits, the input would have advanced past the first byte:
 ///
-/// ```
+/// ```text
 /// ([0b11110000, 0b11001100], 9)
 ///                  ^
 /// ```
 /// Because the first byte will never be used again, Nom optimizes by dropping the first byte
 ///
-/// ```
+/// ```text
 ///  ([0b11001100], 1)
 ///       ^
 /// ```
@@ -39,17 +39,21 @@ pub type BitInput<'a> = (&'a [u8], usize);
 /// Parse into a uint with most significant bit first.
 /// Add 0000 as padding to the most significant bits to the output number to make it
 /// fit into a u8.
-pub fn take_nibble(i: BitInput) -> IResult<BitInput, u8> {
+pub fn take_nibble<'a, E: ParseError<BitInput<'a>>>(
+    i: BitInput<'a>,
+) -> IResult<BitInput<'a>, u8, E> {
     take(4u8)(i)
 }
 
 /// Take 16 bits from the BitInput, parse into a uint with most significant bit first..
-pub fn take_u16(i: BitInput) -> IResult<BitInput, u16> {
+pub fn take_u16<'a, E: ParseError<BitInput<'a>>>(i: BitInput<'a>) -> IResult<BitInput<'a>, u16, E> {
     take(16u8)(i)
 }
 
 /// Takes one bit from the BitInput.
-pub fn take_bit(i: BitInput) -> IResult<BitInput, bool> {
+pub fn take_bit<'a, E: ParseError<BitInput<'a>>>(
+    i: BitInput<'a>,
+) -> IResult<BitInput<'a>, bool, E> {
     let (i, bit): (BitInput, u8) = take(1u8)(i)?;
     Ok((i, bit != 0))
 }
@@ -1,71 +1,72 @@
-use crate::{parse::parse_labels_then_zero, Class, RecordType};
-use anyhow::{anyhow, Result as AResult};
+use crate::{
+    message::{
+        error::{fail, PResult, ParseErrorKind},
+        name::NameCompressor,
+    },
+    parse::parse_labels_then_zero,
+    Class, RecordType,
+};
+use anyhow::Result as AResult;
 use bitvec::prelude::*;
-use nom::{combinator::map_res, number::complete::be_u16, IResult};
+use nom::{combinator::map, number::complete::be_u16};
+use serde::Serialize;
 use std::fmt;
 
-const LABEL_TOO_LONG: &str = "is too long (must be <64 chars)";
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 pub struct Entry {
-    labels: Vec<String>,
-    record_type: RecordType,
-    record_qclass: Class,
+    /// The domain name being asked about, e.g. `adamchalmers.com.`
+    pub name: String,
+    pub record_type: RecordType,
+    pub record_qclass: Class,
 }
 
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = format!("{}: {}", self.record_type, &self.labels.join("."));
+        let s = format!("{}: {}", self.record_type, &self.name);
         s.fmt(f)
     }
 }
 
 impl Entry {
-    pub(crate) fn new(labels: Vec<String>, record_type: RecordType) -> Self {
+    pub fn new(name: String, record_type: RecordType) -> Self {
         Self {
-            labels,
+            name,
             record_type,
             record_qclass: Class::IN,
         }
     }
 
-    pub fn serialize<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) -> AResult<()> {
-        self.serialize_qname(bv)?;
-        self.record_type.serialize(bv);
-        self.record_qclass.serialize(bv);
-        Ok(())
-    }
-
-    fn serialize_qname<T: BitStore>(&self, bv: &mut BitVec<T, Msb0>) -> AResult<()> {
+    pub fn serialize<T: BitStore>(
+        &self,
+        names: &mut NameCompressor,
+        bv: &mut BitVec<T, Msb0>,
+    ) -> AResult<()> {
         // QNAME   a domain name represented as a sequence of labels, where
         //         each label consists of a length octet followed by that
         //         number of octets.
-        for label in &self.labels {
-            // The mapping of domain names to labels is defined in RFC 1035:
-            // 2.3.1. Preferred name syntax
-            let len = label.len();
-            let fmt = format!("Label {label} {LABEL_TOO_LONG}");
-            let len = u8::try_from(len).map_err(|_| anyhow!("{fmt}"))?;
-            if len >= 64 {
-                anyhow::bail!("{fmt}")
-            }
-            bv.extend_from_bitslice(len.view_bits::<Msb0>());
-            label
-                .chars()
-                .map(|ch| ch.try_into().unwrap())
-                .for_each(|byte: u8| bv.extend_from_bitslice(byte.view_bits::<Msb0>()));
-        }
+        names.write_name(&self.name, bv)?;
+        self.record_type.serialize(bv);
+        self.record_qclass.serialize(bv);
         Ok(())
     }
 
-    pub fn deserialize(i: &[u8]) -> IResult<&[u8], Self> {
+    pub(crate) fn deserialize(i: &[u8]) -> PResult<'_, Self> {
         let (i, labels) = parse_labels_then_zero(i)?;
-        let (i, record_type) = map_res(be_u16, RecordType::try_from)(i)?;
-        let (i, record_qclass) = map_res(be_u16, Class::try_from)(i)?;
+        // The last label is always the empty root label, so this puts a dot on the end.
+        let name = match labels.join(".") {
+            root if root.is_empty() => ".".to_owned(),
+            name => name,
+        };
+        let (i, record_type) = map(be_u16, RecordType::from)(i)?;
+        let (rest, class_num) = be_u16(i)?;
+        let Ok(record_qclass) = Class::try_from(class_num) else {
+            return fail(i, ParseErrorKind::UnknownClass(class_num));
+        };
+        let i = rest;
         Ok((
             i,
             Self {
-                labels,
+                name,
                 record_type,
                 record_qclass,
             },
@@ -81,17 +82,11 @@ mod tests {
 
     #[test]
     fn test_serialize_entry() {
-        let entry = Entry {
-            labels: vec![
-                String::from("adamchalmers"),
-                String::from("com"),
-                String::from(""),
-            ],
-            record_type: RecordType::A,
-            record_qclass: Class::IN,
-        };
+        let entry = Entry::new("adamchalmers.com.".to_owned(), RecordType::A);
         let mut bv = BitVec::<u8, Msb0>::new();
-        entry.serialize(&mut bv).unwrap();
+        entry
+            .serialize(&mut NameCompressor::default(), &mut bv)
+            .unwrap();
         let mut buf = Vec::new();
         let expected_bytes_read = "adamchalmers".len() + 1 + // First label
         "com".len() + 1 // Second label
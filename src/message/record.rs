@@ -1,53 +1,360 @@
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
 
-use crate::{Class, RecordType};
+use anyhow::{anyhow, Result as AResult};
+use bitvec::prelude::*;
+use rand::Rng;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 
-#[derive(Debug)]
-#[cfg_attr(test, derive(Eq, PartialEq))]
+use crate::{
+    message::{
+        dnssec::{
+            type_bitmaps, DnskeyData, DsData, Nsec3Data, Nsec3ParamData, NsecData, RrsigData,
+        },
+        name::{split_labels, unescape_label, NameCompressor},
+    },
+    Class, RecordType,
+};
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 pub struct Record {
     pub name: String,
     pub class: Class,
     pub ttl: u32,
+    #[serde(flatten)]
     pub data: RecordData,
 }
 
 impl Record {
     pub fn as_dns_response(&self) -> String {
         let rdata = match &self.data {
-            RecordData::A(ipv4) => ipv4.to_string(),
-            RecordData::Aaaa(ipv6) => ipv6.to_string(),
-            RecordData::Cname(name) => name.to_string(),
-            RecordData::Soa(soa) => format!("{soa:?}"),
-            RecordData::Ns(name) => name.to_string(),
+            // Spread the SOA over several lines, with comments, to make it easier to read.
+            RecordData::Soa(soa) => soa.to_string(),
+            data => data.to_string(),
         };
         format!("{}: {rdata} (TTL {})", self.data.as_type(), self.ttl)
     }
+
+    /// Serialize the record and write it into the stream of bits.
+    /// The bitvec must start at the start of the message, so that names can be compressed.
+    pub fn serialize<T: BitStore>(
+        &self,
+        names: &mut NameCompressor,
+        bv: &mut BitVec<T, Msb0>,
+    ) -> AResult<()> {
+        names.write_name(&self.name, bv)?;
+        self.data.as_type().serialize(bv);
+        self.class.serialize(bv);
+        bv.extend_from_bitslice(self.ttl.view_bits::<Msb0>());
+        // The RDLENGTH isn't known until the RDATA has been written (compression could make
+        // names shorter), so write a placeholder, then fill it in afterwards.
+        let rdlength_start = bv.len();
+        bv.extend_from_bitslice(0u16.view_bits::<Msb0>());
+        self.data.serialize(names, bv)?;
+        let rdlength = (bv.len() - rdlength_start - 16) / 8;
+        let rdlength = u16::try_from(rdlength)
+            .map_err(|_| anyhow!("RDATA is {rdlength} bytes, which is too long"))?;
+        bv[rdlength_start..rdlength_start + 16].store_be(rdlength);
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
-#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RecordData {
     A(Ipv4Addr),
     Aaaa(Ipv6Addr),
     Cname(String),
     Soa(SoaData),
     Ns(String),
+    Mx(MxData),
+    /// One or more <character-string>s. These are arbitrary bytes, not necessarily UTF-8,
+    /// and each one is kept separate because some uses (like DKIM) split values across them.
+    Txt(Vec<Vec<u8>>),
+    Ptr(String),
+    Srv(SrvData),
+    Dnskey(DnskeyData),
+    Ds(DsData),
+    Rrsig(RrsigData),
+    Nsec(NsecData),
+    Nsec3(Nsec3Data),
+    Nsec3param(Nsec3ParamData),
+    /// RDATA of a type dingo doesn't know how to parse, kept as raw bytes.
+    Unknown {
+        rtype: u16,
+        bytes: Vec<u8>,
+    },
+}
+
+/// Serialized as the record type, e.g. "AAAA" or "TYPE65280", and the RDATA.
+/// Records of unknown types have their RDATA as a hex string.
+impl Serialize for RecordData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RecordData", 2)?;
+        state.serialize_field("type", &self.as_type())?;
+        match self {
+            Self::A(ip) => state.serialize_field("data", ip)?,
+            Self::Aaaa(ip) => state.serialize_field("data", ip)?,
+            Self::Cname(name) | Self::Ns(name) | Self::Ptr(name) => {
+                state.serialize_field("data", name)?
+            }
+            Self::Soa(soa) => state.serialize_field("data", soa)?,
+            Self::Mx(mx) => state.serialize_field("data", mx)?,
+            Self::Txt(strings) => state.serialize_field("data", &CharacterStrings(strings))?,
+            Self::Srv(srv) => state.serialize_field("data", srv)?,
+            Self::Dnskey(dnskey) => state.serialize_field("data", dnskey)?,
+            Self::Ds(ds) => state.serialize_field("data", ds)?,
+            Self::Rrsig(rrsig) => state.serialize_field("data", rrsig)?,
+            Self::Nsec(nsec) => state.serialize_field("data", nsec)?,
+            Self::Nsec3(nsec3) => state.serialize_field("data", nsec3)?,
+            Self::Nsec3param(param) => state.serialize_field("data", param)?,
+            Self::Unknown { bytes, .. } => state.serialize_field("data", &Hex(bytes))?,
+        }
+        state.end()
+    }
+}
+
+/// Lets [`serialize_character_strings`] be used for a field of a hand-written Serialize impl.
+struct CharacterStrings<'a>(&'a [Vec<u8>]);
+
+impl Serialize for CharacterStrings<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_character_strings(self.0, serializer)
+    }
+}
+
+/// Lets [`serialize_hex`] be used for a field of a hand-written Serialize impl.
+struct Hex<'a>(&'a [u8]);
+
+impl Serialize for Hex<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_hex(self.0, serializer)
+    }
 }
 
 impl RecordData {
-    fn as_type(&self) -> RecordType {
+    pub fn as_type(&self) -> RecordType {
         match self {
             Self::A(_) => RecordType::A,
             Self::Aaaa(_) => RecordType::Aaaa,
             Self::Cname(_) => RecordType::Cname,
             Self::Soa(_) => RecordType::Soa,
             Self::Ns(_) => RecordType::Ns,
+            Self::Mx(_) => RecordType::Mx,
+            Self::Txt(_) => RecordType::Txt,
+            Self::Ptr(_) => RecordType::Ptr,
+            Self::Srv(_) => RecordType::Srv,
+            Self::Dnskey(_) => RecordType::Dnskey,
+            Self::Ds(_) => RecordType::Ds,
+            Self::Rrsig(_) => RecordType::Rrsig,
+            Self::Nsec(_) => RecordType::Nsec,
+            Self::Nsec3(_) => RecordType::Nsec3,
+            Self::Nsec3param(_) => RecordType::Nsec3param,
+            Self::Unknown { rtype, .. } => RecordType::Unknown(*rtype),
+        }
+    }
+
+    /// The RDATA in the canonical wire format that DNSSEC signs, i.e. with names uncompressed
+    /// and in lowercase (except NSEC's next domain name).
+    /// See <https://datatracker.ietf.org/doc/html/rfc4034#section-6.2>
+    pub fn to_canonical_wire(&self) -> AResult<Vec<u8>> {
+        let mut bv = BitVec::<u8, Msb0>::new();
+        self.serialize(&mut NameCompressor::canonical(), &mut bv)?;
+        Ok(bv.into_vec())
+    }
+
+    /// Serialize the RDATA in wire format. Names in the RDATA of the types from RFC 1035
+    /// are compressed, but RFC 3597 says newer types (like SRV) mustn't be.
+    fn serialize<T: BitStore>(
+        &self,
+        names: &mut NameCompressor,
+        bv: &mut BitVec<T, Msb0>,
+    ) -> AResult<()> {
+        let write_bytes = |bv: &mut BitVec<T, Msb0>, bytes: &[u8]| {
+            for byte in bytes {
+                bv.extend_from_bitslice(byte.view_bits::<Msb0>());
+            }
+        };
+        match self {
+            Self::A(ipv4) => write_bytes(bv, &ipv4.octets()),
+            Self::Aaaa(ipv6) => write_bytes(bv, &ipv6.octets()),
+            Self::Cname(name) | Self::Ns(name) | Self::Ptr(name) => names.write_name(name, bv)?,
+            Self::Soa(soa) => {
+                names.write_name(&soa.mname, bv)?;
+                names.write_name(&soa.rname, bv)?;
+                for field in [soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum] {
+                    bv.extend_from_bitslice(field.view_bits::<Msb0>());
+                }
+            }
+            Self::Mx(mx) => {
+                bv.extend_from_bitslice(mx.preference.view_bits::<Msb0>());
+                names.write_name(&mx.exchange, bv)?;
+            }
+            Self::Txt(strings) => {
+                for string in strings {
+                    let len = u8::try_from(string.len()).map_err(|_| {
+                        anyhow!(
+                            "TXT strings must be <=255 bytes but this one is {}",
+                            string.len()
+                        )
+                    })?;
+                    bv.extend_from_bitslice(len.view_bits::<Msb0>());
+                    write_bytes(bv, string);
+                }
+            }
+            Self::Srv(srv) => {
+                for field in [srv.priority, srv.weight, srv.port] {
+                    bv.extend_from_bitslice(field.view_bits::<Msb0>());
+                }
+                names.write_name_uncompressed(&srv.target, bv)?;
+            }
+            Self::Dnskey(dnskey) => write_bytes(bv, &dnskey.to_wire()),
+            Self::Ds(ds) => {
+                bv.extend_from_bitslice(ds.key_tag.view_bits::<Msb0>());
+                write_bytes(bv, &[ds.algorithm, ds.digest_type]);
+                write_bytes(bv, &ds.digest);
+            }
+            Self::Rrsig(rrsig) => {
+                bv.extend_from_bitslice(u16::from(rrsig.type_covered).view_bits::<Msb0>());
+                write_bytes(bv, &[rrsig.algorithm, rrsig.labels]);
+                for field in [rrsig.original_ttl, rrsig.expiration, rrsig.inception] {
+                    bv.extend_from_bitslice(field.view_bits::<Msb0>());
+                }
+                bv.extend_from_bitslice(rrsig.key_tag.view_bits::<Msb0>());
+                names.write_name_uncompressed(&rrsig.signer_name, bv)?;
+                write_bytes(bv, &rrsig.signature);
+            }
+            Self::Nsec(nsec) => {
+                names.write_name_keeping_case(&nsec.next_domain_name, bv)?;
+                write_bytes(bv, &type_bitmaps(&nsec.types));
+            }
+            Self::Nsec3(nsec3) => {
+                write_bytes(bv, &[nsec3.hash_algorithm, nsec3.flags]);
+                bv.extend_from_bitslice(nsec3.iterations.view_bits::<Msb0>());
+                for field in [&nsec3.salt, &nsec3.next_hashed_owner] {
+                    let len = u8::try_from(field.len())
+                        .map_err(|_| anyhow!("NSEC3 fields must be <=255 bytes"))?;
+                    write_bytes(bv, &[len]);
+                    write_bytes(bv, field);
+                }
+                write_bytes(bv, &type_bitmaps(&nsec3.types));
+            }
+            Self::Nsec3param(param) => {
+                write_bytes(bv, &[param.hash_algorithm, param.flags]);
+                bv.extend_from_bitslice(param.iterations.view_bits::<Msb0>());
+                let len = u8::try_from(param.salt.len())
+                    .map_err(|_| anyhow!("NSEC3PARAM salts must be <=255 bytes"))?;
+                write_bytes(bv, &[len]);
+                write_bytes(bv, &param.salt);
+            }
+            Self::Unknown { bytes, .. } => write_bytes(bv, bytes),
+        }
+        Ok(())
+    }
+}
+
+/// Prints the record in the presentation format from RFC 1035, like zone files and `dig` use:
+/// `name TTL class TYPE rdata`, separated by tabs.
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.name,
+            self.ttl,
+            self.class,
+            self.data.as_type(),
+            self.data
+        )
+    }
+}
+
+/// Prints the RDATA in presentation format, all on one line.
+impl fmt::Display for RecordData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::A(ipv4) => ipv4.fmt(f),
+            Self::Aaaa(ipv6) => ipv6.fmt(f),
+            Self::Cname(name) | Self::Ns(name) | Self::Ptr(name) => name.fmt(f),
+            Self::Soa(soa) => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                soa.mname, soa.rname, soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum
+            ),
+            Self::Mx(mx) => write!(f, "{} {}", mx.preference, mx.exchange),
+            Self::Txt(strings) => {
+                let strings: Vec<_> = strings.iter().map(|s| escape_character_string(s)).collect();
+                strings.join(" ").fmt(f)
+            }
+            Self::Srv(srv) => write!(
+                f,
+                "{} {} {} {}",
+                srv.priority, srv.weight, srv.port, srv.target
+            ),
+            Self::Dnskey(dnskey) => dnskey.fmt(f),
+            Self::Ds(ds) => ds.fmt(f),
+            Self::Rrsig(rrsig) => rrsig.fmt(f),
+            Self::Nsec(nsec) => nsec.fmt(f),
+            Self::Nsec3(nsec3) => nsec3.fmt(f),
+            Self::Nsec3param(param) => param.fmt(f),
+            Self::Unknown { bytes, .. } => unknown_rdata(bytes).fmt(f),
+        }
+    }
+}
+
+/// Formats RDATA of an unknown type in the generic format from
+/// <https://datatracker.ietf.org/doc/html/rfc3597#section-5>, e.g. `\# 4 0a000001`
+pub fn unknown_rdata(bytes: &[u8]) -> String {
+    let mut out = format!("\\# {}", bytes.len());
+    if !bytes.is_empty() {
+        out.push(' ');
+        for byte in bytes {
+            out.push_str(&format!("{byte:02x}"));
+        }
+    }
+    out
+}
+
+/// Serializes bytes as a hex string, e.g. for JSON output.
+pub(crate) fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    serializer.serialize_str(&hex)
+}
+
+/// Serializes <character-string>s as text, with the same escapes zone files use,
+/// because they aren't necessarily valid UTF-8.
+fn serialize_character_strings<S: Serializer>(
+    strings: &[Vec<u8>],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(strings.iter().map(|s| {
+        let escaped = escape_character_string(s);
+        escaped[1..escaped.len() - 1].to_owned()
+    }))
+}
+
+/// Formats a <character-string> the way zone files do: in double quotes, with quotes and
+/// backslashes escaped, and any non-printable bytes written as a decimal escape like `\007`.
+/// See <https://datatracker.ietf.org/doc/html/rfc1035#section-5.1>
+pub fn escape_character_string(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            b' '..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\{byte:03}")),
         }
     }
+    out.push('"');
+    out
 }
 
-#[derive(Debug)]
-#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 pub struct SoaData {
     /// name server that was the original or primary source of data for this zone.
     pub mname: String,
@@ -64,4 +371,351 @@ pub struct SoaData {
     pub retry: u32,
     /// upper limit on the time interval that can elapse before the zone is no longer authoritative.
     pub expire: u32,
+    /// The unsigned 32 bit minimum TTL field that should be exported with any RR from this zone.
+    /// These days it's used as the TTL for negative responses (NXDOMAIN and NODATA),
+    /// see <https://datatracker.ietf.org/doc/html/rfc2308#section-4>
+    pub minimum: u32,
+}
+
+impl SoaData {
+    /// The RNAME field is a mailbox, encoded as a domain name. The first label is the part
+    /// before the @, and any dots in it are escaped, e.g. `john\.smith.example.com.`
+    /// becomes `john.smith@example.com`. Bytes that aren't printable stay escaped.
+    pub fn rname_as_email(&self) -> String {
+        let labels = split_labels(&self.rname);
+        let Some((first, domain)) = labels.split_first() else {
+            return self.rname.clone();
+        };
+        let local_part = match unescape_label(first) {
+            Ok(bytes) if bytes.iter().all(u8::is_ascii_graphic) => {
+                bytes.into_iter().map(char::from).collect()
+            }
+            _ => first.to_string(),
+        };
+        if domain.is_empty() {
+            return local_part;
+        }
+        format!("{local_part}@{}", domain.join("."))
+    }
+}
+
+/// Prints the SOA like a zone file would, one field per line with comments,
+/// and with the timers in a readable format.
+impl fmt::Display for SoaData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {} (", self.mname, self.rname)?;
+        writeln!(f, "        {:<10} ; serial", self.serial)?;
+        writeln!(f, "        {:<10} ; refresh", format_duration(self.refresh))?;
+        writeln!(f, "        {:<10} ; retry", format_duration(self.retry))?;
+        writeln!(f, "        {:<10} ; expire", format_duration(self.expire))?;
+        writeln!(
+            f,
+            "        {:<10} ; minimum (negative caching TTL)",
+            format_duration(self.minimum)
+        )?;
+        write!(f, "        ) ; contact {}", self.rname_as_email())
+    }
+}
+
+/// Formats a number of seconds the way BIND zone files can, e.g. `1w2d` or `1h30m`.
+pub fn format_duration(mut secs: u32) -> String {
+    if secs == 0 {
+        return "0s".to_owned();
+    }
+    const UNITS: [(u32, char); 5] = [
+        (7 * 24 * 60 * 60, 'w'),
+        (24 * 60 * 60, 'd'),
+        (60 * 60, 'h'),
+        (60, 'm'),
+        (1, 's'),
+    ];
+    let mut out = String::new();
+    for (unit_secs, unit) in UNITS {
+        if secs >= unit_secs {
+            out.push_str(&format!("{}{unit}", secs / unit_secs));
+            secs %= unit_secs;
+        }
+    }
+    out
+}
+
+/// Parses a number of seconds, written either as a plain number or the way BIND zone files can,
+/// e.g. `1w2d` or `1h30m`. The opposite of [`format_duration`].
+pub fn parse_duration(s: &str) -> AResult<u32> {
+    if let Ok(secs) = s.parse() {
+        return Ok(secs);
+    }
+    let invalid = || anyhow!("{s} is not a valid duration");
+    let mut total: u32 = 0;
+    let mut digits = String::new();
+    for ch in s.chars() {
+        if ch.is_ascii_digit() {
+            digits.push(ch);
+            continue;
+        }
+        let unit_secs = match ch.to_ascii_lowercase() {
+            'w' => 7 * 24 * 60 * 60,
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let n: u32 = digits.parse().map_err(|_| invalid())?;
+        total = n
+            .checked_mul(unit_secs)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct MxData {
+    /// The preference given to this RR among others at the same owner.
+    /// Lower values are preferred.
+    pub preference: u16,
+    /// A host willing to act as a mail exchange for the owner name.
+    pub exchange: String,
+}
+
+/// Locates a service, see <https://datatracker.ietf.org/doc/html/rfc2782>
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct SrvData {
+    /// Clients must try the target with the lowest priority first.
+    pub priority: u16,
+    /// Relative weight for choosing between targets with the same priority.
+    /// Larger weights get a proportionately higher chance of being chosen.
+    pub weight: u16,
+    /// The port on the target host of this service.
+    pub port: u16,
+    /// The domain name of the target host. "." means the service is decidedly not available.
+    pub target: String,
+}
+
+/// Sorts SRV records into the order a client should try them in, using the selection
+/// algorithm from RFC 2782. Lower priorities go first. Within each priority, records are
+/// picked at random, weighted by their `weight`, so the order is different each time.
+/// The `srv` closure gets the SRV data out of each item, so this works for both plain
+/// [`SrvData`] and whole [`Record`]s.
+pub fn srv_try_order<T, R: Rng + ?Sized>(
+    mut items: Vec<T>,
+    srv: impl Fn(&T) -> &SrvData,
+    rng: &mut R,
+) -> Vec<T> {
+    items.sort_by_key(|item| srv(item).priority);
+    let mut ordered = Vec::with_capacity(items.len());
+    while !items.is_empty() {
+        // Take every record with the lowest remaining priority.
+        let priority = srv(&items[0]).priority;
+        let group_len = items
+            .iter()
+            .take_while(|item| srv(item).priority == priority)
+            .count();
+        let mut group: Vec<T> = items.drain(..group_len).collect();
+        // "arrange all SRV RRs (that have not been ordered yet) in any order, except that all
+        // those with weight 0 are placed at the beginning of the list."
+        group.sort_by_key(|item| srv(item).weight != 0);
+        while !group.is_empty() {
+            // "Compute the sum of the weights of those RRs, and with each RR associate the
+            // running sum in the selected order. Then choose a uniform random number between 0
+            // and the sum computed (inclusive), and select the RR whose running sum value is
+            // the first in the selected order which is greater than or equal to the random
+            // number selected."
+            let total: u32 = group.iter().map(|item| u32::from(srv(item).weight)).sum();
+            let chosen = rng.gen_range(0..=total);
+            let mut running_sum = 0;
+            let index = group
+                .iter()
+                .position(|item| {
+                    running_sum += u32::from(srv(item).weight);
+                    running_sum >= chosen
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(index));
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn srv(priority: u16, weight: u16, target: &str) -> SrvData {
+        SrvData {
+            priority,
+            weight,
+            port: 389,
+            target: target.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_srv_order_respects_priority() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let records = vec![
+            srv(20, 100, "backup-a."),
+            srv(10, 0, "primary-a."),
+            srv(30, 0, "last."),
+            srv(10, 50, "primary-b."),
+            srv(20, 100, "backup-b."),
+        ];
+        let ordered = srv_try_order(records, |s| s, &mut rng);
+        let priorities: Vec<_> = ordered.iter().map(|s| s.priority).collect();
+        assert_eq!(priorities, vec![10, 10, 20, 20, 30]);
+    }
+
+    #[test]
+    fn test_srv_order_is_weighted() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let records = vec![srv(10, 10, "light."), srv(10, 90, "heavy.")];
+            let ordered = srv_try_order(records, |s| s, &mut rng);
+            if ordered[0].target == "heavy." {
+                heavy_first += 1;
+            }
+        }
+        // Should be about 900, so leave plenty of room for randomness.
+        assert!((850..950).contains(&heavy_first), "{heavy_first}");
+    }
+
+    #[test]
+    fn test_srv_order_zero_weight_is_rarely_first() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let records = vec![srv(10, 0, "zero."), srv(10, 65535, "heavy.")];
+            let ordered = srv_try_order(records, |s| s, &mut rng);
+            assert_eq!(ordered[0].target, "heavy.");
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(3600), "1h");
+        assert_eq!(format_duration(1209600), "2w");
+        assert_eq!(format_duration(90061), "1d1h1m1s");
+        assert_eq!(format_duration(300), "5m");
+        for secs in [0, 3600, 1209600, 90061, 300, 86400] {
+            assert_eq!(parse_duration(&format_duration(secs)).unwrap(), secs);
+        }
+        assert_eq!(parse_duration("1H30M").unwrap(), 5400);
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("1y").is_err());
+    }
+
+    #[test]
+    fn test_soa_display() {
+        let soa = SoaData {
+            mname: "ns1.example.com.".to_owned(),
+            rname: "john\\.smith.example.com.".to_owned(),
+            serial: 2022020101,
+            refresh: 7200,
+            retry: 900,
+            expire: 1209600,
+            minimum: 86400,
+        };
+        assert_eq!(soa.rname_as_email(), "john.smith@example.com");
+        let odd = SoaData {
+            rname: "a\\032b.example.com.".to_owned(),
+            ..soa.clone()
+        };
+        // Spaces can't go in an email address unquoted, so they stay escaped.
+        assert_eq!(odd.rname_as_email(), "a\\032b@example.com");
+        let expected = "\
+ns1.example.com. john\\.smith.example.com. (
+        2022020101 ; serial
+        2h         ; refresh
+        15m        ; retry
+        2w         ; expire
+        1d         ; minimum (negative caching TTL)
+        ) ; contact john.smith@example.com";
+        assert_eq!(soa.to_string(), expected);
+
+        let record = Record {
+            name: "example.com.".to_owned(),
+            class: Class::IN,
+            ttl: 3600,
+            data: RecordData::Soa(soa),
+        };
+        assert_eq!(
+            record.to_string(),
+            "example.com.\t3600\tIN\tSOA\tns1.example.com. john\\.smith.example.com. 2022020101 7200 900 1209600 86400"
+        );
+    }
+
+    #[test]
+    fn test_json_type() {
+        let record = Record {
+            name: "example.com.".to_owned(),
+            class: Class::IN,
+            ttl: 300,
+            data: RecordData::Unknown {
+                rtype: 65280,
+                bytes: vec![1, 2, 0xab],
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            serde_json::json!({
+                "name": "example.com.",
+                "class": "IN",
+                "ttl": 300,
+                "type": "TYPE65280",
+                "data": "0102ab",
+            })
+        );
+        let record = Record {
+            data: RecordData::Nsec3param(Nsec3ParamData {
+                hash_algorithm: 1,
+                flags: 0,
+                iterations: 0,
+                salt: Vec::new(),
+            }),
+            ..record
+        };
+        assert_eq!(serde_json::to_value(&record).unwrap()["type"], "NSEC3PARAM");
+    }
+
+    #[test]
+    fn test_canonical_wire() {
+        let cname = RecordData::Cname("WWW.Example.".to_owned());
+        assert_eq!(
+            cname.to_canonical_wire().unwrap(),
+            b"\x03www\x07example\x00"
+        );
+        // RFC 6840 section 5.1: NSEC's next domain name isn't lowercased.
+        let nsec = RecordData::Nsec(NsecData {
+            next_domain_name: "WWW.Example.".to_owned(),
+            types: vec![RecordType::A],
+        });
+        assert_eq!(
+            nsec.to_canonical_wire().unwrap(),
+            b"\x03WWW\x07Example\x00\x00\x01\x40"
+        );
+    }
+
+    #[test]
+    fn test_unknown_rdata() {
+        assert_eq!(unknown_rdata(&[]), r"\# 0");
+        assert_eq!(unknown_rdata(&[1, 2, 0xab]), r"\# 3 0102ab");
+    }
+
+    #[test]
+    fn test_escape_character_string() {
+        assert_eq!(escape_character_string(b"v=spf1 -all"), r#""v=spf1 -all""#);
+        assert_eq!(
+            escape_character_string(br#"say "hi" \o/"#),
+            r#""say \"hi\" \\o/""#
+        );
+        assert_eq!(escape_character_string(&[0, 7, 255]), r#""\000\007\255""#);
+        assert_eq!(escape_character_string(b""), r#""""#);
+    }
 }
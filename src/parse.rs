@@ -1,32 +1,44 @@
-use nom::{combinator::map_res, IResult};
+use crate::message::{
+    error::{fail, PResult, ParseErrorKind},
+    name::escape_label,
+    MAX_LABEL_BYTES,
+};
 
 /// Matches a sequence of labels, terminated by a zero-length label.
-pub fn parse_labels_then_zero(mut i: &[u8]) -> IResult<&[u8], Vec<String>> {
+pub fn parse_labels_then_zero(mut i: &[u8]) -> PResult<'_, Vec<String>> {
     let mut labels = Vec::new();
     loop {
         let (new_i, label) = parse_label(i)?;
         i = new_i;
-        let len = label.len();
-        labels.push(label);
-        if len == 0 {
+        labels.push(escape_label(label));
+        if label.is_empty() {
             return Ok((i, labels));
         }
     }
 }
 
-/// Read one byte as a u8. Then read that many following bytes and output them, as ASCII.
-pub fn parse_label(i: &[u8]) -> IResult<&[u8], String> {
-    let parse_len = map_res(nom::number::complete::be_u8, |num| {
-        if num >= 64 {
-            Err(format!(
-                "DNS name labels must be <=63 bytes but this one is {num}"
-            ))
-        } else {
-            Ok(num)
+/// Read one byte as a u8. Then read that many following bytes and output them.
+/// Labels can contain any bytes, see [`escape_label`] for turning them into text.
+pub fn parse_label(i: &[u8]) -> PResult<'_, &[u8]> {
+    let (rest, len) = nom::number::complete::be_u8(i)?;
+    if usize::from(len) > MAX_LABEL_BYTES {
+        return fail(i, ParseErrorKind::LabelTooLong(len));
+    }
+    nom::bytes::complete::take(len)(rest)
+}
+
+/// Applies the parser over and over until the input runs out. Unlike nom's `many0`, errors
+/// aren't swallowed, so if the last item is cut short, that gets reported.
+pub fn many_until_empty<'i, O>(
+    mut parser: impl FnMut(&'i [u8]) -> PResult<'i, O>,
+) -> impl FnMut(&'i [u8]) -> PResult<'i, Vec<O>> {
+    move |mut i| {
+        let mut items = Vec::new();
+        while !i.is_empty() {
+            let (rest, item) = parser(i)?;
+            items.push(item);
+            i = rest;
         }
-    });
-    let parse_label = nom::multi::length_data(parse_len);
-    map_res(parse_label, |bytes: &[u8]| {
-        String::from_utf8(bytes.to_vec())
-    })(i)
+        Ok((i, items))
+    }
 }
@@ -0,0 +1,433 @@
+//! Printing DNS responses to the terminal.
+use anyhow::Result as AResult;
+use dingo::{
+    message::record::{srv_try_order, SrvData},
+    validate::Validation,
+    Message, Record, RecordData, ResponseCode,
+};
+use std::{fmt::Write, net::SocketAddr, time::Duration};
+
+/// Mail servers are tried in order of preference, and SRV targets are tried in order of
+/// priority and weight, so print them in the order a client would try them.
+/// Other records keep the order the resolver sent them in.
+fn sort_by_preference(records: Vec<Record>) -> Vec<Record> {
+    let (srv, mut records): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|record| matches!(record.data, RecordData::Srv(_)));
+    records.sort_by_key(|record| match &record.data {
+        RecordData::Mx(mx) => Some(mx.preference),
+        _ => None,
+    });
+    fn srv_data(record: &Record) -> &SrvData {
+        match &record.data {
+            RecordData::Srv(srv) => srv,
+            _ => unreachable!("only SRV records were partitioned out"),
+        }
+    }
+    records.extend(srv_try_order(srv, srv_data, &mut rand::thread_rng()));
+    records
+}
+
+/// Print the whole response as JSON. Unlike the text output, responses with an error code are
+/// printed too, because scripts will want to see the error code.
+pub fn print_json(response_msg: &Message, validation: Option<&Validation>) -> AResult<()> {
+    println!("{}", json_output(response_msg, validation)?);
+    Ok(())
+}
+
+/// The response as JSON, with the DNSSEC validation result (if there is one) as its `dnssec`
+/// field.
+fn json_output(response_msg: &Message, validation: Option<&Validation>) -> AResult<String> {
+    let mut json = serde_json::to_value(response_msg)?;
+    if let Some(validation) = validation {
+        json["dnssec"] = serde_json::to_value(validation)?;
+    }
+    Ok(serde_json::to_string_pretty(&json)?)
+}
+
+/// Formats the response the same way `dig` does, so it works with tools built around dig.
+/// `server` and `query_time` are for the footer.
+pub fn dig_output(response_msg: &Message, server: SocketAddr, query_time: Duration) -> String {
+    let header = &response_msg.header;
+    let mut out = String::new();
+    let mut line = |s: String| {
+        out.push_str(&s);
+        out.push('\n');
+    };
+    line(";; Got answer:".to_owned());
+    line(format!(
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+        header.opcode,
+        header.resp_code.mnemonic(),
+        header.id
+    ));
+    let flags = [
+        (header.is_response, "qr"),
+        (header.authoritative_answer, "aa"),
+        (header.truncation, "tc"),
+        (header.recursion_desired, "rd"),
+        (header.recursion_available, "ra"),
+        (header.authentic_data, "ad"),
+        (header.checking_disabled, "cd"),
+    ];
+    let flags: Vec<_> = flags
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect();
+    line(format!(
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags.join(" "),
+        header.question_count,
+        header.answer_count,
+        header.name_server_count,
+        header.additional_records_count
+    ));
+    if let Some(edns) = &response_msg.edns {
+        line(String::new());
+        line(";; OPT PSEUDOSECTION:".to_owned());
+        let flags = if edns.dnssec_ok { " do" } else { "" };
+        line(format!(
+            "; EDNS: version: {}, flags:{flags}; udp: {}",
+            edns.version, edns.udp_payload_size
+        ));
+    }
+    line(String::new());
+    line(";; QUESTION SECTION:".to_owned());
+    for question in &response_msg.question {
+        line(format!(
+            ";{}\t\t{}\t{}",
+            question.name, question.record_qclass, question.record_type
+        ));
+    }
+    let sections = [
+        ("ANSWER", &response_msg.answer),
+        ("AUTHORITY", &response_msg.authority),
+        ("ADDITIONAL", &response_msg.additional),
+    ];
+    for (section, records) in sections {
+        if records.is_empty() {
+            continue;
+        }
+        line(String::new());
+        line(format!(";; {section} SECTION:"));
+        for record in records {
+            line(record.to_string());
+        }
+    }
+    line(String::new());
+    line(format!(";; Query time: {} msec", query_time.as_millis()));
+    let ip = server.ip();
+    write!(out, ";; SERVER: {ip}#{}({ip})", server.port()).unwrap();
+    out
+}
+
+/// One resolver's response to the question, for comparing with the others.
+pub struct ResolverResponse {
+    pub resolver: SocketAddr,
+    /// How long the resolver took to respond, or to fail.
+    pub latency: Duration,
+    pub response: AResult<Message>,
+}
+
+/// Formats a table with a column for each resolver's response, so they're easy to compare.
+/// Each answer gets a row, showing its TTL from each resolver that sent it.
+/// Rows where the resolvers disagree are marked with a !, but TTLs and latency aren't
+/// counted as disagreements, because they're different whenever resolvers cache separately.
+pub fn comparison_output(responses: &[ResolverResponse]) -> String {
+    // Each row is a label, a cell for each resolver, and whether the resolvers disagree.
+    let mut rows = vec![
+        (
+            "Resolver".to_owned(),
+            responses.iter().map(|r| r.resolver.to_string()).collect(),
+            false,
+        ),
+        (
+            "Latency".to_owned(),
+            responses
+                .iter()
+                .map(|r| format!("{}ms", r.latency.as_millis()))
+                .collect(),
+            false,
+        ),
+    ];
+    let rcodes: Vec<String> = responses
+        .iter()
+        .map(|r| match &r.response {
+            Ok(response) => response.header.resp_code.mnemonic(),
+            Err(_) => "error".to_owned(),
+        })
+        .collect();
+    let disagree = rcodes.iter().any(|rcode| rcode != &rcodes[0]);
+    rows.push(("Rcode".to_owned(), rcodes, disagree));
+
+    let answers: Vec<_> = responses
+        .iter()
+        .map(|r| match &r.response {
+            Ok(response) => response
+                .answer
+                .iter()
+                .map(|record| {
+                    (
+                        format!("{} {} {}", record.name, record.data.as_type(), record.data),
+                        record.ttl,
+                    )
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        })
+        .collect();
+    let mut labels: Vec<&String> = Vec::new();
+    for (label, _) in answers.iter().flatten() {
+        if !labels.contains(&label) {
+            labels.push(label);
+        }
+    }
+    for label in labels {
+        let ttls: Vec<_> = answers
+            .iter()
+            .map(|answers| answers.iter().find(|(l, _)| l == label).map(|(_, ttl)| ttl))
+            .collect();
+        // Resolvers which failed don't count, the rcode row already shows they're different.
+        let present: Vec<_> = ttls
+            .iter()
+            .zip(responses)
+            .filter(|(_, r)| r.response.is_ok())
+            .map(|(ttl, _)| ttl.is_some())
+            .collect();
+        let disagree = present.iter().any(|&p| p != present[0]);
+        let cells = ttls
+            .iter()
+            .zip(responses)
+            .map(|(ttl, r)| match (ttl, &r.response) {
+                (_, Err(_)) => String::new(),
+                (Some(ttl), Ok(_)) => format!("TTL {ttl}"),
+                (None, Ok(_)) => "-".to_owned(),
+            })
+            .collect();
+        rows.push((label.clone(), cells, disagree));
+    }
+
+    let label_width = rows
+        .iter()
+        .map(|(label, _, _)| label.len())
+        .max()
+        .unwrap_or(0);
+    let column_widths: Vec<_> = (0..responses.len())
+        .map(|i| {
+            rows.iter()
+                .map(|(_, cells, _)| cells[i].len())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut out = String::new();
+    for (label, cells, disagree) in &rows {
+        let mut line = format!("{} {label:label_width$}", if *disagree { "!" } else { " " });
+        for (cell, width) in cells.iter().zip(&column_widths) {
+            write!(line, "  {cell:width$}").unwrap();
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    for r in responses {
+        if let Err(e) = &r.response {
+            writeln!(out, "{} failed: {e}", r.resolver).unwrap();
+        }
+    }
+    if rows.iter().any(|(_, _, disagree)| *disagree) {
+        out.push_str("! marks where the resolvers disagree");
+    } else {
+        out.push_str("All the resolvers agree");
+    }
+    out
+}
+
+/// Print each resolver's response as JSON, in an array.
+pub fn print_comparison_json(responses: &[ResolverResponse]) -> AResult<()> {
+    let responses: Vec<_> = responses
+        .iter()
+        .map(|r| {
+            let mut json = serde_json::json!({
+                "resolver": r.resolver.to_string(),
+                "latency_ms": r.latency.as_millis(),
+            });
+            match &r.response {
+                Ok(response) => json["response"] = serde_json::to_value(response)?,
+                Err(e) => json["error"] = e.to_string().into(),
+            }
+            Ok(json)
+        })
+        .collect::<AResult<_>>()?;
+    println!("{}", serde_json::to_string_pretty(&responses)?);
+    Ok(())
+}
+
+/// Print the resolver's response nicely.
+pub fn print_resp(mut response_msg: Message) -> AResult<()> {
+    // Check the response was successful.
+    match response_msg.header.resp_code {
+        ResponseCode::NoError => {}
+        err => anyhow::bail!("Error from resolver: {err}"),
+    };
+    if let Some(edns) = &response_msg.edns {
+        // Response codes above 15 don't fit in the header, so EDNS carries their upper bits.
+        if edns.extended_rcode != 0 {
+            let rcode = (u16::from(edns.extended_rcode) << 4)
+                | u16::from(u8::from(response_msg.header.resp_code));
+            anyhow::bail!("Error from resolver: extended response code {rcode}");
+        }
+    }
+
+    // Reprint the question, why not?
+    println!("Questions:");
+    for question in response_msg.question.iter() {
+        println!("{question}");
+    }
+
+    // Print records sent by the resolver.
+    response_msg.answer = sort_by_preference(response_msg.answer);
+    if !response_msg.answer.is_empty() {
+        println!("Answers:");
+        for record in response_msg.answer {
+            println!("{}", record.as_dns_response());
+        }
+    }
+    if !response_msg.authority.is_empty() {
+        println!("Authority records:");
+        for record in response_msg.authority {
+            println!("{}", record.as_dns_response());
+        }
+    }
+    if !response_msg.additional.is_empty() {
+        println!("Additional records:");
+        for record in response_msg.additional {
+            println!("{}", record.as_dns_response());
+        }
+    }
+    if let Some(edns) = response_msg.edns {
+        println!("EDNS:");
+        println!("{edns}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dingo::{validate::Status, Class, Edns, RecordType};
+
+    #[test]
+    fn test_dig_output() {
+        let mut msg =
+            Message::new_query(4242, "example.com.".to_owned(), RecordType::A, None).unwrap();
+        msg.header.is_response = true;
+        msg.header.recursion_available = true;
+        msg.header.answer_count = 1;
+        msg.header.additional_records_count = 1;
+        msg.answer.push(Record {
+            name: "example.com.".to_owned(),
+            class: Class::IN,
+            ttl: 300,
+            data: RecordData::A([93, 184, 216, 34].into()),
+        });
+        msg.edns = Some(Edns::new(1232));
+        let server = "1.1.1.1:53".parse().unwrap();
+        let expected = "\
+;; Got answer:
+;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4242
+;; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 1
+
+;; OPT PSEUDOSECTION:
+; EDNS: version: 0, flags:; udp: 1232
+
+;; QUESTION SECTION:
+;example.com.\t\tIN\tA
+
+;; ANSWER SECTION:
+example.com.\t300\tIN\tA\t93.184.216.34
+
+;; Query time: 12 msec
+;; SERVER: 1.1.1.1#53(1.1.1.1)";
+        assert_eq!(
+            dig_output(&msg, server, Duration::from_millis(12)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_json_output() {
+        let msg = Message::new_query(1, "example.com".to_owned(), RecordType::A, None).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&json_output(&msg, None).unwrap()).unwrap();
+        assert!(json.get("dnssec").is_none());
+
+        let validation = Validation {
+            status: Status::Insecure,
+            reason: "com. has no DS records".to_owned(),
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&json_output(&msg, Some(&validation)).unwrap()).unwrap();
+        assert_eq!(
+            json["dnssec"],
+            serde_json::json!({"status": "insecure", "reason": "com. has no DS records"})
+        );
+        assert_eq!(json["header"]["id"], 1);
+    }
+
+    #[test]
+    fn test_comparison_output() {
+        let a = |ip: [u8; 4], ttl| Record {
+            name: "example.com.".to_owned(),
+            class: Class::IN,
+            ttl,
+            data: RecordData::A(ip.into()),
+        };
+        let response = |answer: Vec<Record>| {
+            let mut msg =
+                Message::new_query(1, "example.com.".to_owned(), RecordType::A, None).unwrap();
+            msg.header.is_response = true;
+            msg.answer = answer;
+            msg
+        };
+        let responses = [
+            ResolverResponse {
+                resolver: "1.1.1.1:53".parse().unwrap(),
+                latency: Duration::from_millis(12),
+                response: Ok(response(vec![
+                    a([192, 0, 2, 1], 300),
+                    a([192, 0, 2, 2], 300),
+                ])),
+            },
+            ResolverResponse {
+                resolver: "8.8.8.8:53".parse().unwrap(),
+                latency: Duration::from_millis(25),
+                response: Ok(response(vec![a([192, 0, 2, 1], 120)])),
+            },
+            ResolverResponse {
+                resolver: "10.0.0.1:53".parse().unwrap(),
+                latency: Duration::from_millis(5000),
+                response: Err(anyhow::anyhow!("no response within 5s")),
+            },
+        ];
+        let expected = "  \
+  Resolver                  1.1.1.1:53  8.8.8.8:53  10.0.0.1:53
+  Latency                   12ms        25ms        5000ms
+! Rcode                     NOERROR     NOERROR     error
+  example.com. A 192.0.2.1  TTL 300     TTL 120
+! example.com. A 192.0.2.2  TTL 300     -
+10.0.0.1:53 failed: no response within 5s
+! marks where the resolvers disagree";
+        assert_eq!(comparison_output(&responses), expected);
+
+        // Different TTLs and latencies are fine.
+        let expected = "  \
+  Resolver                  1.1.1.1:53  8.8.8.8:53
+  Latency                   12ms        25ms
+  Rcode                     NOERROR     NOERROR
+  example.com. A 192.0.2.1  TTL 300     TTL 120
+All the resolvers agree";
+        let mut responses = responses;
+        responses[0].response = Ok(response(vec![a([192, 0, 2, 1], 300)]));
+        assert_eq!(comparison_output(&responses[..2]), expected);
+    }
+}
@@ -0,0 +1,476 @@
+//! A caching, forwarding resolver. Queries are passed on to upstream resolvers, and their
+//! responses are cached until their TTLs run out.
+//!
+//! The proxy doesn't do DNSSEC. It asks upstream without the DO bit, so it gets no signatures
+//! to pass on, and it never sets AD, because it hasn't checked anything itself. Queries with
+//! DO or CD set are answered the same as any other query, from the same cache.
+use crate::{
+    io::{Client, Retry, Transport},
+    message::{
+        edns::{Edns, DEFAULT_UDP_PAYLOAD_SIZE},
+        header::ResponseCode,
+        record::RecordData,
+        Message,
+    },
+    server::{empty_response, Handler},
+    Class, RecordType,
+};
+use anyhow::{anyhow, Result as AResult};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    time::Instant,
+};
+
+/// Stops the cache growing forever. Once it's full, expired responses are removed, and if
+/// that doesn't free up any space, new responses just aren't cached.
+pub const MAX_CACHE_ENTRIES: usize = 10_000;
+
+/// Responses are cached per question. Names are lowercased, because they're case-insensitive.
+type CacheKey = (String, RecordType, Class);
+
+enum Slot {
+    Cached(CachedResponse),
+    /// Some thread is already asking upstream. Other threads asking the same question wait
+    /// for it, instead of asking upstream too.
+    InFlight(Arc<Pending>),
+}
+
+struct CachedResponse {
+    response: Message,
+    cached_at: Instant,
+    /// How long the response can be cached for, in seconds.
+    ttl: u32,
+}
+
+impl CachedResponse {
+    fn age(&self) -> u32 {
+        self.cached_at
+            .elapsed()
+            .as_secs()
+            .try_into()
+            .unwrap_or(u32::MAX)
+    }
+
+    fn is_expired(&self) -> bool {
+        self.age() >= self.ttl
+    }
+
+    /// The cached response, with each record's TTL reduced by the time it's been cached for.
+    fn aged_response(&self) -> Message {
+        let age = self.age();
+        let mut response = self.response.clone();
+        for record in response
+            .answer
+            .iter_mut()
+            .chain(&mut response.authority)
+            .chain(&mut response.additional)
+        {
+            record.ttl = record.ttl.saturating_sub(age);
+        }
+        response
+    }
+}
+
+/// The result of an upstream query that other threads are waiting for.
+#[derive(Default)]
+struct Pending {
+    /// None until the query finishes. Errors are strings so they can be cloned for each waiter.
+    result: Mutex<Option<Result<Message, String>>>,
+    done: Condvar,
+}
+
+/// Forwards queries to upstream resolvers, caching their responses.
+pub struct Proxy {
+    /// Resolvers to forward queries to. They're tried in order, until one responds.
+    pub upstreams: Vec<SocketAddr>,
+    pub transport: Transport,
+    /// How long to wait for each upstream, and how many times to ask it before moving on to
+    /// the next one.
+    pub retry: Retry,
+    cache: Mutex<HashMap<CacheKey, Slot>>,
+}
+
+impl Proxy {
+    /// Each upstream is only asked once, with the usual timeout. The client asking the proxy
+    /// will retry anyway, so retrying here too would just keep it waiting longer.
+    pub fn new(upstreams: Vec<SocketAddr>, transport: Transport) -> Self {
+        Self {
+            upstreams,
+            transport,
+            retry: Retry {
+                tries: 1,
+                ..Retry::default()
+            },
+            cache: Default::default(),
+        }
+    }
+
+    /// Gets the response to a question, from the cache if possible.
+    fn lookup(&self, key: CacheKey) -> Result<Message, String> {
+        let pending = {
+            let mut cache = self.cache();
+            match cache.get(&key) {
+                Some(Slot::Cached(cached)) if !cached.is_expired() => {
+                    return Ok(cached.aged_response())
+                }
+                Some(Slot::InFlight(pending)) => {
+                    let pending = pending.clone();
+                    drop(cache);
+                    return wait_for(&pending);
+                }
+                _ => {
+                    let pending = Arc::new(Pending::default());
+                    cache.insert(key.clone(), Slot::InFlight(pending.clone()));
+                    pending
+                }
+            }
+        };
+
+        let mut guard = PendingGuard {
+            proxy: self,
+            key,
+            pending,
+            result: None,
+        };
+        let result = self
+            .ask_upstream(&guard.key.0, guard.key.1)
+            .map_err(|e| e.to_string());
+        guard.result = Some(result.clone());
+        result
+    }
+
+    /// The cache, even if a thread panicked while it had it locked. Every change to the cache
+    /// is a single insert or remove, so it can't be left half-changed.
+    fn cache(&self) -> MutexGuard<'_, HashMap<CacheKey, Slot>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Asks each upstream resolver in turn, until one responds.
+    fn ask_upstream(&self, name: &str, record_type: RecordType) -> AResult<Message> {
+        let mut errors = Vec::new();
+        for &upstream in &self.upstreams {
+            let client = Client {
+                resolver: upstream,
+                transport: self.transport.clone(),
+                edns: Some(Edns::new(DEFAULT_UDP_PAYLOAD_SIZE)),
+                retry: self.retry,
+                log: None,
+            };
+            match client.query(name, record_type) {
+                Ok(response) => return Ok(response),
+                Err(e) => errors.push(format!("{upstream}: {e}")),
+            }
+        }
+        Err(anyhow!(
+            "No upstream resolver responded ({})",
+            errors.join(", ")
+        ))
+    }
+}
+
+impl Handler for Proxy {
+    fn answer(&self, query: &Message) -> Message {
+        let (mut response, question) = empty_response(query);
+        let Some(question) = question else {
+            return response;
+        };
+        response.header.recursion_available = true;
+        let key = (
+            question.name.to_ascii_lowercase(),
+            question.record_type,
+            question.record_qclass,
+        );
+        match self.lookup(key) {
+            Ok(upstream) => {
+                response.header.resp_code = upstream.header.resp_code;
+                response.answer = upstream.answer;
+                response.authority = upstream.authority;
+                response.additional = upstream.additional;
+            }
+            Err(_) => response.header.resp_code = ResponseCode::ServerFailure,
+        }
+        response
+    }
+}
+
+/// Caches the result of asking upstream (if it can be cached), and passes it on to every
+/// thread waiting for it. This happens when the guard is dropped, so even if asking upstream
+/// panics, the waiting threads still wake up, and the next query asks again.
+struct PendingGuard<'a> {
+    proxy: &'a Proxy,
+    key: CacheKey,
+    pending: Arc<Pending>,
+    /// None until upstream has been asked.
+    result: Option<Result<Message, String>>,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        let result = self
+            .result
+            .take()
+            .unwrap_or_else(|| Err("Asking upstream failed unexpectedly".to_owned()));
+        {
+            let mut cache = self.proxy.cache();
+            match result.as_ref().ok().and_then(cache_ttl) {
+                Some(ttl) if ttl > 0 && make_room(&mut cache) => {
+                    let cached = CachedResponse {
+                        response: result.clone().unwrap(),
+                        cached_at: Instant::now(),
+                        ttl,
+                    };
+                    cache.insert(self.key.clone(), Slot::Cached(cached));
+                }
+                _ => {
+                    cache.remove(&self.key);
+                }
+            }
+        }
+        *self
+            .pending
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(result);
+        self.pending.done.notify_all();
+    }
+}
+
+fn wait_for(pending: &Pending) -> Result<Message, String> {
+    let mut result = pending
+        .result
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    while result.is_none() {
+        result = pending
+            .done
+            .wait(result)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+    result.clone().unwrap()
+}
+
+/// How long a response can be cached for, or None if it shouldn't be cached.
+/// Negative responses (NXDOMAIN, or no records of the type) are cached for as long as
+/// the SOA record in the authority section says,
+/// see <https://datatracker.ietf.org/doc/html/rfc2308#section-5>
+fn cache_ttl(response: &Message) -> Option<u32> {
+    if response.header.truncation {
+        return None;
+    }
+    let is_negative = match response.header.resp_code {
+        ResponseCode::NoError => response.answer.is_empty(),
+        ResponseCode::NameError => true,
+        _ => return None,
+    };
+    if is_negative {
+        return response
+            .authority
+            .iter()
+            .find_map(|record| match &record.data {
+                RecordData::Soa(soa) => Some(record.ttl.min(soa.minimum)),
+                _ => None,
+            });
+    }
+    response
+        .answer
+        .iter()
+        .chain(&response.authority)
+        .chain(&response.additional)
+        .map(|record| record.ttl)
+        .min()
+}
+
+/// Checks there's room in the cache for another response, removing expired ones if not.
+fn make_room(cache: &mut HashMap<CacheKey, Slot>) -> bool {
+    if cache.len() < MAX_CACHE_ENTRIES {
+        return true;
+    }
+    cache.retain(|_, slot| match slot {
+        Slot::Cached(cached) => !cached.is_expired(),
+        Slot::InFlight(_) => true,
+    });
+    cache.len() < MAX_CACHE_ENTRIES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        server::{Server, Zone},
+        zone::parse_zone,
+    };
+    use std::{
+        net::UdpSocket,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    /// Answers from a zone, slowly, counting how many queries it gets. Claims every answer is
+    /// authentic, which the proxy shouldn't pass on.
+    struct Upstream {
+        zone: Zone,
+        queries: Arc<AtomicUsize>,
+    }
+
+    impl Handler for Upstream {
+        fn answer(&self, query: &Message) -> Message {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(200));
+            let mut response = self.zone.answer(query);
+            response.header.authentic_data = true;
+            response
+        }
+    }
+
+    /// Starts an upstream server, returning its address and its query counter.
+    fn upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+        let zone = "
+$TTL 300
+@   SOA ns1 hostmaster 1 7200 1800 1209600 60
+    NS  ns1
+ns1 A   192.0.2.1
+www 30  A   192.0.2.2
+";
+        let zone = Zone::new(parse_zone(zone, "example.com.").unwrap()).unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let upstream = Upstream {
+            zone,
+            queries: queries.clone(),
+        };
+        let server = Server::bind(upstream, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        (addr, queries)
+    }
+
+    fn ask(proxy: &Proxy, name: &str, record_type: RecordType) -> Message {
+        let query = Message::new_query(99, name.to_owned(), record_type, None).unwrap();
+        proxy.answer(&query)
+    }
+
+    /// Pretends the cached response for the name was cached this many seconds ago.
+    fn age_cache(proxy: &Proxy, name: &str, record_type: RecordType, secs: u64) {
+        let key = (name.to_owned(), record_type, Class::IN);
+        let mut cache = proxy.cache.lock().unwrap();
+        let Some(Slot::Cached(cached)) = cache.get_mut(&key) else {
+            panic!("{name} isn't cached");
+        };
+        cached.cached_at -= Duration::from_secs(secs);
+    }
+
+    #[test]
+    fn test_cache_hits_decrement_ttl() {
+        let (addr, queries) = upstream();
+        let proxy = Proxy::new(vec![addr], Transport::Udp);
+        let resp = ask(&proxy, "www.example.com.", RecordType::A);
+        assert_eq!(resp.header.id, 99);
+        assert!(resp.header.recursion_available);
+        assert!(!resp.header.authentic_data);
+        assert_eq!(resp.answer[0].ttl, 30);
+
+        age_cache(&proxy, "www.example.com.", RecordType::A, 10);
+        let resp = ask(&proxy, "WWW.example.com.", RecordType::A);
+        assert_eq!(resp.question[0].name, "WWW.example.com.");
+        assert_eq!(resp.answer[0].ttl, 20);
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+        // Once the TTL runs out, the proxy asks upstream again.
+        age_cache(&proxy, "www.example.com.", RecordType::A, 20);
+        let resp = ask(&proxy, "www.example.com.", RecordType::A);
+        assert_eq!(resp.answer[0].ttl, 30);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_negative_caching() {
+        let (addr, queries) = upstream();
+        let proxy = Proxy::new(vec![addr], Transport::Udp);
+        let resp = ask(&proxy, "nope.example.com.", RecordType::A);
+        assert_eq!(resp.header.resp_code, ResponseCode::NameError);
+        let resp = ask(&proxy, "nope.example.com.", RecordType::A);
+        assert_eq!(resp.header.resp_code, ResponseCode::NameError);
+        ask(&proxy, "www.example.com.", RecordType::Mx);
+        ask(&proxy, "www.example.com.", RecordType::Mx);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        // Negative responses are cached for the SOA's MINIMUM, not its TTL.
+        age_cache(&proxy, "nope.example.com.", RecordType::A, 60);
+        ask(&proxy, "nope.example.com.", RecordType::A);
+        assert_eq!(queries.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_coalesces_identical_queries() {
+        let (addr, queries) = upstream();
+        let proxy = Arc::new(Proxy::new(vec![addr], Transport::Udp));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let proxy = proxy.clone();
+                thread::spawn(move || ask(&proxy, "ns1.example.com.", RecordType::A))
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap().answer.len(), 1);
+        }
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_upstream_failover() {
+        // Nothing is listening on this port, so queries to it fail straight away.
+        let dead = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (addr, _) = upstream();
+        let proxy = Proxy::new(vec![dead, addr], Transport::Udp);
+        let resp = ask(&proxy, "ns1.example.com.", RecordType::A);
+        assert_eq!(resp.header.resp_code, ResponseCode::NoError);
+
+        let proxy = Proxy::new(vec![dead], Transport::Udp);
+        let resp = ask(&proxy, "ns1.example.com.", RecordType::A);
+        assert_eq!(resp.header.resp_code, ResponseCode::ServerFailure);
+        // Failures aren't cached.
+        assert!(proxy.cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_waiters_wake_if_asking_upstream_panics() {
+        let proxy = Arc::new(Proxy::new(Vec::new(), Transport::Udp));
+        let key = ("www.example.com.".to_owned(), RecordType::A, Class::IN);
+        let pending = Arc::new(Pending::default());
+        proxy
+            .cache
+            .lock()
+            .unwrap()
+            .insert(key.clone(), Slot::InFlight(pending.clone()));
+        let waiter = thread::spawn(move || wait_for(&pending));
+
+        let asker = {
+            let proxy = proxy.clone();
+            let key = key.clone();
+            thread::spawn(move || {
+                let pending = match proxy.cache().get(&key) {
+                    Some(Slot::InFlight(pending)) => pending.clone(),
+                    _ => unreachable!(),
+                };
+                let _guard = PendingGuard {
+                    proxy: &proxy,
+                    key,
+                    pending,
+                    result: None,
+                };
+                // Poison the cache's mutex too.
+                let _cache = proxy.cache.lock().unwrap();
+                panic!("upstream exploded");
+            })
+        };
+        assert!(asker.join().is_err());
+        assert!(waiter.join().unwrap().is_err());
+        // The next query isn't stuck waiting either.
+        assert!(proxy.cache().is_empty());
+    }
+}
@@ -0,0 +1,583 @@
+//! Answering queries over UDP and TCP. Zones are answered authoritatively, from the records
+//! in a zone file, using the algorithm from
+//! <https://datatracker.ietf.org/doc/html/rfc1034#section-4.3.2> (minus wildcards).
+use crate::{
+    cname::MAX_CHAIN_LENGTH,
+    io::{debug, Log},
+    message::{
+        edns::{Edns, DEFAULT_UDP_PAYLOAD_SIZE},
+        header::{Header, Opcode, ResponseCode},
+        name::{is_subdomain, same_name, split_labels},
+        question::Entry,
+        record::{Record, RecordData},
+        Message,
+    },
+    RecordType,
+};
+use anyhow::{anyhow, Result as AResult};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+/// Close TCP connections that have been idle for this long.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many UDP queries can be answered at once.
+const UDP_WORKERS: usize = 16;
+
+/// Something that can answer DNS queries, e.g. a zone or a proxy.
+pub trait Handler: Send + Sync + 'static {
+    /// Builds the response to a query.
+    fn answer(&self, query: &Message) -> Message;
+}
+
+/// The start of a response to the query, with no records or response code yet.
+/// Returns the query's only question, or None (with the response code set) if it's not
+/// a query that can be answered.
+pub(crate) fn empty_response(query: &Message) -> (Message, Option<&Entry>) {
+    let mut header = Header::new_query(query.header.id);
+    header.is_response = true;
+    header.opcode = query.header.opcode;
+    header.recursion_desired = query.header.recursion_desired;
+    let mut response = Message {
+        header,
+        question: query.question.clone(),
+        answer: Vec::new(),
+        authority: Vec::new(),
+        additional: Vec::new(),
+        edns: query
+            .edns
+            .as_ref()
+            .map(|_| Edns::new(DEFAULT_UDP_PAYLOAD_SIZE)),
+    };
+    let question = match query.question.as_slice() {
+        [_] if !matches!(query.header.opcode, Opcode::Query) => {
+            response.header.resp_code = ResponseCode::NotImplemented;
+            None
+        }
+        [question] => Some(question),
+        _ => {
+            response.header.resp_code = ResponseCode::FormatError;
+            None
+        }
+    };
+    (response, question)
+}
+
+/// The records of one zone, i.e. everything from its SOA record down to any delegations.
+#[derive(Debug)]
+pub struct Zone {
+    /// The zone's name, i.e. the owner of its SOA record.
+    pub origin: String,
+    records: Vec<Record>,
+}
+
+impl Zone {
+    /// Zones must have exactly one SOA record, and every other record must be in the zone
+    /// it starts.
+    pub fn new(records: Vec<Record>) -> AResult<Self> {
+        let mut soas = records
+            .iter()
+            .filter(|r| matches!(r.data, RecordData::Soa(_)));
+        let origin = soas
+            .next()
+            .ok_or_else(|| anyhow!("Zone has no SOA record"))?
+            .name
+            .clone();
+        if let Some(soa) = soas.next() {
+            anyhow::bail!("Zone has a second SOA record, for {}", soa.name);
+        }
+        if let Some(outside) = records.iter().find(|r| !is_subdomain(&r.name, &origin)) {
+            anyhow::bail!("{} is outside the zone {origin}", outside.name);
+        }
+        Ok(Self { origin, records })
+    }
+
+    /// If the name is at or below a delegation to a child zone, returns the NS records
+    /// for the child zone. DS records at a delegation belong to the parent zone, not the
+    /// child, so queries for them are answered here.
+    /// See <https://datatracker.ietf.org/doc/html/rfc4035#section-3.1.4.1>
+    fn delegation(&self, name: &str, record_type: RecordType) -> Option<Vec<Record>> {
+        // The topmost delegation wins, because anything below it isn't this zone's to give.
+        let cut = self
+            .records
+            .iter()
+            .filter(|r| matches!(r.data, RecordData::Ns(_)))
+            .filter(|r| !same_name(&r.name, &self.origin) && is_subdomain(name, &r.name))
+            .filter(|r| !(record_type == RecordType::Ds && same_name(&r.name, name)))
+            .min_by_key(|r| split_labels(&r.name).len())?;
+        Some(
+            self.records
+                .iter()
+                .filter(|r| matches!(r.data, RecordData::Ns(_)) && same_name(&r.name, &cut.name))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Finds the addresses of the given name servers, so resolvers following a delegation
+    /// don't have to look them up separately.
+    fn glue(&self, name_servers: &[Record]) -> Vec<Record> {
+        let mut glue = Vec::new();
+        for ns in name_servers {
+            let RecordData::Ns(ns_name) = &ns.data else {
+                continue;
+            };
+            glue.extend(
+                self.records
+                    .iter()
+                    .filter(|r| matches!(r.data, RecordData::A(_) | RecordData::Aaaa(_)))
+                    .filter(|r| same_name(&r.name, ns_name))
+                    .cloned(),
+            );
+        }
+        glue
+    }
+
+    /// Names exist if they have records, or if any name below them does.
+    fn name_exists(&self, name: &str) -> bool {
+        self.records.iter().any(|r| is_subdomain(&r.name, name))
+    }
+
+    /// The SOA record to put in negative responses. Its TTL is how long resolvers may cache
+    /// the negative response for, which is the smaller of its TTL and MINIMUM field.
+    /// See <https://datatracker.ietf.org/doc/html/rfc2308#section-3>
+    fn negative_soa(&self) -> Record {
+        let mut soa = self
+            .records
+            .iter()
+            .find(|r| matches!(r.data, RecordData::Soa(_)))
+            .expect("Zone::new checks there's an SOA record")
+            .clone();
+        if let RecordData::Soa(data) = &soa.data {
+            soa.ttl = soa.ttl.min(data.minimum);
+        }
+        soa
+    }
+}
+
+impl Handler for Zone {
+    fn answer(&self, query: &Message) -> Message {
+        let (mut response, question) = empty_response(query);
+        let Some(question) = question else {
+            return response;
+        };
+        if !is_subdomain(&question.name, &self.origin) {
+            response.header.resp_code = ResponseCode::Refused;
+            return response;
+        }
+        response.header.authoritative_answer = true;
+
+        let record_type = question.record_type;
+        let mut name = question.name.clone();
+        for _ in 0..MAX_CHAIN_LENGTH {
+            if let Some(name_servers) = self.delegation(&name, record_type) {
+                // The name is in a child zone, so this zone isn't the authority for it.
+                // Any CNAMEs already in the answer are still authoritative though.
+                response.header.authoritative_answer = !response.answer.is_empty();
+                response.additional = self.glue(&name_servers);
+                response.authority = name_servers;
+                return response;
+            }
+            let at_name: Vec<_> = self
+                .records
+                .iter()
+                .filter(|r| same_name(&r.name, &name))
+                .collect();
+            let matching: Vec<_> = at_name
+                .iter()
+                .filter(|r| r.data.as_type() == record_type)
+                .map(|r| (*r).clone())
+                .collect();
+            if !matching.is_empty() {
+                response.answer.extend(matching);
+                return response;
+            }
+            let cname = at_name.iter().find_map(|r| match &r.data {
+                RecordData::Cname(target) => Some((r, target)),
+                _ => None,
+            });
+            if let Some((record, target)) = cname {
+                response.answer.push((*record).clone());
+                let looped = response.answer.iter().any(|r| same_name(&r.name, target));
+                // Resolvers will follow CNAMEs into other zones themselves.
+                if looped || !is_subdomain(target, &self.origin) {
+                    return response;
+                }
+                name = target.clone();
+                continue;
+            }
+            if !self.name_exists(&name) {
+                response.header.resp_code = ResponseCode::NameError;
+            }
+            response.authority.push(self.negative_soa());
+            return response;
+        }
+        response
+    }
+}
+
+/// Answers queries over UDP and TCP, using the handler.
+pub struct Server<H> {
+    handler: H,
+    udp: UdpSocket,
+    tcp: TcpListener,
+    /// Where to report queries that couldn't be answered, e.g. because a TCP connection broke.
+    pub log: Option<Log>,
+}
+
+impl<H: Handler> Server<H> {
+    /// Listens on the given address, for both UDP and TCP.
+    pub fn bind(handler: H, addr: SocketAddr) -> AResult<Self> {
+        let udp = UdpSocket::bind(addr)
+            .map_err(|e| anyhow!("couldn't listen on {addr} over UDP: {e}"))?;
+        // If the port was 0, use whichever port UDP got for TCP too.
+        let addr = udp.local_addr()?;
+        let tcp = TcpListener::bind(addr)
+            .map_err(|e| anyhow!("couldn't listen on {addr} over TCP: {e}"))?;
+        Ok(Self {
+            handler,
+            udp,
+            tcp,
+            log: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> AResult<SocketAddr> {
+        Ok(self.udp.local_addr()?)
+    }
+
+    /// Answers queries forever, unless the UDP socket fails.
+    pub fn run(self) -> AResult<()> {
+        let handler = Arc::new(self.handler);
+        let tcp_handler = handler.clone();
+        let tcp = self.tcp;
+        let log = self.log;
+        thread::spawn(move || {
+            for stream in tcp.incoming() {
+                let handler = tcp_handler.clone();
+                match stream {
+                    Ok(stream) => {
+                        thread::spawn(move || {
+                            if let Err(e) = serve_tcp(&*handler, stream) {
+                                debug(log, || format!("TCP connection failed: {e}"));
+                            }
+                        });
+                    }
+                    Err(e) => debug(log, || format!("couldn't accept TCP connection: {e}")),
+                }
+            }
+        });
+        // Answering can be slow (e.g. if it means asking another server), so several
+        // workers share the socket. They only stop if it fails, so stop as soon as one does.
+        let (done, finished) = mpsc::channel();
+        for _ in 0..UDP_WORKERS {
+            let socket = self.udp.try_clone()?;
+            let handler = handler.clone();
+            let done = done.clone();
+            thread::spawn(move || done.send(serve_udp(&*handler, socket, log)));
+        }
+        drop(done);
+        finished
+            .recv()
+            .map_err(|_| anyhow!("every UDP worker panicked"))?
+    }
+}
+
+/// Answers queries sent to the UDP socket, until it fails.
+fn serve_udp(handler: &impl Handler, socket: UdpSocket, log: Option<Log>) -> AResult<()> {
+    let mut buf = vec![0; u16::MAX as usize];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if is_transient(&e) => {
+                debug(log, || format!("couldn't receive a query: {e}"));
+                continue;
+            }
+            Err(e) => return Err(anyhow!("couldn't receive a query: {e}")),
+        };
+        let Some(response) = respond(handler, &buf[..len], true) else {
+            continue;
+        };
+        if let Err(e) = socket.send_to(&response, peer) {
+            debug(log, || format!("couldn't send response to {peer}: {e}"));
+        }
+    }
+}
+
+/// Errors which are about one client rather than the socket, so the next query could still
+/// be fine. E.g. Windows reports ECONNRESET on a UDP socket after an earlier response got an
+/// ICMP port unreachable back, because the client had gone away.
+fn is_transient(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+    )
+}
+
+/// Answers every query sent over one TCP connection, until the client closes it.
+/// Each message is prefixed with its length, see
+/// <https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2>
+fn serve_tcp(handler: &impl Handler, mut stream: TcpStream) -> AResult<()> {
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    loop {
+        let mut len_buf = [0; 2];
+        match stream.read_exact(&mut len_buf) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            other => other?,
+        }
+        let mut request = vec![0; u16::from_be_bytes(len_buf).into()];
+        stream.read_exact(&mut request)?;
+        let Some(response) = respond(handler, &request, false) else {
+            continue;
+        };
+        let len = u16::try_from(response.len())
+            .map_err(|_| anyhow!("Response is {} bytes, too long for TCP", response.len()))?;
+        let mut framed = Vec::with_capacity(response.len() + 2);
+        framed.extend_from_slice(&len.to_be_bytes());
+        framed.extend_from_slice(&response);
+        stream.write_all(&framed)?;
+    }
+}
+
+/// Builds the binary response to a binary request. Returns None if there's no sensible
+/// response, e.g. if the request was itself a response.
+fn respond(handler: &impl Handler, request: &[u8], over_udp: bool) -> Option<Vec<u8>> {
+    let query = match Message::deserialize(request.to_vec()) {
+        Ok(query) => query,
+        Err(_) => {
+            // Reply with FORMERR, as long as there's an ID to reply with, and the request
+            // isn't a response (i.e. doesn't have the QR bit set).
+            let id = u16::from_be_bytes(request.get(..2)?.try_into().ok()?);
+            if request.get(2).is_some_and(|flags| flags & 0x80 != 0) {
+                return None;
+            }
+            let mut header = Header::new_query(id);
+            header.is_response = true;
+            header.resp_code = ResponseCode::FormatError;
+            let response = Message {
+                header,
+                question: Vec::new(),
+                answer: Vec::new(),
+                authority: Vec::new(),
+                additional: Vec::new(),
+                edns: None,
+            };
+            return response.serialize_bytes().ok();
+        }
+    };
+    if query.header.is_response {
+        return None;
+    }
+    let mut response = handler.answer(&query);
+    let bytes = response.serialize_bytes().ok()?;
+    if !over_udp || bytes.len() <= query.max_response_bytes() {
+        return Some(bytes);
+    }
+    // It doesn't fit in a UDP datagram, so tell the client to retry over TCP.
+    response.header.truncation = true;
+    response.answer.clear();
+    response.authority.clear();
+    response.additional.clear();
+    response.serialize_bytes().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::{send_req, Client, DEFAULT_TIMEOUT},
+        zone::parse_zone,
+        Transport,
+    };
+    use std::net::Ipv4Addr;
+
+    const ZONE: &str = r#"
+$TTL 3600
+@           SOA ns1 hostmaster 1 7200 1800 1209600 300
+            NS  ns1
+ns1         A   192.0.2.1
+www         A   192.0.2.2
+            A   192.0.2.3
+alias       CNAME www
+far         CNAME www.example.net.
+loop1       CNAME loop2
+loop2       CNAME loop1
+a.b.c       TXT "deep"
+child       NS  ns.child
+            DS  60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118
+ns.child    A   192.0.2.53
+"#;
+
+    fn zone() -> Zone {
+        Zone::new(parse_zone(ZONE, "example.com.").unwrap()).unwrap()
+    }
+
+    fn ask(zone: &Zone, name: &str, record_type: RecordType) -> Message {
+        let query = Message::new_query(7, name.to_owned(), record_type, None).unwrap();
+        zone.answer(&query)
+    }
+
+    fn names(records: &[Record]) -> Vec<String> {
+        records
+            .iter()
+            .map(|r| format!("{} {}", r.name, r.data))
+            .collect()
+    }
+
+    #[test]
+    fn test_answer() {
+        let zone = zone();
+        let resp = ask(&zone, "WWW.example.com.", RecordType::A);
+        assert_eq!(resp.header.id, 7);
+        assert!(resp.header.is_response && resp.header.authoritative_answer);
+        assert_eq!(resp.header.resp_code, ResponseCode::NoError);
+        assert_eq!(
+            names(&resp.answer),
+            vec!["www.example.com. 192.0.2.2", "www.example.com. 192.0.2.3"]
+        );
+    }
+
+    #[test]
+    fn test_negative_answers() {
+        let zone = zone();
+        let nxdomain = ask(&zone, "nope.example.com.", RecordType::A);
+        assert_eq!(nxdomain.header.resp_code, ResponseCode::NameError);
+        assert!(nxdomain.header.authoritative_answer);
+        assert!(nxdomain.answer.is_empty());
+        assert_eq!(nxdomain.authority.len(), 1);
+        // The SOA's MINIMUM is lower than its TTL.
+        assert_eq!(nxdomain.authority[0].ttl, 300);
+
+        let nodata = ask(&zone, "www.example.com.", RecordType::Mx);
+        assert_eq!(nodata.header.resp_code, ResponseCode::NoError);
+        assert!(nodata.answer.is_empty());
+        assert_eq!(nodata.authority[0].data.as_type(), RecordType::Soa);
+
+        // b.c has no records, but a name below it does, so it exists.
+        let empty_non_terminal = ask(&zone, "b.c.example.com.", RecordType::A);
+        assert_eq!(empty_non_terminal.header.resp_code, ResponseCode::NoError);
+
+        let refused = ask(&zone, "example.net.", RecordType::A);
+        assert_eq!(refused.header.resp_code, ResponseCode::Refused);
+        assert!(!refused.header.authoritative_answer);
+    }
+
+    #[test]
+    fn test_delegation() {
+        let zone = zone();
+        let resp = ask(&zone, "www.child.example.com.", RecordType::A);
+        assert!(!resp.header.authoritative_answer);
+        assert!(resp.answer.is_empty());
+        assert_eq!(
+            names(&resp.authority),
+            vec!["child.example.com. ns.child.example.com."]
+        );
+        assert_eq!(
+            names(&resp.additional),
+            vec!["ns.child.example.com. 192.0.2.53"]
+        );
+
+        // The parent zone is the authority for the DS records at the cut.
+        let ds = ask(&zone, "child.example.com.", RecordType::Ds);
+        assert!(ds.header.authoritative_answer);
+        assert_eq!(ds.answer.len(), 1);
+        assert_eq!(ds.answer[0].data.as_type(), RecordType::Ds);
+        assert!(ds.authority.is_empty());
+        // But not for DS records further down.
+        let below = ask(&zone, "www.child.example.com.", RecordType::Ds);
+        assert!(!below.header.authoritative_answer);
+        assert_eq!(below.authority, resp.authority);
+    }
+
+    #[test]
+    fn test_cnames() {
+        let zone = zone();
+        let resp = ask(&zone, "alias.example.com.", RecordType::A);
+        assert_eq!(
+            names(&resp.answer),
+            vec![
+                "alias.example.com. www.example.com.",
+                "www.example.com. 192.0.2.2",
+                "www.example.com. 192.0.2.3"
+            ]
+        );
+        // Asking for the CNAME itself doesn't follow it.
+        let resp = ask(&zone, "alias.example.com.", RecordType::Cname);
+        assert_eq!(resp.answer.len(), 1);
+        // Targets in other zones are left for the resolver.
+        let resp = ask(&zone, "far.example.com.", RecordType::A);
+        assert_eq!(
+            names(&resp.answer),
+            vec!["far.example.com. www.example.net."]
+        );
+        assert_eq!(resp.header.resp_code, ResponseCode::NoError);
+        let resp = ask(&zone, "loop1.example.com.", RecordType::A);
+        assert_eq!(resp.answer.len(), 2);
+    }
+
+    #[test]
+    fn test_serve_udp_and_tcp() {
+        // Lots of records, so the response doesn't fit in 512 bytes without EDNS.
+        let mut zone = ZONE.to_owned();
+        for i in 0..40 {
+            zone.push_str(&format!("big A 10.0.0.{i}\n"));
+        }
+        let zone = Zone::new(parse_zone(&zone, "example.com.").unwrap()).unwrap();
+        let server = Server::bind(zone, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut client = Client::new(addr);
+        let resp = client.query("ns1.example.com.", RecordType::A).unwrap();
+        assert!(resp.header.authoritative_answer);
+        assert_eq!(
+            resp.answer[0].data,
+            RecordData::A(Ipv4Addr::new(192, 0, 2, 1))
+        );
+
+        // The UDP response is truncated, so the client retries over TCP.
+        client.edns = None;
+        let resp = client.query("big.example.com.", RecordType::A).unwrap();
+        assert!(!resp.header.truncation);
+        assert_eq!(resp.answer.len(), 40);
+
+        client.transport = Transport::Tcp;
+        let resp = client.query("nope.example.com.", RecordType::A).unwrap();
+        assert_eq!(resp.header.resp_code, ResponseCode::NameError);
+    }
+
+    #[test]
+    fn test_unanswerable_queries() {
+        let server = Server::bind(zone(), "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        // NOTIFY (opcode 4) isn't implemented.
+        let mut notify =
+            Message::new_query(9, "example.com.".to_owned(), RecordType::Soa, None).unwrap();
+        notify.header.opcode = Opcode::Other(4);
+        let (resp, len) = send_req(notify, addr, &Transport::Udp, DEFAULT_TIMEOUT, None).unwrap();
+        let resp = Message::deserialize(resp[..len].to_vec()).unwrap();
+        assert_eq!(resp.header.id, 9);
+        assert_eq!(resp.header.opcode, Opcode::Other(4));
+        assert_eq!(resp.header.resp_code, ResponseCode::NotImplemented);
+
+        // Requests that don't even have a whole header still get FORMERR, with their ID.
+        let resp = respond(&zone(), &[0x12, 0x34, 0x01], true).unwrap();
+        let resp = Message::deserialize(resp).unwrap();
+        assert_eq!(resp.header.id, 0x1234);
+        assert_eq!(resp.header.resp_code, ResponseCode::FormatError);
+        // Unless they're responses, which shouldn't be answered.
+        assert_eq!(respond(&zone(), &[0x12, 0x34, 0x81], true), None);
+        assert_eq!(respond(&zone(), &[0x12], true), None);
+    }
+}
@@ -0,0 +1,17 @@
+//! Helpers shared by the unit tests in different modules.
+use crate::{message::record::RecordData, Class, Record};
+
+/// A record in the IN class, with the given TTL.
+pub fn record_with_ttl(name: &str, ttl: u32, data: RecordData) -> Record {
+    Record {
+        name: name.to_owned(),
+        class: Class::IN,
+        ttl,
+        data,
+    }
+}
+
+/// A record in the IN class, with a 5 minute TTL.
+pub fn record(name: &str, data: RecordData) -> Record {
+    record_with_ttl(name, 300, data)
+}
@@ -0,0 +1,1464 @@
+//! DNSSEC validation: checking that a response's records were signed by the zones they came
+//! from, following the chain of trust down from a trust anchor through each zone's DS and
+//! DNSKEY records. See <https://datatracker.ietf.org/doc/html/rfc4035#section-5>
+//!
+//! Validating needs more records than the response itself has (the DS and DNSKEY records of
+//! every zone above it), so like [`crate::cname::follow_cnames`], the [`Validator`] takes a
+//! closure to look them up. That could query a resolver, or read responses recorded earlier.
+use std::{cmp::Ordering, collections::HashMap, fmt};
+
+use anyhow::Result as AResult;
+use data_encoding::{BASE32HEX_NOPAD, HEXUPPER};
+use ring::{digest, signature};
+use serde::Serialize;
+
+use crate::{
+    message::{
+        dnssec::{format_timestamp, DnskeyData, DsData, Nsec3Data, NsecData, RrsigData},
+        name::{is_subdomain, name_to_labels, same_name, split_labels},
+        record::{Record, RecordData},
+        Message,
+    },
+    Class, RecordType, ResponseCode,
+};
+
+/// DNSSEC algorithm numbers, from <https://www.iana.org/assignments/dns-sec-alg-numbers>
+const RSASHA1: u8 = 5;
+const RSASHA1_NSEC3_SHA1: u8 = 7;
+const RSASHA256: u8 = 8;
+const RSASHA512: u8 = 10;
+const ECDSAP256SHA256: u8 = 13;
+const ECDSAP384SHA384: u8 = 14;
+const ED25519: u8 = 15;
+
+/// NSEC3 records which hash names more times than this are ignored, as RFC 9276 allows,
+/// because checking them takes too long. Zones like that are treated as unsigned.
+/// See <https://datatracker.ietf.org/doc/html/rfc9276#section-3.2>
+pub const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+/// The root zone's key signing keys, from <https://data.iana.org/root-anchors/root-anchors.xml>:
+/// KSK-2017, and KSK-2024, which replaces it.
+const ROOT_ANCHORS: [(u16, &str); 2] = [
+    (
+        20326,
+        "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D",
+    ),
+    (
+        38696,
+        "683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16",
+    ),
+];
+
+/// The four results of validation, from <https://datatracker.ietf.org/doc/html/rfc4033#section-5>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Every record was signed, along an unbroken chain of trust from the trust anchor.
+    Secure,
+    /// The records are from a zone which is provably unsigned, because its parent proved it
+    /// has no DS records.
+    Insecure,
+    /// The records should have been signed, but the signatures (or the proof that records
+    /// don't exist) are missing or wrong.
+    Bogus,
+    /// Validation couldn't decide, e.g. because the records it needed couldn't be looked up.
+    Indeterminate,
+}
+
+impl Status {
+    /// How far the status can be trusted, for finding the weakest of several.
+    fn strength(self) -> u8 {
+        match self {
+            Self::Bogus => 0,
+            Self::Indeterminate => 1,
+            Self::Insecure => 2,
+            Self::Secure => 3,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Secure => "secure",
+            Self::Insecure => "insecure",
+            Self::Bogus => "bogus",
+            Self::Indeterminate => "indeterminate",
+        };
+        s.fmt(f)
+    }
+}
+
+/// The result of validating a response, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Validation {
+    pub status: Status,
+    pub reason: String,
+}
+
+impl Validation {
+    fn new(status: Status, reason: impl Into<String>) -> Self {
+        Self {
+            status,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.status, self.reason)
+    }
+}
+
+/// The DS records of the zone that validation starts from. Everything it validates must be in
+/// this zone, or under it.
+#[derive(Debug, Clone)]
+pub struct TrustAnchor {
+    pub zone: String,
+    pub ds: Vec<DsData>,
+}
+
+impl TrustAnchor {
+    /// The root zone's key signing keys, as published by IANA.
+    pub fn root() -> Self {
+        let ds = ROOT_ANCHORS
+            .iter()
+            .map(|(key_tag, digest)| DsData {
+                key_tag: *key_tag,
+                algorithm: RSASHA256,
+                digest_type: 2,
+                digest: HEXUPPER.decode(digest.as_bytes()).unwrap(),
+            })
+            .collect();
+        Self {
+            zone: ".".to_owned(),
+            ds,
+        }
+    }
+
+    /// Uses DS records (e.g. from a zone file) as the trust anchor. They must all be for the
+    /// same zone.
+    pub fn from_records(records: &[Record]) -> AResult<Self> {
+        let Some(first) = records.first() else {
+            anyhow::bail!("A trust anchor needs at least one DS record");
+        };
+        let mut ds = Vec::new();
+        for record in records {
+            match &record.data {
+                RecordData::Ds(data) if same_name(&record.name, &first.name) => {
+                    ds.push(data.clone())
+                }
+                RecordData::Ds(_) => anyhow::bail!(
+                    "Trust anchors must all be for the same zone, but there's one for {} and one for {}",
+                    first.name,
+                    record.name
+                ),
+                data => anyhow::bail!(
+                    "Trust anchors must be DS records, but there's a {} record",
+                    data.as_type()
+                ),
+            }
+        }
+        Ok(Self {
+            zone: first.name.clone(),
+            ds,
+        })
+    }
+}
+
+/// What's known about the link between a name and its parent in the chain of trust.
+#[derive(Debug, Clone)]
+enum Link {
+    /// The name is a signed zone, with these (authenticated) keys.
+    Secure(Vec<DnskeyData>),
+    /// There's no zone cut here, so the name is part of its parent's zone.
+    NotACut,
+    /// The name is an unsigned zone, for this reason.
+    Insecure(String),
+}
+
+/// The zone a name is in, as far as the chain of trust could follow it.
+enum Walk {
+    Secure { zone: String, keys: Vec<DnskeyData> },
+    Insecure(String),
+}
+
+/// The result of checking an RRset's signatures, when it isn't bogus.
+enum Checked {
+    Secure {
+        /// The zone that signed it.
+        signer: String,
+        /// If the RRset was made from a wildcard, the name the wildcard was under.
+        wildcard: Option<String>,
+    },
+    Insecure(String),
+}
+
+/// Validates responses, remembering the keys of each zone it authenticates along the way.
+pub struct Validator<Q> {
+    anchor: TrustAnchor,
+    /// Signatures are checked as of this time, in seconds since the Unix epoch.
+    now: u32,
+    query: Q,
+    /// Keyed by lowercase name.
+    links: HashMap<String, Link>,
+}
+
+impl<Q: FnMut(&str, RecordType) -> AResult<Message>> Validator<Q> {
+    /// `query` looks up the DS and DNSKEY records of each zone in the chain of trust. The
+    /// responses it returns must include RRSIGs, i.e. the query must set the DNSSEC OK bit.
+    pub fn new(anchor: TrustAnchor, now: u32, query: Q) -> Self {
+        Self {
+            anchor,
+            now,
+            query,
+            links: HashMap::new(),
+        }
+    }
+
+    /// Checks the signatures of every RRset in the answer and authority sections, and, if the
+    /// answer doesn't have the records that were asked for, the proof that they don't exist.
+    pub fn validate(&mut self, response: &Message) -> Validation {
+        match self.check_response(response, false) {
+            Ok(validation) | Err(validation) => validation,
+        }
+    }
+
+    /// Validates every response it took to follow a CNAME chain (see
+    /// [`crate::cname::Chain`]), and returns the weakest result. The earlier responses end
+    /// with a CNAME whose target was looked up separately, so only their records are checked.
+    pub fn validate_chain(&mut self, earlier: &[Message], last: &Message) -> Validation {
+        let mut validations: Vec<_> = earlier
+            .iter()
+            .map(|response| match self.check_response(response, true) {
+                Ok(validation) | Err(validation) => validation,
+            })
+            .collect();
+        validations.push(self.validate(last));
+        // If they're all as strong as each other, the last response's reason is the most
+        // useful, because it's about the records that were asked for.
+        validations
+            .into_iter()
+            .rev()
+            .min_by_key(|validation| validation.status.strength())
+            .expect("there's always the last response")
+    }
+
+    /// If `cname_only` is set, the response can end at a CNAME without the target's records
+    /// (or proof they don't exist).
+    fn check_response(
+        &mut self,
+        response: &Message,
+        cname_only: bool,
+    ) -> Result<Validation, Validation> {
+        let [question] = response.question.as_slice() else {
+            return Err(Validation::new(
+                Status::Indeterminate,
+                "The response doesn't have exactly one question",
+            ));
+        };
+        let rcode = response.header.resp_code;
+        if !matches!(rcode, ResponseCode::NoError | ResponseCode::NameError) {
+            return Err(Validation::new(
+                Status::Indeterminate,
+                format!(
+                    "The response was {}, so there's nothing to validate",
+                    rcode.mnemonic()
+                ),
+            ));
+        }
+
+        // NS records in the authority section are just hints about where the zone is, and
+        // aren't signed when they're a delegation, so they don't need checking.
+        let answer = rrsets(&response.answer);
+        let authority: Vec<_> = rrsets(&response.authority)
+            .into_iter()
+            .filter(|rrset| rrset.record_type != RecordType::Ns)
+            .collect();
+        let mut insecure = None;
+        let mut signers: Vec<String> = Vec::new();
+        let mut wildcards = Vec::new();
+        let mut denial = Denial::default();
+        for (i, rrset) in answer.iter().chain(&authority).enumerate() {
+            match self.check_rrset(rrset)? {
+                Checked::Insecure(reason) => {
+                    insecure.get_or_insert(reason);
+                }
+                Checked::Secure { signer, wildcard } => {
+                    if i >= answer.len() {
+                        denial.add(rrset, &signer);
+                    } else if let Some(closest_encloser) = wildcard {
+                        wildcards.push((rrset.name, closest_encloser));
+                    }
+                    if !signers.iter().any(|zone| same_name(zone, &signer)) {
+                        signers.push(signer);
+                    }
+                }
+            }
+        }
+        if let Some(reason) = insecure {
+            return Ok(Validation::new(Status::Insecure, reason));
+        }
+
+        // Records made from a wildcard are only valid if the name they're for doesn't exist.
+        for (name, closest_encloser) in wildcards {
+            denial
+                .no_closer_match(name, &closest_encloser)
+                .map_err(|reason| {
+                    Validation::new(
+                        Status::Bogus,
+                        format!("{name} was made from a wildcard, but {reason}"),
+                    )
+                })?;
+        }
+
+        // Follow any CNAMEs to find the name the records asked for should be at.
+        let mut name = question.name.as_str();
+        let record_type = question.record_type;
+        let mut found = false;
+        for _ in 0..=crate::cname::MAX_CHAIN_LENGTH {
+            found = answer
+                .iter()
+                .any(|rrset| same_name(rrset.name, name) && rrset.record_type == record_type);
+            let target = answer
+                .iter()
+                .find_map(|rrset| match &rrset.records[0].data {
+                    RecordData::Cname(target) if same_name(rrset.name, name) => Some(target),
+                    _ => None,
+                });
+            match target {
+                Some(target) if !found => name = target,
+                _ => break,
+            }
+        }
+        let signed_by = signers.join(", ");
+        if found {
+            return Ok(Validation::new(
+                Status::Secure,
+                format!("The answer was signed by {signed_by}"),
+            ));
+        }
+        if cname_only && !same_name(name, &question.name) {
+            return Ok(Validation::new(
+                Status::Secure,
+                format!("The CNAMEs to {name} were signed by {signed_by}"),
+            ));
+        }
+
+        let (proof, claim) = if rcode == ResponseCode::NameError {
+            (denial.name_doesnt_exist(name), "doesn't exist".to_owned())
+        } else {
+            (
+                denial.type_doesnt_exist(name, record_type),
+                format!("has no {record_type} records"),
+            )
+        };
+        match proof {
+            Ok(()) => Ok(Validation::new(
+                Status::Secure,
+                format!("{signed_by} proved {name} {claim}"),
+            )),
+            Err(reason) => {
+                if denial.too_many_iterations {
+                    return Ok(Validation::new(
+                        Status::Insecure,
+                        format!(
+                            "The zone's NSEC3 records use over {MAX_NSEC3_ITERATIONS} iterations"
+                        ),
+                    ));
+                }
+                // Without any signed records, the zone might just be unsigned.
+                if signers.is_empty() {
+                    if let Walk::Insecure(reason) = self.walk(name)? {
+                        return Ok(Validation::new(Status::Insecure, reason));
+                    }
+                }
+                Err(Validation::new(
+                    Status::Bogus,
+                    format!("Couldn't prove {name} {claim}: {reason}"),
+                ))
+            }
+        }
+    }
+
+    /// Checks an RRset was signed by the zone it's in, or that the zone is unsigned.
+    fn check_rrset(&mut self, rrset: &RRset<'_>) -> Result<Checked, Validation> {
+        let Some(first) = rrset.signatures.first() else {
+            return match self.walk(rrset.name)? {
+                Walk::Insecure(reason) => Ok(Checked::Insecure(reason)),
+                Walk::Secure { zone, .. } => Err(Validation::new(
+                    Status::Bogus,
+                    format!("{rrset} isn't signed, but it's in {zone}, which is"),
+                )),
+            };
+        };
+        let signer = first.signer_name.clone();
+        if !is_subdomain(rrset.name, &signer) {
+            return Err(Validation::new(
+                Status::Bogus,
+                format!("{rrset} is signed by {signer}, which isn't a zone it could be in"),
+            ));
+        }
+        let keys = match self.walk(&signer)? {
+            Walk::Insecure(reason) => return Ok(Checked::Insecure(reason)),
+            Walk::Secure { zone, keys } if same_name(&zone, &signer) => keys,
+            Walk::Secure { zone, .. } => {
+                return Err(Validation::new(
+                    Status::Bogus,
+                    format!(
+                        "{rrset} is signed by {signer}, but the chain of trust says it's in {zone}"
+                    ),
+                ))
+            }
+        };
+        let rrsig = verify_signed_by(rrset, &signer, &keys, self.now)
+            .map_err(|reason| Validation::new(Status::Bogus, format!("{rrset}: {reason}")))?;
+        // The labels field says how many labels the signed name had, so a shorter count
+        // means the records came from a wildcard under that many labels.
+        let labels = split_labels(rrset.name);
+        let wildcard = (usize::from(rrsig.labels) < label_count(rrset.name))
+            .then(|| ancestor(&labels, labels.len() - usize::from(rrsig.labels)));
+        Ok(Checked::Secure { signer, wildcard })
+    }
+
+    /// Follows the chain of trust from the trust anchor down towards `name`, and returns the
+    /// keys of the deepest signed zone containing it, or why it's in an unsigned zone.
+    fn walk(&mut self, name: &str) -> Result<Walk, Validation> {
+        if !is_subdomain(name, &self.anchor.zone) {
+            return Ok(Walk::Insecure(format!(
+                "{name} isn't under the trust anchor for {}",
+                self.anchor.zone
+            )));
+        }
+        let mut zone = self.anchor.zone.clone();
+        let mut keys = self.anchor_keys()?;
+        let labels = split_labels(name);
+        let anchor_depth = split_labels(&zone).len();
+        for skipped in (0..labels.len() - anchor_depth).rev() {
+            let child = ancestor(&labels, skipped);
+            match self.link(&zone, &keys, &child)? {
+                Link::Secure(child_keys) => (zone, keys) = (child, child_keys),
+                Link::NotACut => {}
+                Link::Insecure(reason) => return Ok(Walk::Insecure(reason)),
+            }
+        }
+        Ok(Walk::Secure { zone, keys })
+    }
+
+    fn anchor_keys(&mut self) -> Result<Vec<DnskeyData>, Validation> {
+        let cache_key = self.anchor.zone.to_ascii_lowercase();
+        if let Some(Link::Secure(keys)) = self.links.get(&cache_key) {
+            return Ok(keys.clone());
+        }
+        let zone = self.anchor.zone.clone();
+        let ds: Vec<_> = self
+            .anchor
+            .ds
+            .iter()
+            .filter(|ds| is_usable(ds))
+            .cloned()
+            .collect();
+        if ds.is_empty() {
+            return Err(Validation::new(
+                Status::Indeterminate,
+                "None of the trust anchor's DS records use algorithms dingo supports",
+            ));
+        }
+        let keys = self.zone_keys(&zone, &ds)?;
+        self.links.insert(cache_key, Link::Secure(keys.clone()));
+        Ok(keys)
+    }
+
+    /// Works out whether `child` is a zone cut under `parent`, and if so, whether it's signed.
+    fn link(
+        &mut self,
+        parent: &str,
+        parent_keys: &[DnskeyData],
+        child: &str,
+    ) -> Result<Link, Validation> {
+        let cache_key = child.to_ascii_lowercase();
+        if let Some(link) = self.links.get(&cache_key) {
+            return Ok(link.clone());
+        }
+        let response = self.lookup(child, RecordType::Ds)?;
+        let answer = rrsets(&response.answer);
+        let ds_rrset = answer
+            .iter()
+            .find(|rrset| same_name(rrset.name, child) && rrset.record_type == RecordType::Ds);
+        let link = if let Some(rrset) = ds_rrset {
+            // DS records are in the parent zone, so the parent's keys sign them.
+            verify_signed_by(rrset, parent, parent_keys, self.now)
+                .map_err(|reason| Validation::new(Status::Bogus, format!("{rrset}: {reason}")))?;
+            let ds: Vec<_> = rrset
+                .records
+                .iter()
+                .filter_map(|record| match &record.data {
+                    RecordData::Ds(ds) if is_usable(ds) => Some(ds.clone()),
+                    _ => None,
+                })
+                .collect();
+            // RFC 4035 says to treat zones like this as unsigned.
+            if ds.is_empty() {
+                Link::Insecure(format!(
+                    "{child}'s DS records only use algorithms dingo doesn't support"
+                ))
+            } else {
+                Link::Secure(self.zone_keys(child, &ds)?)
+            }
+        } else {
+            // No DS records means either there's no zone cut, or there's an unsigned one.
+            // Only trust that it's unsigned if the parent proves it, otherwise an attacker
+            // could strip the DS records to make a signed zone look unsigned.
+            let mut denial = Denial::default();
+            for rrset in rrsets(&response.authority) {
+                if verify_signed_by(&rrset, parent, parent_keys, self.now).is_ok() {
+                    denial.add(&rrset, parent);
+                }
+            }
+            if denial.insecure_delegation(child) {
+                Link::Insecure(format!(
+                    "{parent} proved {child} has no DS records, so it isn't signed"
+                ))
+            } else {
+                Link::NotACut
+            }
+        };
+        self.links.insert(cache_key, link.clone());
+        Ok(link)
+    }
+
+    /// Looks up a zone's DNSKEYs, and checks they were signed by a key one of its DS
+    /// records is for.
+    fn zone_keys(&mut self, zone: &str, ds: &[DsData]) -> Result<Vec<DnskeyData>, Validation> {
+        let response = self.lookup(zone, RecordType::Dnskey)?;
+        let answer = rrsets(&response.answer);
+        let Some(rrset) = answer
+            .iter()
+            .find(|rrset| same_name(rrset.name, zone) && rrset.record_type == RecordType::Dnskey)
+        else {
+            return Err(Validation::new(
+                Status::Bogus,
+                format!("{zone} has DS records, but no DNSKEY records"),
+            ));
+        };
+        let keys: Vec<DnskeyData> = rrset
+            .records
+            .iter()
+            .filter_map(|record| match &record.data {
+                RecordData::Dnskey(key) => Some(key.clone()),
+                _ => None,
+            })
+            .collect();
+        let entry_keys: Vec<DnskeyData> = keys
+            .iter()
+            .filter(|key| ds.iter().any(|ds| ds_matches(ds, zone, key)))
+            .cloned()
+            .collect();
+        if entry_keys.is_empty() {
+            return Err(Validation::new(
+                Status::Bogus,
+                format!("None of {zone}'s DNSKEY records match its DS records"),
+            ));
+        }
+        verify_signed_by(rrset, zone, &entry_keys, self.now)
+            .map_err(|reason| Validation::new(Status::Bogus, format!("{rrset}: {reason}")))?;
+        Ok(keys)
+    }
+
+    fn lookup(&mut self, name: &str, record_type: RecordType) -> Result<Message, Validation> {
+        let response = (self.query)(name, record_type).map_err(|e| {
+            Validation::new(
+                Status::Indeterminate,
+                format!("Couldn't look up {record_type} records for {name}: {e:#}"),
+            )
+        })?;
+        let rcode = response.header.resp_code;
+        if !matches!(rcode, ResponseCode::NoError | ResponseCode::NameError) {
+            return Err(Validation::new(
+                Status::Indeterminate,
+                format!(
+                    "Looking up {record_type} records for {name} gave {}",
+                    rcode.mnemonic()
+                ),
+            ));
+        }
+        Ok(response)
+    }
+}
+
+/// All the records of one type at one name, and the signatures over them.
+struct RRset<'a> {
+    name: &'a str,
+    record_type: RecordType,
+    class: Class,
+    records: Vec<&'a Record>,
+    signatures: Vec<&'a RrsigData>,
+}
+
+impl fmt::Display for RRset<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.record_type)
+    }
+}
+
+/// Groups records into RRsets, in the order they first appear, and puts each RRSIG with the
+/// RRset it covers. RRSIGs for RRsets that aren't there are dropped.
+fn rrsets(records: &[Record]) -> Vec<RRset<'_>> {
+    let mut rrsets: Vec<RRset<'_>> = Vec::new();
+    let find = |rrsets: &[RRset<'_>], name: &str, record_type, class| {
+        rrsets.iter().position(|rrset| {
+            same_name(rrset.name, name) && rrset.record_type == record_type && rrset.class == class
+        })
+    };
+    for record in records {
+        let record_type = record.data.as_type();
+        if record_type == RecordType::Rrsig {
+            continue;
+        }
+        match find(&rrsets, &record.name, record_type, record.class) {
+            Some(i) => rrsets[i].records.push(record),
+            None => rrsets.push(RRset {
+                name: &record.name,
+                record_type,
+                class: record.class,
+                records: vec![record],
+                signatures: Vec::new(),
+            }),
+        }
+    }
+    for record in records {
+        if let RecordData::Rrsig(rrsig) = &record.data {
+            if let Some(i) = find(&rrsets, &record.name, rrsig.type_covered, record.class) {
+                rrsets[i].signatures.push(rrsig);
+            }
+        }
+    }
+    rrsets
+}
+
+/// Checks that one of the RRSIGs that `signer` made over the RRset is valid.
+fn verify_signed_by<'a>(
+    rrset: &RRset<'a>,
+    signer: &str,
+    keys: &[DnskeyData],
+    now: u32,
+) -> Result<&'a RrsigData, String> {
+    let mut failures = Vec::new();
+    for &rrsig in &rrset.signatures {
+        if !same_name(&rrsig.signer_name, signer) {
+            continue;
+        }
+        match verify_rrsig(rrset, rrsig, keys, now) {
+            Ok(()) => return Ok(rrsig),
+            Err(reason) => failures.push(reason),
+        }
+    }
+    if failures.is_empty() {
+        Err(format!("it isn't signed by {signer}"))
+    } else {
+        Err(failures.join(", and "))
+    }
+}
+
+fn verify_rrsig(
+    rrset: &RRset<'_>,
+    rrsig: &RrsigData,
+    keys: &[DnskeyData],
+    now: u32,
+) -> Result<(), String> {
+    if usize::from(rrsig.labels) > split_labels(rrset.name).len() {
+        return Err("the RRSIG says the name has more labels than it does".to_owned());
+    }
+    // Times are compared with serial number arithmetic, so they can wrap around in 2106.
+    // See <https://datatracker.ietf.org/doc/html/rfc4034#section-3.1.5>
+    if (now.wrapping_sub(rrsig.inception) as i32) < 0 {
+        return Err(format!(
+            "the signature isn't valid until {}",
+            format_timestamp(rrsig.inception)
+        ));
+    }
+    if (rrsig.expiration.wrapping_sub(now) as i32) < 0 {
+        return Err(format!(
+            "the signature expired at {}",
+            format_timestamp(rrsig.expiration)
+        ));
+    }
+    let keys: Vec<_> = keys
+        .iter()
+        .filter(|key| {
+            key.algorithm == rrsig.algorithm
+                && key.key_tag() == rrsig.key_tag
+                && key.is_zone_key()
+                && key.protocol == 3
+        })
+        .collect();
+    if keys.is_empty() {
+        return Err(format!(
+            "there's no DNSKEY with key tag {} and algorithm {}",
+            rrsig.key_tag, rrsig.algorithm
+        ));
+    }
+    let signed_data = signed_data(rrset, rrsig).map_err(|e| format!("{e:#}"))?;
+    let mut result = Err(String::new());
+    for key in keys {
+        result = verify_signature(key, &signed_data, &rrsig.signature);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result.map_err(|reason| format!("the signature by key {} {reason}", rrsig.key_tag))
+}
+
+/// The data an RRSIG signs: its own RDATA (minus the signature), then each record in the
+/// RRset in canonical form and order. See <https://datatracker.ietf.org/doc/html/rfc4034#section-3.1.8.1>
+fn signed_data(rrset: &RRset<'_>, rrsig: &RrsigData) -> AResult<Vec<u8>> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&u16::from(rrsig.type_covered).to_be_bytes());
+    data.extend_from_slice(&[rrsig.algorithm, rrsig.labels]);
+    for field in [rrsig.original_ttl, rrsig.expiration, rrsig.inception] {
+        data.extend_from_slice(&field.to_be_bytes());
+    }
+    data.extend_from_slice(&rrsig.key_tag.to_be_bytes());
+    data.extend_from_slice(&canonical_name(&rrsig.signer_name)?);
+
+    // Records made from a wildcard were signed with the wildcard as their name.
+    let labels = split_labels(rrset.name);
+    let owner = if usize::from(rrsig.labels) < label_count(rrset.name) {
+        let closest_encloser = ancestor(&labels, labels.len() - usize::from(rrsig.labels));
+        canonical_name(&wildcard_of(&closest_encloser))?
+    } else {
+        canonical_name(rrset.name)?
+    };
+    let mut rdatas = rrset
+        .records
+        .iter()
+        .map(|record| record.data.to_canonical_wire())
+        .collect::<AResult<Vec<_>>>()?;
+    rdatas.sort();
+    rdatas.dedup();
+    for rdata in rdatas {
+        data.extend_from_slice(&owner);
+        data.extend_from_slice(&u16::from(rrset.record_type).to_be_bytes());
+        data.extend_from_slice(&u16::from(rrset.class).to_be_bytes());
+        data.extend_from_slice(&rrsig.original_ttl.to_be_bytes());
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend_from_slice(&rdata);
+    }
+    Ok(data)
+}
+
+fn verify_signature(key: &DnskeyData, message: &[u8], sig: &[u8]) -> Result<(), String> {
+    let result = match key.algorithm {
+        RSASHA1 | RSASHA1_NSEC3_SHA1 => verify_rsa(
+            &signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY,
+            &key.public_key,
+            message,
+            sig,
+        ),
+        RSASHA256 => verify_rsa(
+            &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+            &key.public_key,
+            message,
+            sig,
+        ),
+        RSASHA512 => verify_rsa(
+            &signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY,
+            &key.public_key,
+            message,
+            sig,
+        ),
+        // The key is the curve point without the 0x04 prefix that marks it as uncompressed.
+        ECDSAP256SHA256 | ECDSAP384SHA384 => {
+            let algorithm = if key.algorithm == ECDSAP256SHA256 {
+                &signature::ECDSA_P256_SHA256_FIXED
+            } else {
+                &signature::ECDSA_P384_SHA384_FIXED
+            };
+            let mut point = vec![0x04];
+            point.extend_from_slice(&key.public_key);
+            signature::UnparsedPublicKey::new(algorithm, point).verify(message, sig)
+        }
+        ED25519 => signature::UnparsedPublicKey::new(&signature::ED25519, &key.public_key)
+            .verify(message, sig),
+        algorithm => return Err(format!("uses algorithm {algorithm}, which isn't supported")),
+    };
+    result.map_err(|_| "is wrong".to_owned())
+}
+
+/// RSA keys are the exponent's length, the exponent, then the modulus.
+/// See <https://datatracker.ietf.org/doc/html/rfc3110#section-2>
+fn verify_rsa(
+    params: &'static signature::RsaParameters,
+    key: &[u8],
+    message: &[u8],
+    sig: &[u8],
+) -> Result<(), ring::error::Unspecified> {
+    let (&len, rest) = key.split_first().ok_or(ring::error::Unspecified)?;
+    // Exponents over 255 bytes have a zero, then a two-byte length.
+    let (len, rest) = match len {
+        0 => {
+            let (len, rest) = rest.split_at_checked(2).ok_or(ring::error::Unspecified)?;
+            (usize::from(u16::from_be_bytes([len[0], len[1]])), rest)
+        }
+        len => (usize::from(len), rest),
+    };
+    let (e, n) = rest.split_at_checked(len).ok_or(ring::error::Unspecified)?;
+    let first_nonzero = n.iter().position(|&byte| byte != 0).unwrap_or(n.len());
+    signature::RsaPublicKeyComponents {
+        n: &n[first_nonzero..],
+        e,
+    }
+    .verify(params, message, sig)
+}
+
+fn is_supported(algorithm: u8) -> bool {
+    matches!(
+        algorithm,
+        RSASHA1
+            | RSASHA1_NSEC3_SHA1
+            | RSASHA256
+            | RSASHA512
+            | ECDSAP256SHA256
+            | ECDSAP384SHA384
+            | ED25519
+    )
+}
+
+/// Can the DS record be checked, i.e. are its key's algorithm and its digest supported?
+fn is_usable(ds: &DsData) -> bool {
+    is_supported(ds.algorithm) && ds_digest(ds.digest_type).is_some()
+}
+
+/// DS digest types, from <https://www.iana.org/assignments/ds-rr-types>
+fn ds_digest(digest_type: u8) -> Option<&'static digest::Algorithm> {
+    match digest_type {
+        1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+        2 => Some(&digest::SHA256),
+        4 => Some(&digest::SHA384),
+        _ => None,
+    }
+}
+
+/// Is the DS record a digest of this zone key? See <https://datatracker.ietf.org/doc/html/rfc4034#section-5.1.4>
+fn ds_matches(ds: &DsData, zone: &str, key: &DnskeyData) -> bool {
+    if ds.key_tag != key.key_tag() || ds.algorithm != key.algorithm || !key.is_zone_key() {
+        return false;
+    }
+    let (Some(algorithm), Ok(mut data)) = (ds_digest(ds.digest_type), canonical_name(zone)) else {
+        return false;
+    };
+    data.extend_from_slice(&key.to_wire());
+    digest::digest(algorithm, &data).as_ref() == ds.digest
+}
+
+/// A name in wire format, uncompressed and in lowercase.
+fn canonical_name(name: &str) -> AResult<Vec<u8>> {
+    let mut wire = Vec::new();
+    for label in name_to_labels(name)? {
+        wire.push(label.len() as u8);
+        wire.extend(label.iter().map(u8::to_ascii_lowercase));
+    }
+    wire.push(0);
+    Ok(wire)
+}
+
+/// Orders names the way DNSSEC does: by their last label, then the one before, etc, comparing
+/// labels as lowercase bytes. See <https://datatracker.ietf.org/doc/html/rfc4034#section-6.1>
+fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name| -> Vec<Vec<u8>> {
+        let mut labels = name_to_labels(name).unwrap_or_default();
+        labels
+            .iter_mut()
+            .for_each(|label| label.make_ascii_lowercase());
+        labels.reverse();
+        labels
+    };
+    labels(a).cmp(&labels(b))
+}
+
+/// The name made of `labels` without its first `skip` labels.
+fn ancestor(labels: &[&str], skip: usize) -> String {
+    match &labels[skip..] {
+        [] => ".".to_owned(),
+        rest => format!("{}.", rest.join(".")),
+    }
+}
+
+/// How many labels an RRSIG for `name` should say it has. A wildcard's `*` isn't counted, so
+/// records at the wildcard itself aren't mistaken for records made from it.
+/// See <https://datatracker.ietf.org/doc/html/rfc4034#section-3.1.3>
+fn label_count(name: &str) -> usize {
+    let labels = split_labels(name);
+    match labels.first() {
+        Some(&"*") => labels.len() - 1,
+        _ => labels.len(),
+    }
+}
+
+fn wildcard_of(name: &str) -> String {
+    if name == "." {
+        "*.".to_owned()
+    } else {
+        format!("*.{name}")
+    }
+}
+
+/// The longest name that both names are equal to or under.
+fn common_ancestor(a: &str, b: &str) -> String {
+    let (a, b) = (split_labels(a), split_labels(b));
+    let common = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+        .count();
+    ancestor(&a, a.len() - common)
+}
+
+/// The authenticated NSEC and NSEC3 records in a response, which prove that names or types
+/// don't exist.
+#[derive(Default)]
+struct Denial<'a> {
+    /// Each NSEC record, with its owner name and the zone that signed it.
+    nsecs: Vec<(&'a str, String, &'a NsecData)>,
+    nsec3s: Vec<Nsec3<'a>>,
+    /// Whether any NSEC3 records were ignored for having too many iterations.
+    too_many_iterations: bool,
+}
+
+struct Nsec3<'a> {
+    /// The hash in the owner name, i.e. the first label, decoded.
+    owner_hash: Vec<u8>,
+    /// The zone the record is in, i.e. the rest of the owner name.
+    zone: String,
+    data: &'a Nsec3Data,
+}
+
+impl Nsec3<'_> {
+    /// Hashes a name with this record's salt and iterations, if the name is in its zone.
+    /// See <https://datatracker.ietf.org/doc/html/rfc5155#section-5>
+    fn hash(&self, name: &str) -> Option<Vec<u8>> {
+        if !is_subdomain(name, &self.zone) {
+            return None;
+        }
+        let mut hash = canonical_name(name).ok()?;
+        for _ in 0..=self.data.iterations {
+            let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+            context.update(&hash);
+            context.update(&self.data.salt);
+            hash = context.finish().as_ref().to_vec();
+        }
+        Some(hash)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.hash(name).is_some_and(|hash| hash == self.owner_hash)
+    }
+
+    /// Does this record prove there's no name with a hash between its owner and next hashes?
+    fn covers(&self, name: &str) -> bool {
+        let Some(hash) = self.hash(name) else {
+            return false;
+        };
+        let (owner, next) = (&self.owner_hash, &self.data.next_hashed_owner);
+        // The last record in the zone points back to the first.
+        if next <= owner {
+            &hash > owner || &hash < next
+        } else {
+            owner < &hash && &hash < next
+        }
+    }
+
+    /// A record at a delegation (NS but no SOA) is from the parent, so it can't prove
+    /// anything about names in the child zone.
+    fn is_delegation(&self) -> bool {
+        self.data.types.contains(&RecordType::Ns) && !self.data.types.contains(&RecordType::Soa)
+    }
+}
+
+impl<'a> Denial<'a> {
+    /// Adds an RRset's NSEC or NSEC3 records, if it has any. It must already be authenticated.
+    fn add(&mut self, rrset: &RRset<'a>, signer: &str) {
+        for record in &rrset.records {
+            match &record.data {
+                RecordData::Nsec(nsec) => self.nsecs.push((rrset.name, signer.to_owned(), nsec)),
+                // Only SHA-1 has been defined.
+                RecordData::Nsec3(nsec3) if nsec3.hash_algorithm == 1 => {
+                    if nsec3.iterations > MAX_NSEC3_ITERATIONS {
+                        self.too_many_iterations = true;
+                        continue;
+                    }
+                    let labels = split_labels(rrset.name);
+                    let Some(first) = labels.first() else {
+                        continue;
+                    };
+                    let Ok(owner_hash) =
+                        BASE32HEX_NOPAD.decode(first.to_ascii_uppercase().as_bytes())
+                    else {
+                        continue;
+                    };
+                    self.nsec3s.push(Nsec3 {
+                        owner_hash,
+                        zone: ancestor(&labels, 1),
+                        data: nsec3,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Finds an NSEC record proving there are no names between its owner and next names,
+    /// including `name`.
+    fn nsec_covering(&self, name: &str) -> Option<&(&'a str, String, &'a NsecData)> {
+        self.nsecs.iter().find(|(owner, zone, nsec)| {
+            // An NSEC at a delegation is from the parent, so it says nothing about the child.
+            let delegation =
+                nsec.types.contains(&RecordType::Ns) && !nsec.types.contains(&RecordType::Soa);
+            if delegation && is_subdomain(name, owner) {
+                return false;
+            }
+            let next = &nsec.next_domain_name;
+            // The last NSEC in the zone points back to the apex.
+            let last = canonical_cmp(next, owner) != Ordering::Greater;
+            is_subdomain(name, zone)
+                && canonical_cmp(owner, name) == Ordering::Less
+                && (last || canonical_cmp(name, next) == Ordering::Less)
+        })
+    }
+
+    /// Finds the closest encloser of `name` (the longest ancestor of it that exists), and
+    /// the NSEC3 records proving that it exists and the next name down from it doesn't.
+    /// See <https://datatracker.ietf.org/doc/html/rfc5155#section-8.3>
+    fn closest_encloser(&self, name: &str) -> Option<(String, &Nsec3<'a>)> {
+        let labels = split_labels(name);
+        for skip in 1..=labels.len() {
+            let candidate = ancestor(&labels, skip);
+            let Some(nsec3) = self.nsec3s.iter().find(|nsec3| nsec3.matches(&candidate)) else {
+                continue;
+            };
+            if nsec3.is_delegation() {
+                return None;
+            }
+            let next_closer = ancestor(&labels, skip - 1);
+            return self
+                .nsec3s
+                .iter()
+                .find(|nsec3| nsec3.covers(&next_closer))
+                .map(|cover| (candidate, cover));
+        }
+        None
+    }
+
+    /// Proves `name` doesn't exist, and that there's no wildcard that could have matched it.
+    fn name_doesnt_exist(&self, name: &str) -> Result<(), String> {
+        if let Some((owner, _, nsec)) = self.nsec_covering(name) {
+            // A wildcard would have to be at the closest encloser, which is the longest
+            // name that's an ancestor of both the name and one end of the NSEC.
+            let closest_encloser = [
+                common_ancestor(name, owner),
+                common_ancestor(name, &nsec.next_domain_name),
+            ]
+            .into_iter()
+            .max_by_key(|ancestor| split_labels(ancestor).len())
+            .unwrap();
+            let wildcard = wildcard_of(&closest_encloser);
+            return match self.nsec_covering(&wildcard) {
+                Some(_) => Ok(()),
+                None => Err(format!(
+                    "there's no NSEC record proving there's no wildcard {wildcard}"
+                )),
+            };
+        }
+        if let Some((closest_encloser, _)) = self.closest_encloser(name) {
+            let wildcard = wildcard_of(&closest_encloser);
+            return match self.nsec3s.iter().any(|nsec3| nsec3.covers(&wildcard)) {
+                true => Ok(()),
+                false => Err(format!(
+                    "there's no NSEC3 record proving there's no wildcard {wildcard}"
+                )),
+            };
+        }
+        Err("there's no NSEC or NSEC3 record covering it".to_owned())
+    }
+
+    /// Proves `name` exists, but has no records of the type (or a CNAME instead).
+    fn type_doesnt_exist(&self, name: &str, record_type: RecordType) -> Result<(), String> {
+        let has_type = |types: &[RecordType]| {
+            types.contains(&record_type) || types.contains(&RecordType::Cname)
+        };
+        // At a delegation, the parent's record only knows about the DS records. Everything
+        // else is in the child zone. See <https://datatracker.ietf.org/doc/html/rfc6840#section-4.1>
+        let delegation = |types: &[RecordType]| {
+            record_type != RecordType::Ds
+                && types.contains(&RecordType::Ns)
+                && !types.contains(&RecordType::Soa)
+        };
+        if let Some((_, _, nsec)) = self
+            .nsecs
+            .iter()
+            .find(|(owner, _, _)| same_name(owner, name))
+        {
+            return if delegation(&nsec.types) {
+                Err(format!(
+                    "the NSEC record for {name} is from the parent zone of a delegation"
+                ))
+            } else if has_type(&nsec.types) {
+                Err(format!(
+                    "the NSEC record for {name} says it has {record_type} records"
+                ))
+            } else {
+                Ok(())
+            };
+        }
+        if let Some(nsec3) = self.nsec3s.iter().find(|nsec3| nsec3.matches(name)) {
+            return if delegation(&nsec3.data.types) {
+                Err(format!(
+                    "the NSEC3 record for {name} is from the parent zone of a delegation"
+                ))
+            } else if has_type(&nsec3.data.types) {
+                Err(format!(
+                    "the NSEC3 record for {name} says it has {record_type} records"
+                ))
+            } else {
+                Ok(())
+            };
+        }
+        // Opt-out NSEC3 records skip unsigned delegations, so they can't prove there's no
+        // DS there, only that it's unsigned. See <https://datatracker.ietf.org/doc/html/rfc5155#section-8.6>
+        if record_type == RecordType::Ds && self.opted_out(name) {
+            return Ok(());
+        }
+        Err("there's no NSEC or NSEC3 record for it".to_owned())
+    }
+
+    /// Proves there's no name closer to `name` than the wildcard it was made from.
+    /// See <https://datatracker.ietf.org/doc/html/rfc4035#section-5.3.4>
+    fn no_closer_match(&self, name: &str, closest_encloser: &str) -> Result<(), String> {
+        let labels = split_labels(name);
+        let next_closer = ancestor(
+            &labels,
+            labels.len() - split_labels(closest_encloser).len() - 1,
+        );
+        if self.nsec_covering(name).is_some()
+            || self.nsec3s.iter().any(|nsec3| nsec3.covers(&next_closer))
+        {
+            Ok(())
+        } else {
+            Err(format!(
+                "there's no NSEC or NSEC3 record proving {name} doesn't exist"
+            ))
+        }
+    }
+
+    /// Does this prove `name` is a delegation to an unsigned zone?
+    fn insecure_delegation(&self, name: &str) -> bool {
+        let unsigned_cut = |types: &[RecordType]| {
+            types.contains(&RecordType::Ns)
+                && !types.contains(&RecordType::Ds)
+                && !types.contains(&RecordType::Soa)
+        };
+        let nsec = self
+            .nsecs
+            .iter()
+            .find(|(owner, _, _)| same_name(owner, name));
+        if let Some((_, _, nsec)) = nsec {
+            return unsigned_cut(&nsec.types);
+        }
+        if let Some(nsec3) = self.nsec3s.iter().find(|nsec3| nsec3.matches(name)) {
+            return unsigned_cut(&nsec3.data.types);
+        }
+        self.opted_out(name)
+    }
+
+    /// Is `name` in a span of an opt-out NSEC3 record, which might skip unsigned delegations?
+    fn opted_out(&self, name: &str) -> bool {
+        self.closest_encloser(name)
+            .is_some_and(|(_, cover)| cover.data.opt_out())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::dnssec::parse_timestamp, zone::parse_zone};
+
+    /// Responses from a test hierarchy, signed under the trust anchor in root-anchor.zone:
+    /// example. is signed with ECDSA P-256 and uses NSEC, hashed. is signed with RSA/SHA-256
+    /// and uses NSEC3, and insecure. isn't signed. They're made by generate.py.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/dnssec");
+
+    fn fixture(name: &str, record_type: RecordType) -> AResult<Message> {
+        let name = match name.trim_end_matches('.') {
+            "" => "root",
+            name => name,
+        };
+        let path = format!("{FIXTURES}/{name}_{record_type}.bin");
+        let bytes = std::fs::read(&path).map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+        Ok(Message::deserialize(bytes)?)
+    }
+
+    fn anchor() -> TrustAnchor {
+        let text = std::fs::read_to_string(format!("{FIXTURES}/root-anchor.zone")).unwrap();
+        TrustAnchor::from_records(&parse_zone(&text, ".").unwrap()).unwrap()
+    }
+
+    fn now() -> u32 {
+        parse_timestamp("20260101000000").unwrap()
+    }
+
+    fn validate(name: &str, record_type: RecordType) -> Validation {
+        let response = fixture(name, record_type).unwrap();
+        Validator::new(anchor(), now(), fixture).validate(&response)
+    }
+
+    #[test]
+    fn test_secure() {
+        for (name, record_type) in [
+            ("www.example.", RecordType::A),
+            ("alias.example.", RecordType::A),
+            ("example.", RecordType::Dnskey),
+            ("hashed.", RecordType::Ds),
+        ] {
+            let validation = validate(name, record_type);
+            assert_eq!(validation.status, Status::Secure, "{name}: {validation}");
+        }
+        assert_eq!(
+            validate("www.example.", RecordType::A).reason,
+            "The answer was signed by example."
+        );
+    }
+
+    #[test]
+    fn test_denial() {
+        let nsec_nxdomain = validate("nx.example.", RecordType::A);
+        assert_eq!(nsec_nxdomain.status, Status::Secure, "{nsec_nxdomain}");
+        assert_eq!(
+            nsec_nxdomain.reason,
+            "example. proved nx.example. doesn't exist"
+        );
+        let nsec_nodata = validate("www.example.", RecordType::Mx);
+        assert_eq!(nsec_nodata.status, Status::Secure, "{nsec_nodata}");
+        let nsec3_nxdomain = validate("nx.hashed.", RecordType::A);
+        assert_eq!(nsec3_nxdomain.status, Status::Secure, "{nsec3_nxdomain}");
+        let nsec3_nodata = validate("www.hashed.", RecordType::Txt);
+        assert_eq!(nsec3_nodata.status, Status::Secure, "{nsec3_nodata}");
+
+        // A denial can't be used for a different question.
+        let mut response = fixture("www.example.", RecordType::Mx).unwrap();
+        response.question[0].record_type = RecordType::A;
+        let wrong_type = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(wrong_type.status, Status::Bogus, "{wrong_type}");
+        let mut response = fixture("nx.example.", RecordType::A).unwrap();
+        response.question[0].name = "www.example.".to_owned();
+        let exists = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(exists.status, Status::Bogus, "{exists}");
+        let mut response = fixture("www.example.", RecordType::Ds).unwrap();
+        response.question[0].record_type = RecordType::A;
+        let has_type = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(has_type.status, Status::Bogus, "{has_type}");
+
+        // The root's NSEC record for the insecure. delegation proves there's no DS there, but
+        // it can't prove anything about the records in insecure. itself.
+        let mut response = fixture("insecure.", RecordType::Ds).unwrap();
+        response.question[0].record_type = RecordType::A;
+        let delegation = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(delegation.status, Status::Bogus, "{delegation}");
+        assert!(
+            delegation.reason.contains("from the parent zone"),
+            "{delegation}"
+        );
+
+        // NXDOMAIN needs a second NSEC, proving there's no wildcard either.
+        let mut response = fixture("nx.example.", RecordType::A).unwrap();
+        response.authority.truncate(4);
+        let validation = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(
+            validation,
+            Validation::new(
+                Status::Bogus,
+                "Couldn't prove nx.example. doesn't exist: there's no NSEC record proving there's \
+                 no wildcard *.example."
+            )
+        );
+    }
+
+    #[test]
+    fn test_chain() {
+        // The resolver only sent the CNAME, so the address was looked up separately.
+        let mut alias = fixture("alias.example.", RecordType::A).unwrap();
+        alias
+            .answer
+            .retain(|r| same_name(&r.name, "alias.example."));
+        let www = fixture("www.example.", RecordType::A).unwrap();
+        let validation = Validator::new(anchor(), now(), fixture)
+            .validate_chain(std::slice::from_ref(&alias), &www);
+        assert_eq!(
+            validation,
+            Validation::new(Status::Secure, "The answer was signed by example.")
+        );
+        // On its own, the first response doesn't have the records that were asked for.
+        let validation = Validator::new(anchor(), now(), fixture).validate(&alias);
+        assert_eq!(validation.status, Status::Bogus, "{validation}");
+
+        // The weakest response decides, even if it's not the last one.
+        alias.answer[0].data = RecordData::Cname("www.insecure.".to_owned());
+        let validation = Validator::new(anchor(), now(), fixture)
+            .validate_chain(std::slice::from_ref(&alias), &www);
+        assert_eq!(validation.status, Status::Bogus, "{validation}");
+        assert_eq!(
+            validation.reason,
+            "alias.example. CNAME: the signature by key 29354 is wrong"
+        );
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let validation = validate("foo.wild.example.", RecordType::A);
+        assert_eq!(
+            validation,
+            Validation::new(Status::Secure, "The answer was signed by example.")
+        );
+
+        // Asking for the wildcard itself gets the same records, which weren't made from it.
+        let mut response = fixture("foo.wild.example.", RecordType::A).unwrap();
+        response.question[0].name = "*.wild.example.".to_owned();
+        response.authority.clear();
+        for record in &mut response.answer {
+            record.name = "*.wild.example.".to_owned();
+        }
+        let validation = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(
+            validation,
+            Validation::new(Status::Secure, "The answer was signed by example.")
+        );
+
+        // Without the NSEC record, there could be a real foo.wild.example. that the wildcard
+        // answer is hiding.
+        let mut response = fixture("foo.wild.example.", RecordType::A).unwrap();
+        response.authority.clear();
+        let validation = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(
+            validation,
+            Validation::new(
+                Status::Bogus,
+                "foo.wild.example. was made from a wildcard, but there's no NSEC or NSEC3 \
+                 record proving foo.wild.example. doesn't exist"
+            )
+        );
+    }
+
+    #[test]
+    fn test_insecure() {
+        let validation = validate("www.insecure.", RecordType::A);
+        assert_eq!(
+            validation,
+            Validation::new(
+                Status::Insecure,
+                ". proved insecure. has no DS records, so it isn't signed"
+            )
+        );
+    }
+
+    #[test]
+    fn test_bogus() {
+        // Changing a signed record breaks its signature.
+        let mut response = fixture("www.example.", RecordType::A).unwrap();
+        response.answer[0].data = RecordData::A([192, 0, 2, 99].into());
+        let validation = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(validation.status, Status::Bogus);
+        assert_eq!(
+            validation.reason,
+            "www.example. A: the signature by key 29354 is wrong"
+        );
+
+        // So does stripping the signatures.
+        let mut response = fixture("www.example.", RecordType::A).unwrap();
+        response
+            .answer
+            .retain(|r| r.data.as_type() != RecordType::Rrsig);
+        let validation = Validator::new(anchor(), now(), fixture).validate(&response);
+        assert_eq!(
+            validation.reason,
+            "www.example. A isn't signed, but it's in example., which is"
+        );
+
+        // The signatures expire in 2034.
+        let later = parse_timestamp("20350101000000").unwrap();
+        let response = fixture("www.example.", RecordType::A).unwrap();
+        let validation = Validator::new(anchor(), later, fixture).validate(&response);
+        assert_eq!(validation.status, Status::Bogus);
+        assert!(
+            validation.reason.contains("expired at 20340101000000"),
+            "{validation}"
+        );
+
+        // The real root keys didn't sign the test root zone.
+        let response = fixture("www.example.", RecordType::A).unwrap();
+        let validation = Validator::new(TrustAnchor::root(), now(), fixture).validate(&response);
+        assert_eq!(
+            validation,
+            Validation::new(
+                Status::Bogus,
+                "None of .'s DNSKEY records match its DS records"
+            )
+        );
+    }
+
+    #[test]
+    fn test_indeterminate() {
+        // Without the DS records for example., the chain of trust can't be followed.
+        let response = fixture("www.example.", RecordType::A).unwrap();
+        let query = |name: &str, record_type| match record_type {
+            RecordType::Ds => anyhow::bail!("timed out"),
+            _ => fixture(name, record_type),
+        };
+        let validation = Validator::new(anchor(), now(), query).validate(&response);
+        assert_eq!(
+            validation,
+            Validation::new(
+                Status::Indeterminate,
+                "Couldn't look up DS records for example.: timed out"
+            )
+        );
+    }
+
+    #[test]
+    fn test_root_anchor() {
+        // The DS digest in the root anchor matches the root KSK-2017 key.
+        let key = DnskeyData {
+            flags: 257,
+            protocol: 3,
+            algorithm: RSASHA256,
+            public_key: data_encoding::BASE64
+                .decode(
+                    b"AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLYA4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+                )
+                .unwrap(),
+        };
+        assert!(TrustAnchor::root()
+            .ds
+            .iter()
+            .any(|ds| ds_matches(ds, ".", &key)));
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        // The example from <https://datatracker.ietf.org/doc/html/rfc4034#section-6.1>
+        let names = [
+            "example.",
+            "a.example.",
+            "yljkjljk.a.example.",
+            "Z.a.example.",
+            "zABC.a.EXAMPLE.",
+            "z.example.",
+            "\\001.z.example.",
+            "*.z.example.",
+            "\\200.z.example.",
+        ];
+        for pair in names.windows(2) {
+            assert_eq!(canonical_cmp(pair[0], pair[1]), Ordering::Less, "{pair:?}");
+        }
+    }
+}
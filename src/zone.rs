@@ -0,0 +1,832 @@
+//! Reading and writing master files (better known as zone files), as described in
+//! <https://datatracker.ietf.org/doc/html/rfc1035#section-5>.
+use crate::{
+    message::{
+        deserialize_rdata,
+        dnssec::{
+            parse_timestamp, DnskeyData, DsData, Nsec3Data, Nsec3ParamData, NsecData, RrsigData,
+        },
+        name::{escape_label, name_to_labels},
+        record::{parse_duration, MxData, Record, RecordData, SoaData, SrvData},
+    },
+    Class, RecordType,
+};
+use anyhow::{anyhow, Context, Result as AResult};
+use data_encoding::{Encoding, BASE32HEX_NOPAD, BASE64, HEXLOWER_PERMISSIVE};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Stops zone files that include each other from recursing forever.
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Parses the records in a zone file's text. Relative names are relative to `origin`,
+/// until the file changes it with `$ORIGIN`. Files named by `$INCLUDE` are relative to the
+/// current directory.
+pub fn parse_zone(text: &str, origin: &str) -> AResult<Vec<Record>> {
+    let mut parser = ZoneParser::new(origin)?;
+    parser.parse_text(text, None, 0)?;
+    Ok(parser.records)
+}
+
+/// Reads and parses the records in a zone file. Files named by `$INCLUDE` are relative to
+/// the directory this file is in.
+pub fn read_zone_file(path: impl AsRef<Path>, origin: &str) -> AResult<Vec<Record>> {
+    let mut parser = ZoneParser::new(origin)?;
+    parser.parse_file(path.as_ref(), 0)?;
+    Ok(parser.records)
+}
+
+/// Writes records as a zone file, one record per line, with absolute names.
+pub fn write_zone(records: &[Record]) -> String {
+    let mut out = String::new();
+    for record in records {
+        writeln!(out, "{record}").unwrap();
+    }
+    out
+}
+
+/// One entry in a zone file. Parentheses let an entry span several lines.
+struct ZoneEntry {
+    /// The line the entry starts on, for error messages.
+    line: usize,
+    /// Entries starting with whitespace don't have an owner name, and use the previous one.
+    has_owner: bool,
+    tokens: Vec<Token>,
+}
+
+struct Token {
+    /// Escapes (like `\.` or `\032`) are left in, because what they mean depends on whether
+    /// the token is a name or a <character-string>.
+    text: String,
+    quoted: bool,
+}
+
+/// Splits a zone file into entries, removing comments and parentheses along the way.
+fn tokenize(text: &str) -> Result<Vec<ZoneEntry>, (usize, anyhow::Error)> {
+    let mut entries = Vec::new();
+    let mut line = 1;
+    let mut entry = ZoneEntry {
+        line,
+        has_owner: false,
+        tokens: Vec::new(),
+    };
+    let mut token: Option<Token> = None;
+    let mut parens_opened_on = None;
+    let mut at_line_start = true;
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if at_line_start && parens_opened_on.is_none() {
+            entry.line = line;
+            entry.has_owner = !ch.is_whitespace();
+        }
+        at_line_start = false;
+        if let Some(quoted) = token.as_mut().filter(|t| t.quoted) {
+            match ch {
+                '"' => end_token(&mut entry, &mut token),
+                '\\' => {
+                    quoted.text.push(ch);
+                    match chars.next() {
+                        Some('\n') | None => {
+                            return Err((line, anyhow!("Escape at the end of a line")))
+                        }
+                        Some(escaped) => quoted.text.push(escaped),
+                    }
+                }
+                '\n' => return Err((line, anyhow!("Quoted string isn't closed"))),
+                _ => quoted.text.push(ch),
+            }
+            continue;
+        }
+        match ch {
+            '\n' => {
+                end_token(&mut entry, &mut token);
+                if parens_opened_on.is_none() && !entry.tokens.is_empty() {
+                    entries.push(std::mem::replace(
+                        &mut entry,
+                        ZoneEntry {
+                            line,
+                            has_owner: false,
+                            tokens: Vec::new(),
+                        },
+                    ));
+                }
+                line += 1;
+                at_line_start = true;
+            }
+            ';' => {
+                end_token(&mut entry, &mut token);
+                while chars.next_if(|&ch| ch != '\n').is_some() {}
+            }
+            '(' => {
+                end_token(&mut entry, &mut token);
+                if parens_opened_on.is_some() {
+                    return Err((line, anyhow!("Parentheses can't be nested")));
+                }
+                parens_opened_on = Some(line);
+            }
+            ')' => {
+                end_token(&mut entry, &mut token);
+                if parens_opened_on.take().is_none() {
+                    return Err((line, anyhow!("Closing parenthesis without an opening one")));
+                }
+            }
+            '"' => {
+                end_token(&mut entry, &mut token);
+                token = Some(Token {
+                    text: String::new(),
+                    quoted: true,
+                });
+            }
+            _ if ch.is_whitespace() => end_token(&mut entry, &mut token),
+            _ => {
+                let unquoted = token.get_or_insert_with(|| Token {
+                    text: String::new(),
+                    quoted: false,
+                });
+                unquoted.text.push(ch);
+                if ch == '\\' {
+                    match chars.next() {
+                        Some('\n') | None => {
+                            return Err((line, anyhow!("Escape at the end of a line")))
+                        }
+                        Some(escaped) => unquoted.text.push(escaped),
+                    }
+                }
+            }
+        }
+    }
+    if token.as_ref().is_some_and(|t| t.quoted) {
+        return Err((line, anyhow!("Quoted string isn't closed")));
+    }
+    if let Some(opened_on) = parens_opened_on {
+        return Err((opened_on, anyhow!("Parenthesis is never closed")));
+    }
+    end_token(&mut entry, &mut token);
+    if !entry.tokens.is_empty() {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn end_token(entry: &mut ZoneEntry, token: &mut Option<Token>) {
+    if let Some(token) = token.take() {
+        entry.tokens.push(token);
+    }
+}
+
+struct ZoneParser {
+    origin: String,
+    /// Set by `$TTL`.
+    default_ttl: Option<u32>,
+    previous_owner: Option<String>,
+    previous_ttl: Option<u32>,
+    records: Vec<Record>,
+}
+
+impl ZoneParser {
+    fn new(origin: &str) -> AResult<Self> {
+        let origin = if origin.ends_with('.') {
+            origin.to_owned()
+        } else {
+            format!("{origin}.")
+        };
+        Ok(Self {
+            origin: normalize_name(&origin)?,
+            default_ttl: None,
+            previous_owner: None,
+            previous_ttl: None,
+            records: Vec::new(),
+        })
+    }
+
+    fn parse_file(&mut self, path: &Path, depth: usize) -> AResult<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read zone file {}", path.display()))?;
+        self.parse_text(&text, Some(path), depth)
+    }
+
+    fn parse_text(&mut self, text: &str, file: Option<&Path>, depth: usize) -> AResult<()> {
+        let at_line = |line: usize, err: anyhow::Error| match file {
+            Some(path) => anyhow!("{}, line {line}: {err:#}", path.display()),
+            None => anyhow!("line {line}: {err:#}"),
+        };
+        let entries = tokenize(text).map_err(|(line, err)| at_line(line, err))?;
+        for entry in entries {
+            let first = &entry.tokens[0];
+            let result = if entry.has_owner && !first.quoted && first.text.starts_with('$') {
+                self.parse_directive(&entry.tokens, file, depth)
+            } else {
+                self.parse_record(&entry)
+            };
+            result.map_err(|err| at_line(entry.line, err))?;
+        }
+        Ok(())
+    }
+
+    fn parse_directive(
+        &mut self,
+        tokens: &[Token],
+        file: Option<&Path>,
+        depth: usize,
+    ) -> AResult<()> {
+        let args = &tokens[1..];
+        match tokens[0].text.to_uppercase().as_str() {
+            "$ORIGIN" => {
+                expect_count(args, 1)?;
+                self.origin = self.absolute_name(&args[0])?;
+            }
+            "$TTL" => {
+                expect_count(args, 1)?;
+                self.default_ttl = Some(parse_duration(&args[0].text)?);
+            }
+            "$INCLUDE" => {
+                if args.is_empty() || args.len() > 2 {
+                    anyhow::bail!("$INCLUDE needs a file name, and optionally an origin");
+                }
+                if depth >= MAX_INCLUDE_DEPTH {
+                    anyhow::bail!("$INCLUDEs are nested over the max of {MAX_INCLUDE_DEPTH} deep");
+                }
+                let mut path = PathBuf::from(&args[0].text);
+                if let Some(dir) = file.and_then(Path::parent) {
+                    path = dir.join(path);
+                }
+                // The included file can change the origin, but that doesn't affect this file.
+                let origin = self.origin.clone();
+                if let Some(new_origin) = args.get(1) {
+                    self.origin = self.absolute_name(new_origin)?;
+                }
+                let result = self.parse_file(&path, depth + 1);
+                self.origin = origin;
+                result?;
+            }
+            other => anyhow::bail!("Unknown directive {other}"),
+        }
+        Ok(())
+    }
+
+    fn parse_record(&mut self, entry: &ZoneEntry) -> AResult<()> {
+        let mut tokens = entry.tokens.iter();
+        let name = if entry.has_owner {
+            self.absolute_name(tokens.next().unwrap())?
+        } else {
+            self.previous_owner
+                .clone()
+                .ok_or_else(|| anyhow!("Record has no name, and there's no previous one to use"))?
+        };
+
+        // The TTL and class are both optional, and can be in either order.
+        let mut tokens = tokens.as_slice();
+        let mut ttl = None;
+        let mut class = None;
+        while let Some((token, rest)) = tokens.split_first() {
+            if class.is_none() {
+                if let Ok(c) = Class::from_str(&token.text) {
+                    class = Some(c);
+                    tokens = rest;
+                    continue;
+                }
+            }
+            if ttl.is_none() && token.text.starts_with(|ch: char| ch.is_ascii_digit()) {
+                ttl = Some(parse_duration(&token.text)?);
+                tokens = rest;
+                continue;
+            }
+            break;
+        }
+        let (record_type, rdata) = tokens
+            .split_first()
+            .ok_or_else(|| anyhow!("Record for {name} has no type"))?;
+        let record_type = RecordType::from_str(&record_type.text).map_err(|e| anyhow!(e))?;
+        let data = self.parse_rdata(record_type, rdata)?;
+
+        let ttl = ttl
+            .or(self.default_ttl)
+            .or(self.previous_ttl)
+            .ok_or_else(|| anyhow!("Record for {name} has no TTL, and there's no $TTL"))?;
+        self.previous_owner = Some(name.clone());
+        self.previous_ttl = Some(ttl);
+        self.records.push(Record {
+            name,
+            class: class.unwrap_or(Class::IN),
+            ttl,
+            data,
+        });
+        Ok(())
+    }
+
+    fn parse_rdata(&self, record_type: RecordType, tokens: &[Token]) -> AResult<RecordData> {
+        if tokens.first().is_some_and(|t| !t.quoted && t.text == "\\#") {
+            return parse_generic_rdata(record_type, &tokens[1..]);
+        }
+        let data = match record_type {
+            RecordType::A => {
+                expect_count(tokens, 1)?;
+                let text = &tokens[0].text;
+                RecordData::A(
+                    text.parse()
+                        .with_context(|| format!("{text} is not a valid IPv4 address"))?,
+                )
+            }
+            RecordType::Aaaa => {
+                expect_count(tokens, 1)?;
+                let text = &tokens[0].text;
+                RecordData::Aaaa(
+                    text.parse()
+                        .with_context(|| format!("{text} is not a valid IPv6 address"))?,
+                )
+            }
+            RecordType::Cname | RecordType::Ns | RecordType::Ptr => {
+                expect_count(tokens, 1)?;
+                let name = self.absolute_name(&tokens[0])?;
+                match record_type {
+                    RecordType::Cname => RecordData::Cname(name),
+                    RecordType::Ns => RecordData::Ns(name),
+                    _ => RecordData::Ptr(name),
+                }
+            }
+            RecordType::Mx => {
+                expect_count(tokens, 2)?;
+                RecordData::Mx(MxData {
+                    preference: number(&tokens[0])?,
+                    exchange: self.absolute_name(&tokens[1])?,
+                })
+            }
+            RecordType::Srv => {
+                expect_count(tokens, 4)?;
+                RecordData::Srv(SrvData {
+                    priority: number(&tokens[0])?,
+                    weight: number(&tokens[1])?,
+                    port: number(&tokens[2])?,
+                    target: self.absolute_name(&tokens[3])?,
+                })
+            }
+            RecordType::Soa => {
+                expect_count(tokens, 7)?;
+                let serial = &tokens[2].text;
+                RecordData::Soa(SoaData {
+                    mname: self.absolute_name(&tokens[0])?,
+                    rname: self.absolute_name(&tokens[1])?,
+                    serial: serial
+                        .parse()
+                        .with_context(|| format!("{serial} is not a valid serial number"))?,
+                    refresh: parse_duration(&tokens[3].text)?,
+                    retry: parse_duration(&tokens[4].text)?,
+                    expire: parse_duration(&tokens[5].text)?,
+                    minimum: parse_duration(&tokens[6].text)?,
+                })
+            }
+            RecordType::Txt => {
+                if tokens.is_empty() {
+                    anyhow::bail!("TXT records need at least one string");
+                }
+                let strings = tokens
+                    .iter()
+                    .map(|token| unescape_character_string(&token.text))
+                    .collect::<AResult<_>>()?;
+                RecordData::Txt(strings)
+            }
+            RecordType::Dnskey => {
+                expect_at_least(tokens, 4)?;
+                RecordData::Dnskey(DnskeyData {
+                    flags: number(&tokens[0])?,
+                    protocol: number(&tokens[1])?,
+                    algorithm: number(&tokens[2])?,
+                    public_key: decode(&BASE64, &joined(&tokens[3..]))?,
+                })
+            }
+            RecordType::Ds => {
+                expect_at_least(tokens, 4)?;
+                RecordData::Ds(DsData {
+                    key_tag: number(&tokens[0])?,
+                    algorithm: number(&tokens[1])?,
+                    digest_type: number(&tokens[2])?,
+                    digest: decode(&HEXLOWER_PERMISSIVE, &joined(&tokens[3..]))?,
+                })
+            }
+            RecordType::Rrsig => {
+                expect_at_least(tokens, 9)?;
+                let type_covered = &tokens[0].text;
+                RecordData::Rrsig(RrsigData {
+                    type_covered: type_covered.parse().map_err(|e: String| anyhow!(e))?,
+                    algorithm: number(&tokens[1])?,
+                    labels: number(&tokens[2])?,
+                    original_ttl: parse_duration(&tokens[3].text)?,
+                    expiration: parse_timestamp(&tokens[4].text)?,
+                    inception: parse_timestamp(&tokens[5].text)?,
+                    key_tag: number(&tokens[6])?,
+                    signer_name: self.absolute_name(&tokens[7])?,
+                    signature: decode(&BASE64, &joined(&tokens[8..]))?,
+                })
+            }
+            RecordType::Nsec => {
+                expect_at_least(tokens, 1)?;
+                RecordData::Nsec(NsecData {
+                    next_domain_name: self.absolute_name(&tokens[0])?,
+                    types: parse_types(&tokens[1..])?,
+                })
+            }
+            RecordType::Nsec3 => {
+                expect_at_least(tokens, 5)?;
+                let next_hashed_owner = tokens[4].text.to_ascii_uppercase();
+                RecordData::Nsec3(Nsec3Data {
+                    hash_algorithm: number(&tokens[0])?,
+                    flags: number(&tokens[1])?,
+                    iterations: number(&tokens[2])?,
+                    salt: parse_salt(&tokens[3])?,
+                    next_hashed_owner: decode(&BASE32HEX_NOPAD, &next_hashed_owner)?,
+                    types: parse_types(&tokens[5..])?,
+                })
+            }
+            RecordType::Nsec3param => {
+                expect_count(tokens, 4)?;
+                RecordData::Nsec3param(Nsec3ParamData {
+                    hash_algorithm: number(&tokens[0])?,
+                    flags: number(&tokens[1])?,
+                    iterations: number(&tokens[2])?,
+                    salt: parse_salt(&tokens[3])?,
+                })
+            }
+            RecordType::Opt => anyhow::bail!("OPT pseudo-records can't be in zone files"),
+            RecordType::Unknown(_) => {
+                anyhow::bail!("{record_type} records must use the \\# format from RFC 3597")
+            }
+        };
+        Ok(data)
+    }
+
+    /// Makes a name from the zone file absolute, by appending the origin if it's relative.
+    fn absolute_name(&self, token: &Token) -> AResult<String> {
+        let name = &token.text;
+        if token.quoted {
+            anyhow::bail!("Name \"{name}\" can't be quoted");
+        }
+        if name == "@" {
+            return Ok(self.origin.clone());
+        }
+        // Check the trailing dot isn't an escaped dot in the last label.
+        let trailing_backslashes = name
+            .trim_end_matches('.')
+            .chars()
+            .rev()
+            .take_while(|&ch| ch == '\\')
+            .count();
+        if name.ends_with('.') && trailing_backslashes % 2 == 0 {
+            normalize_name(name)
+        } else if self.origin == "." {
+            normalize_name(&format!("{name}."))
+        } else {
+            normalize_name(&format!("{name}.{}", self.origin))
+        }
+    }
+}
+
+/// Validates an absolute name, and escapes it the same way names parsed from messages are,
+/// so that names from zone files can be compared to them.
+fn normalize_name(name: &str) -> AResult<String> {
+    let labels = name_to_labels(name)?;
+    if labels.is_empty() {
+        return Ok(".".to_owned());
+    }
+    let mut out = String::with_capacity(name.len());
+    for label in labels {
+        out.push_str(&escape_label(&label));
+        out.push('.');
+    }
+    Ok(out)
+}
+
+/// Parses RDATA written in the generic format from
+/// <https://datatracker.ietf.org/doc/html/rfc3597#section-5>, e.g. `\# 4 0a000001`.
+/// Any type can be written this way, not just unknown types.
+fn parse_generic_rdata(record_type: RecordType, tokens: &[Token]) -> AResult<RecordData> {
+    let (len, hex) = tokens
+        .split_first()
+        .ok_or_else(|| anyhow!("\\# must be followed by the RDATA length"))?;
+    let len: usize = len
+        .text
+        .parse()
+        .with_context(|| format!("{} is not a valid RDATA length", len.text))?;
+    let bytes = decode(&HEXLOWER_PERMISSIVE, &joined(hex))?;
+    if bytes.len() != len {
+        anyhow::bail!(
+            "RDATA is {} bytes, but its length was given as {len}",
+            bytes.len()
+        );
+    }
+    match record_type {
+        RecordType::Opt => anyhow::bail!("OPT pseudo-records can't be in zone files"),
+        _ => Ok(deserialize_rdata(record_type, bytes)?),
+    }
+}
+
+/// Unescapes a <character-string>, e.g. from a TXT record.
+/// The opposite of [`crate::message::record::escape_character_string`].
+fn unescape_character_string(s: &str) -> AResult<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some(digit) if digit.is_ascii_digit() => {
+                let digits: String = std::iter::once(digit)
+                    .chain(chars.by_ref().take(2))
+                    .collect();
+                let byte = digits
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|_| digits.len() == 3)
+                    .ok_or_else(|| anyhow!("Invalid escape \\{digits} in \"{s}\""))?;
+                bytes.push(byte);
+            }
+            Some(escaped) => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+            }
+            None => anyhow::bail!("Invalid escape at the end of \"{s}\""),
+        }
+    }
+    if bytes.len() > 255 {
+        anyhow::bail!(
+            "\"{s}\" is {} bytes, which is over the max of 255",
+            bytes.len()
+        );
+    }
+    Ok(bytes)
+}
+
+fn number<N: FromStr>(token: &Token) -> AResult<N> {
+    token
+        .text
+        .parse()
+        .map_err(|_| anyhow!("{} is not a valid number", token.text))
+}
+
+/// Joins tokens back together, for base64 and hex values that are split up with spaces.
+fn joined(tokens: &[Token]) -> String {
+    tokens.iter().map(|t| t.text.as_str()).collect()
+}
+
+fn decode(encoding: &Encoding, text: &str) -> AResult<Vec<u8>> {
+    encoding
+        .decode(text.as_bytes())
+        .map_err(|e| anyhow!("Could not decode {text}: {e}"))
+}
+
+/// NSEC3 salts are hex, or `-` for no salt.
+fn parse_salt(token: &Token) -> AResult<Vec<u8>> {
+    match token.text.as_str() {
+        "-" => Ok(Vec::new()),
+        hex => decode(&HEXLOWER_PERMISSIVE, hex),
+    }
+}
+
+/// Parses the list of types in an NSEC or NSEC3 record.
+fn parse_types(tokens: &[Token]) -> AResult<Vec<RecordType>> {
+    tokens
+        .iter()
+        .map(|token| RecordType::from_str(&token.text).map_err(|e| anyhow!(e)))
+        .collect()
+}
+
+fn expect_at_least(tokens: &[Token], expected: usize) -> AResult<()> {
+    if tokens.len() < expected {
+        anyhow::bail!(
+            "Expected at least {expected} values, but found {}",
+            tokens.len()
+        );
+    }
+    Ok(())
+}
+
+fn expect_count(tokens: &[Token], expected: usize) -> AResult<()> {
+    if tokens.len() != expected {
+        let found: Vec<_> = tokens.iter().map(|t| t.text.as_str()).collect();
+        anyhow::bail!(
+            "Expected {expected} values, but found {}: {}",
+            tokens.len(),
+            found.join(" ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::record_with_ttl, Message};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_parse_zone() {
+        let zone = r#"
+$TTL 1h
+@   IN  SOA ns1 hostmaster.example.com. (
+        2024010101 ; serial
+        2h         ; refresh
+        30m 2w     ; retry, expire
+        300 )      ; minimum
+    IN  NS  ns1
+ns1 300 IN  A   192.0.2.1
+    IN 60   TXT "hello \"world\"" two\032words
+$ORIGIN sub.example.com.
+www CNAME   @
+mail        MX 10 smtp.example.net.
+a\.b        TYPE65280 \# 3 ab cdef
+x           A  \# 4 0a000001
+"#;
+        let records = parse_zone(zone, "example.com").unwrap();
+        let expected = vec![
+            record_with_ttl(
+                "example.com.",
+                3600,
+                RecordData::Soa(SoaData {
+                    mname: "ns1.example.com.".to_owned(),
+                    rname: "hostmaster.example.com.".to_owned(),
+                    serial: 2024010101,
+                    refresh: 7200,
+                    retry: 1800,
+                    expire: 1209600,
+                    minimum: 300,
+                }),
+            ),
+            record_with_ttl(
+                "example.com.",
+                3600,
+                RecordData::Ns("ns1.example.com.".to_owned()),
+            ),
+            record_with_ttl(
+                "ns1.example.com.",
+                300,
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            ),
+            record_with_ttl(
+                "ns1.example.com.",
+                60,
+                RecordData::Txt(vec![b"hello \"world\"".to_vec(), b"two words".to_vec()]),
+            ),
+            record_with_ttl(
+                "www.sub.example.com.",
+                3600,
+                RecordData::Cname("sub.example.com.".to_owned()),
+            ),
+            record_with_ttl(
+                "mail.sub.example.com.",
+                3600,
+                RecordData::Mx(MxData {
+                    preference: 10,
+                    exchange: "smtp.example.net.".to_owned(),
+                }),
+            ),
+            record_with_ttl(
+                "a\\.b.sub.example.com.",
+                3600,
+                RecordData::Unknown {
+                    rtype: 65280,
+                    bytes: vec![0xab, 0xcd, 0xef],
+                },
+            ),
+            record_with_ttl(
+                "x.sub.example.com.",
+                3600,
+                RecordData::A(Ipv4Addr::new(10, 0, 0, 1)),
+            ),
+        ];
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let zone = r#"
+$ORIGIN example.com.
+$TTL 300
+@       SOA ns1 h\.master 1 2 3 4 5
+_sip._tcp SRV 10 60 5060 sip
+odd     TXT "semi;colon (paren)" "\255\000" ""
+\@\$    PTR \(\)
+.       NS  a.root-servers.net.
+"#;
+        let records = parse_zone(zone, ".").unwrap();
+        let written = write_zone(&records);
+        assert_eq!(parse_zone(&written, "unused.").unwrap(), records);
+        assert!(written.starts_with(
+            "example.com.\t300\tIN\tSOA\tns1.example.com. h\\.master.example.com. 1 2 3 4 5\n"
+        ));
+    }
+
+    #[test]
+    fn test_dnssec_round_trip() {
+        let zone = r#"
+$ORIGIN example.com.
+$TTL 3600
+@   DNSKEY 257 3 13 ( mdsswUyr3DPW132mOi8V9xESWE8jTo0d
+                      xCjjnopKl+GqJxpVXckHAeF+KkxLbxIL
+                      fDLUT0rAK9iUzy1L53eKGQ== )
+    NSEC3PARAM 1 0 0 -
+    RRSIG A 13 2 3600 20240131000000 20240101000000 2371 @ aGVsbG8gd29ybGQ=
+sub DS  60485 13 2 d4b7d520e7bb5f0f67674a0cceb1e3e0614b93c4f9e99b8383f6a1e4469da50a
+www NSEC example.com. A AAAA RRSIG NSEC TYPE1234
+0p9mhaveqvm6t7vbl5lop2u3t2rp3tom NSEC3 1 1 12 aabbccdd 2vptu5timamqttgl4luu9kg21e0aor3s A RRSIG
+"#;
+        let records = parse_zone(zone, ".").unwrap();
+        let written = write_zone(&records);
+        assert_eq!(parse_zone(&written, ".").unwrap(), records);
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(
+            lines[2],
+            "example.com.\t3600\tIN\tRRSIG\tA 13 2 3600 20240131000000 20240101000000 2371 example.com. aGVsbG8gd29ybGQ="
+        );
+        assert_eq!(
+            lines[5],
+            "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example.com.\t3600\tIN\tNSEC3\t1 1 12 AABBCCDD 2VPTU5TIMAMQTTGL4LUU9KG21E0AOR3S A RRSIG"
+        );
+
+        // And through the wire format.
+        let mut msg =
+            Message::new_query(1, "example.com.".to_owned(), RecordType::A, None).unwrap();
+        msg.answer = records.clone();
+        let parsed = Message::deserialize(msg.serialize_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.answer, records);
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("dingo-zone-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("main.zone"),
+            "$TTL 60\nwww A 192.0.2.1\n$INCLUDE hosts.zone hosts\nmail A 192.0.2.2\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("hosts.zone"),
+            "a A 192.0.2.3\n$ORIGIN other.\nb A 192.0.2.4\n",
+        )
+        .unwrap();
+        let records = read_zone_file(dir.join("main.zone"), "example.com.").unwrap();
+        let names: Vec<_> = records.iter().map(|r| r.name.as_str()).collect();
+        // The included file's $ORIGIN doesn't leak back into the including file.
+        assert_eq!(
+            names,
+            vec![
+                "www.example.com.",
+                "a.hosts.example.com.",
+                "b.other.",
+                "mail.example.com."
+            ]
+        );
+
+        std::fs::write(dir.join("loop.zone"), "$INCLUDE loop.zone\n").unwrap();
+        let err = read_zone_file(dir.join("loop.zone"), "example.com.").unwrap_err();
+        assert!(err.to_string().contains("max of 8 deep"), "{err}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases = [
+            (
+                "www A 192.0.2.1",
+                "line 1: Record for www.example.com. has no TTL, and there's no $TTL",
+            ),
+            (
+                "$TTL 60\n\nwww A (\n192.0.2.1",
+                "line 3: Parenthesis is never closed",
+            ),
+            (
+                "$TTL 60\n\nwww TXT \"open",
+                "line 3: Quoted string isn't closed",
+            ),
+            (
+                "$TTL 60\n  A 192.0.2.1",
+                "line 2: Record has no name, and there's no previous one to use",
+            ),
+            (
+                "$TTL 60\nwww A 1 2",
+                "line 2: Expected 1 values, but found 2: 1 2",
+            ),
+            (
+                "$TTL 60\nwww TYPE9 1",
+                "line 2: TYPE9 records must use the \\# format from RFC 3597",
+            ),
+            (
+                "$TTL 60\nwww A \\# 3 0a0000",
+                "line 2: record data is too short for its type (in the answer section, at byte 3)",
+            ),
+            (
+                "$GENERATE 1-2 a A 1.2.3.4",
+                "line 1: Unknown directive $GENERATE",
+            ),
+        ];
+        for (zone, expected) in cases {
+            let err = parse_zone(zone, "example.com.").unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+}